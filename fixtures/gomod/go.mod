@@ -0,0 +1,28 @@
+module example.com/app
+
+go 1.21
+
+require (
+	github.com/gorilla/mux v1.6.2
+	example.com/retracted v1.0.0
+	example.com/local v0.1.0
+	example.com/missing v1.0.0
+	github.com/BurntSushi/toml v0.3.1 // indirect
+)
+
+exclude github.com/gorilla/mux v1.8.0
+
+replace example.com/local => ../local
+
+retract [v0.1.0, v0.2.0] // Broken builds
+
+// Published too early,
+// use v0.3.1
+retract v0.3.0
+
+// Leaked credentials
+retract (
+	v0.4.0
+	// Wrong module path
+	v0.5.0
+)
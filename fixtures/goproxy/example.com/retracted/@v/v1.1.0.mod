@@ -0,0 +1,5 @@
+module example.com/retracted
+
+go 1.16
+
+retract v1.0.0 // Published too early
@@ -0,0 +1,272 @@
+//! Audit of uploaded dependency files. Every crates.io package of a
+//! `Cargo.lock` is checked for newer versions and yanked releases in the
+//! index and for advisories in the RustSec database, `go.mod` files are
+//! checked against the module proxy.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use semver::Version;
+use cargoindex::{self, IndexVersion, SparseIndex};
+use config;
+use gomod::{self, GoMod, GoModReport};
+use goproxy::GoRepo;
+use rustsec::{self, AdvisoryDb};
+use error::Error;
+
+const CRATES_IO_SOURCES: [&'static str; 2] =
+    ["registry+https://github.com/rust-lang/crates.io-index", "sparse+https://index.crates.io/"];
+
+#[derive(RustcDecodable)]
+struct Lockfile {
+    package: Option<Vec<LockedPackage>>,
+}
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+impl LockedPackage {
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source.as_ref().map_or(false, |s| CRATES_IO_SOURCES.contains(&s.as_ref()))
+    }
+}
+
+/// Packages of a lockfile, the `[root]` package of old lockfiles isn't included
+pub fn parse_lockfile(data: &str) -> Result<Vec<LockedPackage>, Error> {
+    let lockfile: Lockfile = try!(config::decode_toml(data));
+    Ok(lockfile.package.unwrap_or(Vec::new()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    Outdated(String),
+    Yanked,
+    Vulnerable {
+        id: String,
+        title: String,
+        url: Option<String>,
+    },
+    /// The crate is missing from the index or the lookup failed
+    Unchecked(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub name: String,
+    pub version: String,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditReport {
+    pub packages: usize,
+    /// Packages from git, path or other registries
+    pub skipped: usize,
+    /// Packages with findings only
+    pub entries: Vec<AuditEntry>,
+    pub advisories_checked: bool,
+}
+
+impl AuditReport {
+    pub fn count<F: Fn(&Finding) -> bool>(&self, f: F) -> usize {
+        self.entries.iter().filter(|e| e.findings.iter().any(|finding| f(finding))).count()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} packages: {} outdated, {} yanked, {} vulnerable",
+                                  self.packages,
+                                  self.count(|f| match *f {
+                                      Finding::Outdated(_) => true,
+                                      _ => false,
+                                  }),
+                                  self.count(|f| *f == Finding::Yanked),
+                                  self.count(|f| match *f {
+                                      Finding::Vulnerable { .. } => true,
+                                      _ => false,
+                                  }));
+
+        if self.skipped > 0 {
+            summary.push_str(&format!(", {} not from crates.io", self.skipped));
+        }
+        if !self.advisories_checked {
+            summary.push_str(", advisories not checked");
+        }
+
+        summary
+    }
+
+    /// Plain text version of the report for downloading
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", self.summary());
+
+        for entry in &self.entries {
+            text.push_str(&format!("\n{} {}\n", entry.name, entry.version));
+
+            for finding in &entry.findings {
+                let line = match *finding {
+                    Finding::Outdated(ref latest) => format!("  outdated, latest is {}", latest),
+                    Finding::Yanked => "  yanked".to_owned(),
+                    Finding::Vulnerable { ref id, ref title, ref url } => {
+                        format!("  {}: {} {}",
+                                id,
+                                title,
+                                url.as_ref().map_or("", |u| u.as_ref()))
+                    }
+                    Finding::Unchecked(ref reason) => format!("  not checked: {}", reason),
+                };
+                text.push_str(line.trim_right());
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+pub struct Auditor {
+    index: SparseIndex,
+    advisories: Option<Arc<AdvisoryDb>>,
+    go_proxy: GoRepo,
+}
+
+impl Auditor {
+    pub fn new(index: SparseIndex,
+               advisories: Option<Arc<AdvisoryDb>>,
+               go_proxy: GoRepo)
+               -> Auditor {
+        Auditor {
+            index: index,
+            advisories: advisories,
+            go_proxy: go_proxy,
+        }
+    }
+
+    pub fn audit_go_mod(&self, go_mod: &GoMod) -> GoModReport {
+        gomod::check(&self.go_proxy, go_mod)
+    }
+
+    fn check_index(versions: &[IndexVersion], locked: &Version, findings: &mut Vec<Finding>) {
+        if versions.iter().any(|v| v.semver().as_ref() == Some(locked) && v.yanked) {
+            findings.push(Finding::Yanked);
+        }
+
+        if let Some(latest) = cargoindex::latest_version(versions) {
+            if latest.semver().map_or(false, |v| v > *locked) {
+                findings.push(Finding::Outdated(latest.vers.clone()));
+            }
+        }
+    }
+
+    pub fn audit(&self, packages: &[LockedPackage]) -> AuditReport {
+        let mut index_cache: HashMap<&str, Result<Option<Vec<IndexVersion>>, String>> =
+            HashMap::new();
+        let mut entries = Vec::new();
+        let mut skipped = 0;
+
+        for pkg in packages {
+            if !pkg.is_from_crates_io() {
+                skipped += 1;
+                continue;
+            }
+
+            let locked = match Version::parse(&pkg.version) {
+                Ok(locked) => locked,
+                Err(_) => {
+                    entries.push(AuditEntry {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        findings: vec![Finding::Unchecked("invalid version".to_owned())],
+                    });
+                    continue;
+                }
+            };
+
+            let index = &self.index;
+            let versions = index_cache.entry(pkg.name.as_str()).or_insert_with(|| {
+                index.versions(&pkg.name).map_err(|err| {
+                    warn!("index lookup for {} failed: {:?}", pkg.name, err);
+                    format!("{}", err)
+                })
+            });
+
+            let mut findings = Vec::new();
+            match *versions {
+                Ok(Some(ref versions)) => Auditor::check_index(versions, &locked, &mut findings),
+                Ok(None) => findings.push(Finding::Unchecked("not in the index".to_owned())),
+                Err(ref err) => findings.push(Finding::Unchecked(err.clone())),
+            }
+
+            if let Some(ref db) = self.advisories {
+                for advisory in db.affecting(&pkg.name, &locked) {
+                    findings.push(Finding::Vulnerable {
+                        id: advisory.id.clone(),
+                        title: advisory.title.clone(),
+                        url: advisory.url.clone(),
+                    });
+                }
+            }
+
+            if !findings.is_empty() {
+                entries.push(AuditEntry {
+                    name: pkg.name.clone(),
+                    version: pkg.version.clone(),
+                    findings: findings,
+                });
+            }
+        }
+
+        AuditReport {
+            packages: packages.len(),
+            skipped: skipped,
+            entries: entries,
+            advisories_checked: self.advisories.is_some(),
+        }
+    }
+}
+
+#[test]
+fn test_parse_lockfile() {
+    let packages = parse_lockfile(include_str!("../fixtures/audit/Cargo.lock")).unwrap();
+    assert_eq!(packages.len(), 7);
+    assert!(packages[0].is_from_crates_io());
+    assert!(!packages[5].is_from_crates_io());
+    assert!(packages[6].source.is_none());
+
+    assert!(parse_lockfile("[[package]]\nname = ").is_err());
+}
+
+#[test]
+fn test_audit_lockfile() {
+    use cargoindex::LocalIndex;
+
+    let index = SparseIndex::new(Arc::new(LocalIndex::new("fixtures/index")));
+    let db = AdvisoryDb::load("fixtures/advisory-db").unwrap();
+    let auditor = Auditor::new(index, Some(Arc::new(db)), ::goproxy::fixture_repo());
+
+    let packages = parse_lockfile(include_str!("../fixtures/audit/Cargo.lock")).unwrap();
+    let report = auditor.audit(&packages);
+
+    let findings = report.entries
+        .iter()
+        .map(|e| (format!("{} {}", e.name, e.version), e.findings.len()))
+        .collect::<Vec<(String, usize)>>();
+    assert_eq!(findings,
+               vec![("hyper 0.9.1".to_owned(), 2),
+                    ("log 0.3.6".to_owned(), 1),
+                    ("serde 0.8.11".to_owned(), 1),
+                    ("serde 0.8.13".to_owned(), 1),
+                    ("smallvec 0.6.9".to_owned(), 2)]);
+
+    assert_eq!(report.entries[1].findings,
+               vec![Finding::Unchecked("not in the index".to_owned())]);
+    assert_eq!(report.entries[2].findings, vec![Finding::Outdated("0.8.12".to_owned())]);
+    assert_eq!(report.entries[3].findings, vec![Finding::Yanked]);
+    assert_eq!(report.summary(),
+               "7 packages: 2 outdated, 1 yanked, 2 vulnerable, 2 not from crates.io");
+    assert!(report.to_text().contains("  RUSTSEC-2019-0009: Double-free and use-after-free in \
+                                       SmallVec::grow() \
+                                       https://github.com/servo/rust-smallvec/issues/148\n"));
+}
@@ -6,32 +6,38 @@ extern crate slog_term;
 extern crate packagesbot;
 
 use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
 use slog::*;
+use packagesbot::Config;
 
-struct EnvConfig {
-    telegram_token: String,
-    botanio_token: String,
-}
-
-impl EnvConfig {
-    fn load() -> EnvConfig {
-        let tg_token = env::var("TELEGRAM_TOKEN").unwrap();
-        let bt_token = env::var("BOTANIO_TOKEN").unwrap();
-
-        EnvConfig {
-            telegram_token: tg_token,
-            botanio_token: bt_token,
-        }
-    }
+// The configuration file is the first argument or PACKAGESBOT_CONFIG,
+// without either the defaults and environment variables are used
+fn config_path() -> Option<PathBuf> {
+    env::args_os().nth(1).or(env::var_os("PACKAGESBOT_CONFIG")).map(PathBuf::from)
 }
 
 fn main() {
+    // The logger drain runs on its own thread, signals have to be blocked first
+    let signals = packagesbot::watch_signals();
+
     let logger = Logger::new_root(o!());
     logger.set_drain(slog_term::async_stderr());
 
     slog_stdlog::set_logger_level(logger, log::LogLevelFilter::Debug).unwrap();
 
-    let conf = EnvConfig::load();
+    let path = config_path();
+    let config = match Config::load(path.as_ref().map(|p| p.as_path())) {
+        Ok(config) => config,
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "{}", err);
+            process::exit(2);
+        }
+    };
 
-    packagesbot::main(&conf.telegram_token, &conf.botanio_token);
+    if let Err(err) = packagesbot::main(config, signals) {
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
 }
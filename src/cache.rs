@@ -0,0 +1,357 @@
+//! In-memory response cache for search repositories.
+//!
+//! Entries are fresh for `ttl`, after that and up to `ttl + stale_ttl` the
+//! stale value is served while a shared pool of threads fetches a new one.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+use {SearchRepo, Package, Release};
+use error::Error;
+
+/// Threads refreshing stale entries, shared by every cached repository
+const REFRESH_THREADS: usize = 4;
+
+lazy_static!(
+    static ref REFRESH_POOL: Mutex<ThreadPool> = Mutex::new(ThreadPool::new(REFRESH_THREADS));
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    pub capacity: usize,
+    pub ttl: Duration,
+    pub stale_ttl: Duration,
+}
+
+/// Counters of cache lookups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub stale_hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicUsize,
+    stale_hits: AtomicUsize,
+    misses: AtomicUsize,
+    evictions: AtomicUsize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Search(String),
+    Lookup(String),
+    Releases(String),
+}
+
+#[derive(Clone)]
+enum Value {
+    Packages(Vec<Package>),
+    Package(Option<Package>),
+    Releases(Option<Vec<Release>>),
+}
+
+struct Entry {
+    value: Value,
+    fetched_at: Instant,
+    last_used: u64,
+}
+
+enum Cached {
+    Fresh(Value),
+    Stale(Value, bool),
+    Miss,
+}
+
+struct Entries {
+    map: HashMap<Key, Entry>,
+    tick: u64,
+    /// Keys with a refresh queued or running, kept apart from the entries so
+    /// that an eviction doesn't start a second one
+    refreshing: HashSet<Key>,
+}
+
+#[derive(Clone)]
+pub struct CachedRepo {
+    inner: Box<SearchRepo>,
+    config: CacheConfig,
+    entries: Arc<Mutex<Entries>>,
+    counters: Arc<Counters>,
+}
+
+impl CachedRepo {
+    pub fn new(inner: Box<SearchRepo>, config: CacheConfig) -> CachedRepo {
+        CachedRepo {
+            inner: inner,
+            config: config,
+            entries: Arc::new(Mutex::new(Entries {
+                map: HashMap::new(),
+                tick: 0,
+                refreshing: HashSet::new(),
+            })),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            stale_hits: self.counters.stale_hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    // Returns whether the caller has to refresh a stale entry, only the
+    // first caller gets `true` until the refresh is stored.
+    fn get(&self, key: &Key) -> Cached {
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        let expired = match entries.map.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = tick;
+                let age = entry.fetched_at.elapsed();
+
+                if age < self.config.ttl {
+                    return Cached::Fresh(entry.value.clone());
+                } else if age < self.config.ttl + self.config.stale_ttl {
+                    let refresh = entries.refreshing.insert(key.clone());
+                    return Cached::Stale(entry.value.clone(), refresh);
+                }
+                true
+            }
+            None => false,
+        };
+
+        if expired {
+            entries.map.remove(key);
+        }
+        Cached::Miss
+    }
+
+    fn put(&self, key: Key, value: Value) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        entries.map.insert(key,
+                           Entry {
+                               value: value,
+                               fetched_at: Instant::now(),
+                               last_used: tick,
+                           });
+
+        while entries.map.len() > self.config.capacity {
+            let lru = entries.map
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+
+            match lru {
+                Some(key) => {
+                    entries.map.remove(&key);
+                    self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn unmark_refreshing(&self, key: &Key) {
+        self.entries.lock().unwrap().refreshing.remove(key);
+    }
+
+    // `query` is what the caller asked for, the key may be normalized and
+    // some repositories are case sensitive
+    fn fetch(inner: &SearchRepo, key: &Key, query: &str) -> Result<Value, Error> {
+        match *key {
+            Key::Search(_) => inner.search(query).map(Value::Packages),
+            Key::Lookup(_) => inner.lookup(query).map(Value::Package),
+            Key::Releases(_) => inner.releases(query).map(Value::Releases),
+        }
+    }
+
+    fn cached(&self, key: Key, query: &str) -> Result<Value, Error> {
+        match self.get(&key) {
+            Cached::Fresh(value) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(value)
+            }
+            Cached::Stale(value, refresh) => {
+                self.counters.stale_hits.fetch_add(1, Ordering::Relaxed);
+
+                if refresh {
+                    let repo = self.clone();
+                    let query = query.to_owned();
+                    REFRESH_POOL.lock().unwrap().execute(move || {
+                        match CachedRepo::fetch(&*repo.inner, &key, &query) {
+                            Ok(value) => repo.put(key.clone(), value),
+                            Err(err) => warn!("cache refresh of {:?} failed: {:?}", key, err),
+                        }
+                        repo.unmark_refreshing(&key);
+                    });
+                }
+
+                Ok(value)
+            }
+            Cached::Miss => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+
+                let value = try!(CachedRepo::fetch(&*self.inner, &key, query));
+                self.put(key, value.clone());
+                Ok(value)
+            }
+        }
+    }
+}
+
+fn cache_key(query: &str) -> String {
+    query.trim().to_lowercase()
+}
+
+impl SearchRepo for CachedRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        match try!(self.cached(Key::Search(cache_key(query)), query)) {
+            Value::Packages(packages) => Ok(packages),
+            _ => unreachable!(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Option<Package>, Error> {
+        match try!(self.cached(Key::Lookup(name.to_owned()), name)) {
+            Value::Package(package) => Ok(package),
+            _ => unreachable!(),
+        }
+    }
+
+    fn releases(&self, name: &str) -> Result<Option<Vec<Release>>, Error> {
+        match try!(self.cached(Key::Releases(name.to_owned()), name)) {
+            Value::Releases(releases) => Ok(releases),
+            _ => unreachable!(),
+        }
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats())
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+struct CountingRepo {
+    calls: Arc<AtomicUsize>,
+}
+
+#[cfg(test)]
+impl SearchRepo for CountingRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut pkg = Package::empty();
+        pkg.name = format!("{}-{}", query, call);
+        Ok(vec![pkg])
+    }
+}
+
+#[cfg(test)]
+fn counting_cache(config: CacheConfig) -> (CachedRepo, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let repo = CountingRepo { calls: calls.clone() };
+    (CachedRepo::new(Box::new(repo), config), calls)
+}
+
+#[test]
+fn test_cache_hits_and_misses() {
+    let (cache, calls) = counting_cache(CacheConfig {
+        capacity: 10,
+        ttl: Duration::from_secs(60),
+        stale_ttl: Duration::from_secs(60),
+    });
+
+    assert_eq!(cache.search("serde").unwrap()[0].name, "serde-1");
+    assert_eq!(cache.search(" Serde ").unwrap()[0].name, "serde-1");
+    // The repository gets the query as typed, only the key is normalized
+    assert_eq!(cache.search("Tokio").unwrap()[0].name, "Tokio-2");
+    assert_eq!(cache.search("tokio").unwrap()[0].name, "Tokio-2");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    assert_eq!(cache.stats(),
+               CacheStats {
+                   hits: 2,
+                   stale_hits: 0,
+                   misses: 2,
+                   evictions: 0,
+               });
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used() {
+    let (cache, calls) = counting_cache(CacheConfig {
+        capacity: 2,
+        ttl: Duration::from_secs(60),
+        stale_ttl: Duration::from_secs(60),
+    });
+
+    cache.search("a").unwrap();
+    cache.search("b").unwrap();
+    cache.search("a").unwrap();
+    cache.search("c").unwrap();
+
+    // `b` was used least recently
+    cache.search("a").unwrap();
+    cache.search("b").unwrap();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+    assert_eq!(cache.stats().evictions, 2);
+}
+
+#[test]
+fn test_cache_serves_stale_while_revalidating() {
+    use std::thread;
+
+    let (cache, calls) = counting_cache(CacheConfig {
+        capacity: 10,
+        ttl: Duration::from_millis(0),
+        stale_ttl: Duration::from_secs(60),
+    });
+
+    assert_eq!(cache.search("serde").unwrap()[0].name, "serde-1");
+    assert_eq!(cache.search("serde").unwrap()[0].name, "serde-1");
+
+    for _ in 0..100 {
+        if calls.load(Ordering::SeqCst) == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(cache.stats().stale_hits, 1);
+}
+
+#[test]
+fn test_cache_refreshes_a_key_once() {
+    use std::thread;
+
+    let (cache, calls) = counting_cache(CacheConfig {
+        capacity: 10,
+        ttl: Duration::from_millis(0),
+        stale_ttl: Duration::from_secs(60),
+    });
+
+    cache.search("serde").unwrap();
+    // A refresh of the key is still in flight
+    cache.entries.lock().unwrap().refreshing.insert(Key::Search("serde".to_owned()));
+
+    assert_eq!(cache.search("serde").unwrap()[0].name, "serde-1");
+    assert_eq!(cache.search("serde").unwrap()[0].name, "serde-1");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(cache.stats().stale_hits, 2);
+}
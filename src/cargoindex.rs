@@ -0,0 +1,303 @@
+//! Client for the Cargo sparse registry protocol.
+//!
+//! Every crate has a file in the index with one JSON entry per published
+//! version, see https://doc.rust-lang.org/cargo/reference/registry-index.html
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use hyper::header::Authorization;
+use hyper::status::StatusCode;
+use hyper::Url;
+use rustc_serialize::json;
+use semver::Version;
+use Release;
+use error::{self, Error};
+use http::{HttpClient, WithHttp};
+
+pub const CRATES_INDEX_URL: &'static str = "https://index.crates.io/";
+/// Longest crate name crates.io accepts
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct IndexDependency {
+    pub name: String,
+    pub req: String,
+    pub features: Vec<String>,
+    pub optional: bool,
+    pub default_features: bool,
+    pub target: Option<String>,
+    pub kind: Option<String>,
+    pub registry: Option<String>,
+    pub package: Option<String>,
+}
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct IndexVersion {
+    pub name: String,
+    pub vers: String,
+    pub deps: Vec<IndexDependency>,
+    pub cksum: String,
+    pub features: BTreeMap<String, Vec<String>>,
+    pub features2: Option<BTreeMap<String, Vec<String>>>,
+    pub yanked: bool,
+    pub links: Option<String>,
+    pub rust_version: Option<String>,
+}
+
+impl IndexVersion {
+    /// Names of all features, including the ones stored in `features2`
+    pub fn feature_names(&self) -> Vec<&str> {
+        let mut names = self.features.keys().map(|k| k.as_ref()).collect::<Vec<&str>>();
+        if let Some(ref features2) = self.features2 {
+            names.extend(features2.keys().map(|k| k.as_ref() as &str));
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn semver(&self) -> Option<Version> {
+        Version::parse(&self.vers).ok()
+    }
+
+    pub fn release(&self) -> Release {
+        let mut release = Release::new(&self.vers);
+        release.yanked = self.yanked;
+
+        if let Some(ref rust_version) = self.rust_version {
+            release.attributes.insert("rust version".to_owned(), rust_version.clone());
+        }
+
+        let features = self.feature_names();
+        if !features.is_empty() {
+            release.attributes.insert("features".to_owned(), features.join(", "));
+        }
+
+        let deps = self.deps.iter().filter(|d| d.kind.as_ref().map_or(true, |k| k == "normal"));
+        release.attributes.insert("dependencies".to_owned(), deps.count().to_string());
+
+        release
+    }
+}
+
+/// Whether the name follows the crates.io rules, which also keeps user
+/// input from escaping the index with `/` or `..`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LENGTH &&
+    name.bytes().all(|b| match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' | b'-' => true,
+        _ => false,
+    })
+}
+
+/// Location of the index file for a crate name, `None` for invalid names
+pub fn index_path(name: &str) -> Option<String> {
+    if !is_valid_name(name) {
+        return None;
+    }
+    let name = name.to_lowercase();
+
+    Some(match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    })
+}
+
+/// Parses a newline delimited index file
+pub fn parse_index_file(data: &str) -> Result<Vec<IndexVersion>, Error> {
+    let mut versions = Vec::new();
+
+    for line in data.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let version: IndexVersion = try!(json::decode(line));
+        versions.push(version);
+    }
+
+    Ok(versions)
+}
+
+/// The most recent version that is neither yanked nor a pre-release,
+/// falling back to pre-releases for crates without a stable version
+pub fn latest_version(versions: &[IndexVersion]) -> Option<&IndexVersion> {
+    let mut candidates = versions.iter()
+        .filter(|v| !v.yanked)
+        .filter_map(|v| v.semver().map(|s| (s, v)))
+        .collect::<Vec<(Version, &IndexVersion)>>();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let stable = candidates.iter().rev().find(|&&(ref s, _)| !s.is_prerelease());
+    stable.or(candidates.last()).map(|&(_, v)| v)
+}
+
+pub trait IndexSource: Send + Sync {
+    /// Returns the contents of the index file or `None` if there is no such crate
+    fn fetch(&self, path: &str) -> Result<Option<String>, Error>;
+}
+
+/// Index served over HTTP with the sparse protocol
+pub struct HttpIndex {
+    base: Url,
+    token: Option<String>,
+    http: HttpClient,
+}
+
+impl HttpIndex {
+    pub fn new(base: &str) -> Result<HttpIndex, Error> {
+        match Url::parse(base) {
+            Ok(base) => {
+                Ok(HttpIndex {
+                    base: base,
+                    token: None,
+                    http: HttpClient::shared(),
+                })
+            }
+            Err(e) => Err(Error::Config(format!("invalid index url {}: {}", base, e))),
+        }
+    }
+
+    /// Private registries require the token for index requests as well
+    pub fn with_token(mut self, token: &str) -> HttpIndex {
+        self.token = Some(token.to_owned());
+        self
+    }
+}
+
+impl WithHttp for HttpIndex {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+impl IndexSource for HttpIndex {
+    fn fetch(&self, path: &str) -> Result<Option<String>, Error> {
+        let url = match self.base.join(path) {
+            Ok(url) => url,
+            Err(e) => {
+                return Err(Error::InvalidInput(format!("invalid index path {}: {}", path, e)))
+            }
+        };
+
+        let mut request = self.http.get(url);
+        if let Some(ref token) = self.token {
+            request = request.header(Authorization(token.clone()));
+        }
+
+        let mut resp = try!(request.send());
+        if resp.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        try!(error::check_status(resp.status, self.base.as_str()));
+
+        let mut data = String::new();
+        try!(resp.read_to_string(&mut data));
+
+        Ok(Some(data))
+    }
+}
+
+/// Index files laid out in a local directory, e.g. a checkout of an index repository
+pub struct LocalIndex {
+    root: PathBuf,
+}
+
+impl LocalIndex {
+    pub fn new<P: Into<PathBuf>>(root: P) -> LocalIndex {
+        LocalIndex { root: root.into() }
+    }
+}
+
+impl IndexSource for LocalIndex {
+    fn fetch(&self, path: &str) -> Result<Option<String>, Error> {
+        let mut file = match File::open(self.root.join(path)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::from(e)),
+        };
+
+        let mut data = String::new();
+        try!(file.read_to_string(&mut data));
+
+        Ok(Some(data))
+    }
+}
+
+#[derive(Clone)]
+pub struct SparseIndex {
+    source: Arc<IndexSource>,
+}
+
+impl SparseIndex {
+    pub fn new(source: Arc<IndexSource>) -> SparseIndex {
+        SparseIndex { source: source }
+    }
+
+    pub fn crates_io() -> SparseIndex {
+        SparseIndex::new(Arc::new(HttpIndex::new(CRATES_INDEX_URL).unwrap()))
+    }
+
+    /// All published versions of the crate in publication order, invalid
+    /// names are reported as missing crates
+    pub fn versions(&self, name: &str) -> Result<Option<Vec<IndexVersion>>, Error> {
+        let path = match index_path(name) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        match try!(self.source.fetch(&path)) {
+            Some(data) => parse_index_file(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[test]
+fn test_index_path() {
+    assert_eq!(index_path("a"), Some("1/a".to_owned()));
+    assert_eq!(index_path("cc"), Some("2/cc".to_owned()));
+    assert_eq!(index_path("log"), Some("3/l/log".to_owned()));
+    assert_eq!(index_path("Serde"), Some("se/rd/serde".to_owned()));
+    assert_eq!(index_path("serde_json-2"), Some("se/rd/serde_json-2".to_owned()));
+
+    for name in &["", "éa", "日本x", "../../etc/passwd", "se/rde", "..", "a b"] {
+        assert_eq!(index_path(name), None);
+    }
+    assert_eq!(index_path(&"a".repeat(MAX_NAME_LENGTH)).map(|p| p.len()),
+               Some(MAX_NAME_LENGTH + 6));
+    assert_eq!(index_path(&"a".repeat(MAX_NAME_LENGTH + 1)), None);
+}
+
+#[test]
+fn test_local_index_versions() {
+    let index = SparseIndex::new(Arc::new(LocalIndex::new("fixtures/index")));
+
+    let versions = index.versions("serde").unwrap().unwrap();
+    assert_eq!(versions.len(), 4);
+    assert!(versions[2].yanked);
+    assert_eq!(versions[1].rust_version, Some("1.13".to_owned()));
+    assert_eq!(versions[1].feature_names(), vec!["default", "derive", "std"]);
+    assert_eq!(versions[1].deps[1].kind, Some("dev".to_owned()));
+
+    let latest = latest_version(&versions).unwrap();
+    assert_eq!(latest.vers, "0.8.12");
+
+    let release = latest.release();
+    assert_eq!(release.attributes.get("dependencies"), Some(&"1".to_owned()));
+
+    let log = index.versions("log").unwrap().unwrap();
+    assert_eq!(log[0].deps[0].target, Some("cfg(unix)".to_owned()));
+
+    assert!(index.versions("no-such-crate").unwrap().is_none());
+    assert!(index.versions("../index/se/rd/serde").unwrap().is_none());
+    assert!(index.versions("日本x").unwrap().is_none());
+}
+
+#[test]
+fn test_parse_invalid_index_file() {
+    assert!(parse_index_file("{\"name\": \"serde\"}").is_err());
+    assert!(parse_index_file("").unwrap().is_empty());
+}
@@ -0,0 +1,957 @@
+//! Configuration file of the bot.
+//!
+//! Every setting except the Telegram token has a default, an empty file
+//! serves the public ecosystems. Secrets and deployment specific paths can
+//! be overridden with environment variables, see `apply_env` and
+//! `packagesbot.example.toml`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use rustc_serialize::Decodable;
+use toml;
+use url::Url;
+use state::StateConfig;
+use telegram::analytics::AnalyticsConfig;
+use telegram::bot::{self, BotConfig, ListenMode};
+use telegram::webhook::WebhookConfig;
+use cratesdump;
+use http::HttpConfig;
+use htmlrepo::FieldSelector;
+use scrape::Selector;
+use urltemplate::QUERY_PLACEHOLDER;
+use error::Error;
+
+const DEFAULT_STATE_PATH: &'static str = "packagesbot-state.json";
+
+/// Parses a TOML document, keeping the line and column of syntax errors
+pub fn parse_toml(data: &str) -> Result<toml::Table, Error> {
+    let mut parser = toml::Parser::new(data);
+
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => {
+            let errors = parser.errors
+                .iter()
+                .map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    format!("{} at {}:{}", e.desc, line + 1, col + 1)
+                })
+                .collect::<Vec<String>>();
+            Err(Error::Toml(errors.join(", ")))
+        }
+    }
+}
+
+/// Parses a TOML document into `T`, keys `T` has no field for are ignored
+pub fn decode_toml<T: Decodable>(data: &str) -> Result<T, Error> {
+    let table = try!(parse_toml(data));
+    let mut decoder = toml::Decoder::new(toml::Value::Table(table));
+    T::decode(&mut decoder).map_err(|e| Error::Toml(format!("{}", e)))
+}
+
+// Keys left over after decoding, i.e. the ones no setting exists for
+fn unused_keys(value: &toml::Value, prefix: &str, keys: &mut Vec<String>) {
+    match *value {
+        toml::Value::Table(ref table) => {
+            for (key, value) in table {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                unused_keys(value, &path, keys);
+            }
+        }
+        toml::Value::Array(ref values) => {
+            for value in values {
+                unused_keys(value, prefix, keys);
+            }
+        }
+        _ => keys.push(prefix.to_owned()),
+    }
+}
+
+#[derive(RustcDecodable, Default)]
+struct ConfigFile {
+    telegram: Option<TelegramSection>,
+    webhook: Option<WebhookSection>,
+    bot: Option<BotSection>,
+    cache: Option<CacheSection>,
+    http: Option<HttpSection>,
+    analytics: Option<AnalyticsSection>,
+    state: Option<StateSection>,
+    audit: Option<AuditSection>,
+    crates_dump: Option<CratesDumpSection>,
+    ecosystem: Option<Vec<EcosystemSection>>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct TelegramSection {
+    token: Option<String>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct WebhookSection {
+    url: Option<String>,
+    listen: Option<String>,
+    path: Option<String>,
+    secret_token: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct BotSection {
+    workers: Option<usize>,
+    max_message_length: Option<usize>,
+    inline_deadline_ms: Option<u64>,
+    watch_interval_secs: Option<u64>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct CacheSection {
+    capacity: Option<usize>,
+    stale_secs: Option<u64>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct HttpSection {
+    user_agent: Option<String>,
+    connect_timeout_secs: Option<u64>,
+    read_timeout_secs: Option<u64>,
+    max_idle_connections: Option<usize>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct AnalyticsSection {
+    sink: Option<String>,
+    path: Option<String>,
+    token: Option<String>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct StateSection {
+    store: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct AuditSection {
+    advisory_db: Option<String>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct CratesDumpSection {
+    enabled: Option<bool>,
+    url: Option<String>,
+    path: Option<String>,
+    refresh_secs: Option<u64>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct EcosystemSection {
+    kind: String,
+    command: String,
+    aliases: Option<Vec<String>>,
+    endpoint: Option<String>,
+    search_endpoint: Option<String>,
+    index: Option<String>,
+    token: Option<String>,
+    allowed_chats: Option<Vec<i64>>,
+    cache_ttl_secs: Option<u64>,
+    results: Option<String>,
+    no_results: Option<String>,
+    fields: Option<FieldsSection>,
+}
+
+#[derive(RustcDecodable, Default)]
+struct FieldsSection {
+    name: Option<String>,
+    version: Option<String>,
+    description: Option<String>,
+    repository: Option<String>,
+    documentation: Option<String>,
+    homepage: Option<String>,
+    license: Option<String>,
+    downloads: Option<String>,
+    keywords: Option<String>,
+    updated_at: Option<String>,
+    attributes: Option<BTreeMap<String, String>>,
+}
+
+impl EcosystemSection {
+    fn public(kind: &str,
+              command: &str,
+              aliases: &[&str],
+              cache_ttl_secs: u64)
+              -> EcosystemSection {
+        EcosystemSection {
+            kind: kind.to_owned(),
+            command: command.to_owned(),
+            aliases: Some(aliases.iter().map(|a| (*a).to_owned()).collect()),
+            cache_ttl_secs: Some(cache_ttl_secs),
+            ..EcosystemSection::default()
+        }
+    }
+}
+
+/// Ecosystems served when the file doesn't list any
+fn default_ecosystems() -> Vec<EcosystemSection> {
+    vec![EcosystemSection::public("crates", "rust", &[], 10 * 60),
+         EcosystemSection::public("go", "go", &[], 30 * 60),
+         EcosystemSection::public("npm", "npm", &["js"], 10 * 60),
+         EcosystemSection::public("pypi", "python", &["pypi"], 30 * 60)]
+}
+
+fn section<T: Default>(section: &mut Option<T>) -> &mut T {
+    if section.is_none() {
+        *section = Some(T::default());
+    }
+    section.as_mut().unwrap()
+}
+
+// GOPROXY is a comma separated list that may contain the `direct` and
+// `off` keywords, only the first real proxy is used.
+fn first_proxy(goproxy: &str) -> Option<String> {
+    goproxy.split(|c| c == ',' || c == '|')
+        .map(|p| p.trim())
+        .find(|p| !p.is_empty() && *p != "direct" && *p != "off")
+        .map(|p| p.to_owned())
+}
+
+/// Environment variables take precedence over the file:
+///
+/// * `TELEGRAM_TOKEN`
+/// * `WEBHOOK_URL`, `WEBHOOK_LISTEN`, `WEBHOOK_PATH`, `WEBHOOK_SECRET`,
+///   `WEBHOOK_TLS_CERT` and `WEBHOOK_TLS_KEY`
+/// * `ANALYTICS`, `ANALYTICS_PATH` and `BOTANIO_TOKEN`, the token alone
+///   enables Botan unless a sink is configured
+/// * `STATE_STORE` and `STATE_PATH`
+/// * `RUSTSEC_DB`
+/// * `GOPROXY` replaces the proxy of every go ecosystem
+fn apply_env<F>(file: &mut ConfigFile, var: F)
+    where F: Fn(&str) -> Option<String>
+{
+    if let Some(token) = var("TELEGRAM_TOKEN") {
+        section(&mut file.telegram).token = Some(token);
+    }
+
+    let webhook_vars = [("WEBHOOK_URL", 0), ("WEBHOOK_LISTEN", 1), ("WEBHOOK_PATH", 2),
+                        ("WEBHOOK_SECRET", 3), ("WEBHOOK_TLS_CERT", 4), ("WEBHOOK_TLS_KEY", 5)];
+    for &(name, field) in &webhook_vars {
+        if let Some(value) = var(name) {
+            let webhook = section(&mut file.webhook);
+            let setting = match field {
+                0 => &mut webhook.url,
+                1 => &mut webhook.listen,
+                2 => &mut webhook.path,
+                3 => &mut webhook.secret_token,
+                4 => &mut webhook.tls_cert,
+                _ => &mut webhook.tls_key,
+            };
+            *setting = Some(value);
+        }
+    }
+
+    if let Some(sink) = var("ANALYTICS") {
+        section(&mut file.analytics).sink = Some(sink);
+    }
+    if let Some(path) = var("ANALYTICS_PATH") {
+        section(&mut file.analytics).path = Some(path);
+    }
+    if let Some(token) = var("BOTANIO_TOKEN") {
+        let analytics = section(&mut file.analytics);
+        if analytics.sink.is_none() {
+            analytics.sink = Some("botan".to_owned());
+        }
+        analytics.token = Some(token);
+    }
+
+    if let Some(store) = var("STATE_STORE") {
+        section(&mut file.state).store = Some(store);
+    }
+    if let Some(path) = var("STATE_PATH") {
+        section(&mut file.state).path = Some(path);
+    }
+
+    if let Some(path) = var("RUSTSEC_DB") {
+        section(&mut file.audit).advisory_db = Some(path);
+    }
+
+    if let Some(proxy) = var("GOPROXY").and_then(|v| first_proxy(&v)) {
+        for ecosystem in section(&mut file.ecosystem).iter_mut().filter(|e| e.kind == "go") {
+            ecosystem.endpoint = Some(proxy.clone());
+        }
+    }
+}
+
+/// Where the package fields are found in a search result: paths like
+/// `links.0.href` for json ecosystems, selectors like `a.title@href` for
+/// html ones
+#[derive(Debug, Clone, Default)]
+pub struct FieldMapping {
+    pub name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub documentation: Option<String>,
+    pub homepage: Option<String>,
+    pub license: Option<String>,
+    pub downloads: Option<String>,
+    /// A list or a comma separated string
+    pub keywords: Option<String>,
+    pub updated_at: Option<String>,
+    /// Attribute name to path or selector, shown as extra details
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl FieldMapping {
+    fn specs(&self) -> Vec<&str> {
+        let optional = [&self.version,
+                        &self.description,
+                        &self.repository,
+                        &self.documentation,
+                        &self.homepage,
+                        &self.license,
+                        &self.downloads,
+                        &self.keywords,
+                        &self.updated_at];
+
+        let mut specs = vec![self.name.as_str()];
+        specs.extend(optional.iter().filter_map(|spec| spec.as_ref()).map(|s| s.as_str()));
+        specs.extend(self.attributes.values().map(|s| s.as_str()));
+        specs
+    }
+}
+
+/// Crates.io database dump answering searches while the API is down
+#[derive(Debug, Clone)]
+pub struct CratesDumpConfig {
+    pub url: String,
+    pub path: PathBuf,
+    pub refresh: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheSettings {
+    pub capacity: usize,
+    /// How long expired entries are served while being refreshed
+    pub stale_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EcosystemKind {
+    Crates,
+    Go,
+    Npm,
+    Pypi,
+    /// Any JSON API described by a url template and field mappings
+    Json,
+    /// Any search page described by a url template and selectors
+    Html,
+}
+
+#[derive(Debug, Clone)]
+pub struct EcosystemConfig {
+    pub kind: EcosystemKind,
+    pub command: String,
+    /// Further commands sharing the repository and its cache
+    pub aliases: Vec<String>,
+    /// Registry API, module proxy or search endpoint, the public one by default
+    pub endpoint: Option<String>,
+    /// Search page of go ecosystems
+    pub search_endpoint: Option<String>,
+    /// Sparse index of crates ecosystems
+    pub index: Option<String>,
+    /// Registry token of crates ecosystems
+    pub token: Option<String>,
+    /// Chats that may use the commands, `None` makes them public
+    pub allowed_chats: Option<Vec<i64>>,
+    /// `None` disables caching
+    pub cache_ttl: Option<Duration>,
+    /// Path to the results array of json ecosystems, selector of the result
+    /// rows of html ones
+    pub results: Option<String>,
+    /// Selector of the html element shown when nothing is found
+    pub no_results: Option<String>,
+    /// Package fields of json and html ecosystems
+    pub fields: Option<FieldMapping>,
+}
+
+impl EcosystemConfig {
+    /// The command followed by the aliases
+    pub fn commands(&self) -> Vec<&str> {
+        let mut commands = vec![self.command.as_ref()];
+        commands.extend(self.aliases.iter().map(|a| a.as_ref() as &str));
+        commands
+    }
+}
+
+pub struct Config {
+    pub telegram_token: String,
+    pub listen_mode: ListenMode,
+    pub bot: BotConfig,
+    pub cache: CacheSettings,
+    pub http: HttpConfig,
+    pub analytics: AnalyticsConfig,
+    pub state: StateConfig,
+    /// Checkout of the RustSec advisory database used by lockfile audits
+    pub advisory_db: Option<PathBuf>,
+    /// `None` disables the crates.io fallback
+    pub crates_dump: Option<CratesDumpConfig>,
+    pub ecosystems: Vec<EcosystemConfig>,
+}
+
+fn is_valid_command(command: &str) -> bool {
+    !command.is_empty() && command.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn check_url(errors: &mut Vec<String>, setting: &str, url: &Option<String>) {
+    if let Some(ref url) = *url {
+        let parsed = Url::parse(url.trim_left_matches("sparse+"));
+        if let Err(e) = parsed {
+            errors.push(format!("{} {:?} is not a valid url: {}", setting, url, e));
+        }
+    }
+}
+
+fn positive_secs(errors: &mut Vec<String>,
+                 setting: &str,
+                 value: Option<u64>,
+                 default: u64)
+                 -> Duration {
+    match value {
+        Some(0) => {
+            errors.push(format!("{} must be greater than 0", setting));
+            Duration::from_secs(default)
+        }
+        Some(secs) => Duration::from_secs(secs),
+        None => Duration::from_secs(default),
+    }
+}
+
+impl Config {
+    /// Reads the file at `path`, without a path the defaults and the
+    /// environment make up the configuration
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let mut data = String::new();
+        if let Some(path) = path {
+            let read = File::open(path).and_then(|mut file| file.read_to_string(&mut data));
+            if let Err(err) = read {
+                return Err(Error::Config(format!("can't read {}: {}", path.display(), err)));
+            }
+        }
+
+        Config::parse(&data, |name| ::std::env::var(name).ok()).map_err(|err| {
+            match (err, path) {
+                (Error::Toml(msg), Some(path)) => {
+                    Error::Config(format!("{} is invalid: {}", path.display(), msg))
+                }
+                (err, _) => err,
+            }
+        })
+    }
+
+    /// Parses and validates the configuration, `var` looks up environment
+    /// variables. All problems are reported at once.
+    pub fn parse<F>(data: &str, var: F) -> Result<Config, Error>
+        where F: Fn(&str) -> Option<String>
+    {
+        let mut decoder = toml::Decoder::new(toml::Value::Table(try!(parse_toml(data))));
+        let mut file: ConfigFile = try!(ConfigFile::decode(&mut decoder)
+            .map_err(|e| Error::Toml(format!("{}", e))));
+
+        let mut unknown = Vec::new();
+        if let Some(ref leftover) = decoder.toml {
+            unused_keys(leftover, "", &mut unknown);
+        }
+        if !unknown.is_empty() {
+            return Err(Error::Config(format!("unknown settings: {}", unknown.join(", "))));
+        }
+
+        if file.ecosystem.is_none() {
+            file.ecosystem = Some(default_ecosystems());
+        }
+        apply_env(&mut file, var);
+
+        Config::validate(file)
+    }
+
+    fn validate(file: ConfigFile) -> Result<Config, Error> {
+        let mut errors = Vec::new();
+
+        let telegram_token = match file.telegram.and_then(|t| t.token) {
+            Some(ref token) if !token.is_empty() => token.clone(),
+            _ => {
+                errors.push("telegram.token is missing, set it in the file or TELEGRAM_TOKEN"
+                    .to_owned());
+                String::new()
+            }
+        };
+
+        let listen_mode = match file.webhook {
+            Some(webhook) => {
+                let url = webhook.url.unwrap_or(String::new());
+                let path = match webhook.path {
+                    Some(path) => path,
+                    None => Url::parse(&url).map(|u| u.path().to_owned()).unwrap_or(String::new()),
+                };
+                check_url(&mut errors, "webhook.url", &Some(url.clone()));
+
+                if webhook.secret_token.as_ref().map_or(true, |s| s.is_empty()) {
+                    errors.push("webhook.secret_token is missing".to_owned());
+                }
+
+                let tls = match (webhook.tls_cert, webhook.tls_key) {
+                    (Some(cert), Some(key)) => Some((PathBuf::from(cert), PathBuf::from(key))),
+                    (None, None) => None,
+                    _ => {
+                        errors.push("webhook.tls_cert and webhook.tls_key go together".to_owned());
+                        None
+                    }
+                };
+
+                ListenMode::Webhook(WebhookConfig {
+                    listen: webhook.listen.unwrap_or("0.0.0.0:8080".to_owned()),
+                    url: url,
+                    path: path,
+                    secret_token: webhook.secret_token.unwrap_or(String::new()),
+                    tls: tls,
+                })
+            }
+            None => ListenMode::LongPoll,
+        };
+
+        let bot_section = file.bot.unwrap_or(BotSection::default());
+        let defaults = BotConfig::default();
+        let workers = bot_section.workers.unwrap_or(defaults.workers);
+        if workers == 0 {
+            errors.push("bot.workers must be at least 1".to_owned());
+        }
+        let max_message_length = bot_section.max_message_length
+            .unwrap_or(defaults.max_message_length);
+        if max_message_length == 0 || max_message_length > defaults.max_message_length {
+            errors.push(format!("bot.max_message_length must be between 1 and {}",
+                                defaults.max_message_length));
+        }
+        let bot = BotConfig {
+            workers: workers,
+            max_message_length: max_message_length,
+            inline_deadline: bot_section.inline_deadline_ms
+                .map_or(defaults.inline_deadline, Duration::from_millis),
+            watch_interval: positive_secs(&mut errors,
+                                          "bot.watch_interval_secs",
+                                          bot_section.watch_interval_secs,
+                                          defaults.watch_interval.as_secs()),
+        };
+
+        let cache_section = file.cache.unwrap_or(CacheSection::default());
+        let cache = CacheSettings {
+            capacity: cache_section.capacity.unwrap_or(1000),
+            stale_ttl: Duration::from_secs(cache_section.stale_secs.unwrap_or(60 * 60)),
+        };
+        if cache.capacity == 0 {
+            errors.push("cache.capacity must be at least 1".to_owned());
+        }
+
+        let http_section = file.http.unwrap_or(HttpSection::default());
+        let defaults = HttpConfig::default();
+        let http = HttpConfig {
+            user_agent: http_section.user_agent.unwrap_or(defaults.user_agent),
+            connect_timeout: positive_secs(&mut errors,
+                                           "http.connect_timeout_secs",
+                                           http_section.connect_timeout_secs,
+                                           defaults.connect_timeout.as_secs()),
+            read_timeout: positive_secs(&mut errors,
+                                        "http.read_timeout_secs",
+                                        http_section.read_timeout_secs,
+                                        defaults.read_timeout.as_secs()),
+            max_idle_connections: http_section.max_idle_connections
+                .unwrap_or(defaults.max_idle_connections),
+        };
+        // crates.io refuses requests without one
+        if http.user_agent.trim().is_empty() || http.user_agent.chars().any(|c| c.is_control()) {
+            errors.push("http.user_agent must be a non-empty single line".to_owned());
+        }
+
+        let analytics = match file.analytics {
+            Some(analytics) => {
+                let path = analytics.path.map(PathBuf::from);
+                match (analytics.sink.as_ref().map(|s| s.as_str()), path, analytics.token) {
+                    (None, _, _) |
+                    (Some("none"), _, _) => AnalyticsConfig::Disabled,
+                    (Some("jsonl"), Some(path), _) => AnalyticsConfig::JsonLines(path),
+                    (Some("sqlite"), Some(path), _) => AnalyticsConfig::Sqlite(path),
+                    (Some("botan"), _, Some(token)) => AnalyticsConfig::Botan(token),
+                    (Some("jsonl"), None, _) |
+                    (Some("sqlite"), None, _) => {
+                        errors.push("analytics.path is required by the jsonl and sqlite sinks"
+                            .to_owned());
+                        AnalyticsConfig::Disabled
+                    }
+                    (Some("botan"), _, None) => {
+                        errors.push("analytics.token is required by the botan sink".to_owned());
+                        AnalyticsConfig::Disabled
+                    }
+                    (Some(other), _, _) => {
+                        errors.push(format!("unknown analytics.sink {:?}, expected none, jsonl, \
+                                             sqlite or botan",
+                                            other));
+                        AnalyticsConfig::Disabled
+                    }
+                }
+            }
+            None => AnalyticsConfig::Disabled,
+        };
+
+        // Kept in the working directory by default so that histories and
+        // watchlists survive restarts, the memory store has to be asked for
+        let state = match file.state {
+            Some(state) => {
+                let path = state.path.map(PathBuf::from);
+                match (state.store.as_ref().map(|s| s.as_str()), path) {
+                    (None, path) => {
+                        StateConfig::File(path.unwrap_or(PathBuf::from(DEFAULT_STATE_PATH)))
+                    }
+                    (Some("memory"), _) => StateConfig::Memory,
+                    (Some("file"), Some(path)) => StateConfig::File(path),
+                    (Some("sqlite"), Some(path)) => StateConfig::Sqlite(path),
+                    (Some("file"), None) |
+                    (Some("sqlite"), None) => {
+                        errors.push("state.path is required by the file and sqlite stores"
+                            .to_owned());
+                        StateConfig::Memory
+                    }
+                    (Some(other), _) => {
+                        errors.push(format!("unknown state.store {:?}, expected memory, file or \
+                                             sqlite",
+                                            other));
+                        StateConfig::Memory
+                    }
+                }
+            }
+            None => StateConfig::File(PathBuf::from(DEFAULT_STATE_PATH)),
+        };
+
+        let crates_dump = file.crates_dump.unwrap_or(CratesDumpSection::default());
+        check_url(&mut errors, "crates_dump.url", &crates_dump.url);
+        // The dump is hundreds of MB, it's only downloaded when asked for
+        let crates_dump = if crates_dump.enabled.unwrap_or(false) {
+            Some(CratesDumpConfig {
+                url: crates_dump.url.unwrap_or(cratesdump::CRATES_DUMP_URL.to_owned()),
+                path: PathBuf::from(crates_dump.path
+                    .unwrap_or("crates-db-dump.tar.gz".to_owned())),
+                refresh: positive_secs(&mut errors,
+                                       "crates_dump.refresh_secs",
+                                       crates_dump.refresh_secs,
+                                       24 * 60 * 60),
+            })
+        } else {
+            None
+        };
+
+        let mut ecosystems: Vec<EcosystemConfig> = Vec::new();
+        for (i, e) in file.ecosystem.unwrap_or(Vec::new()).into_iter().enumerate() {
+            let name = if e.command.is_empty() {
+                format!("ecosystem #{}", i + 1)
+            } else {
+                format!("ecosystem {}", e.command)
+            };
+
+            let kind = match e.kind.as_str() {
+                "crates" => EcosystemKind::Crates,
+                "go" => EcosystemKind::Go,
+                "npm" => EcosystemKind::Npm,
+                "pypi" => EcosystemKind::Pypi,
+                "json" => EcosystemKind::Json,
+                "html" => EcosystemKind::Html,
+                other => {
+                    errors.push(format!("{}: unknown kind {:?}, expected crates, go, npm, pypi, \
+                                         json or html",
+                                        name,
+                                        other));
+                    continue;
+                }
+            };
+
+            let fields = e.fields.map(|f| {
+                FieldMapping {
+                    name: f.name.unwrap_or(String::new()),
+                    version: f.version,
+                    description: f.description,
+                    repository: f.repository,
+                    documentation: f.documentation,
+                    homepage: f.homepage,
+                    license: f.license,
+                    downloads: f.downloads,
+                    keywords: f.keywords,
+                    updated_at: f.updated_at,
+                    attributes: f.attributes.unwrap_or(BTreeMap::new()),
+                }
+            });
+
+            let ecosystem = EcosystemConfig {
+                kind: kind,
+                command: e.command,
+                aliases: e.aliases.unwrap_or(Vec::new()),
+                endpoint: e.endpoint,
+                search_endpoint: e.search_endpoint,
+                index: e.index,
+                token: e.token,
+                allowed_chats: e.allowed_chats,
+                cache_ttl: e.cache_ttl_secs.and_then(|secs| if secs > 0 {
+                    Some(Duration::from_secs(secs))
+                } else {
+                    None
+                }),
+                results: e.results,
+                no_results: e.no_results,
+                fields: fields,
+            };
+
+            for command in ecosystem.commands() {
+                let taken = bot::BUILTIN_COMMANDS.contains(&command) ||
+                            ecosystems.iter().any(|other| other.commands().contains(&command));
+                if !is_valid_command(command) {
+                    errors.push(format!("{}: {:?} is not a valid command", name, command));
+                } else if taken {
+                    errors.push(format!("{}: the /{} command is taken", name, command));
+                }
+            }
+
+            check_url(&mut errors, &format!("{} endpoint", name), &ecosystem.endpoint);
+            check_url(&mut errors,
+                      &format!("{} search_endpoint", name),
+                      &ecosystem.search_endpoint);
+            check_url(&mut errors, &format!("{} index", name), &ecosystem.index);
+
+            if ecosystem.search_endpoint.is_some() && kind != EcosystemKind::Go {
+                errors.push(format!("{}: search_endpoint is only used by go ecosystems", name));
+            }
+            if (ecosystem.index.is_some() || ecosystem.token.is_some()) &&
+               kind != EcosystemKind::Crates {
+                errors.push(format!("{}: index and token are only used by crates ecosystems",
+                                    name));
+            }
+            if kind == EcosystemKind::Crates && ecosystem.endpoint.is_some() &&
+               ecosystem.index.is_none() {
+                errors.push(format!("{}: index is required with a custom endpoint", name));
+            }
+            if kind == EcosystemKind::Json || kind == EcosystemKind::Html {
+                let has_placeholder = ecosystem.endpoint
+                    .as_ref()
+                    .map_or(false, |e| e.contains(QUERY_PLACEHOLDER));
+                let has_name = ecosystem.fields.as_ref().map_or(false, |f| !f.name.is_empty());
+                if !has_placeholder {
+                    errors.push(format!("{}: endpoint must be a url containing {}",
+                                        name,
+                                        QUERY_PLACEHOLDER));
+                }
+                if !has_name {
+                    errors.push(format!("{}: fields.name is required", name));
+                }
+
+                if kind == EcosystemKind::Html {
+                    match ecosystem.results {
+                        Some(ref rows) => {
+                            let mut checks = vec![Selector::parse(rows).map(|_| ())];
+                            if let Some(ref no_results) = ecosystem.no_results {
+                                checks.push(Selector::parse(no_results).map(|_| ()));
+                            }
+                            if let Some(ref fields) = ecosystem.fields {
+                                checks.extend(fields.specs()
+                                    .into_iter()
+                                    .map(|spec| FieldSelector::parse(spec).map(|_| ())));
+                            }
+
+                            for check in checks {
+                                if let Err(Error::Config(msg)) = check {
+                                    errors.push(format!("{}: {}", name, msg));
+                                }
+                            }
+                        }
+                        None => {
+                            errors.push(format!("{}: results, the selector of the result rows, \
+                                                 is required",
+                                                name))
+                        }
+                    }
+                } else if ecosystem.no_results.is_some() {
+                    errors.push(format!("{}: no_results is only used by html ecosystems", name));
+                }
+            } else if ecosystem.results.is_some() || ecosystem.fields.is_some() ||
+                      ecosystem.no_results.is_some() {
+                errors.push(format!("{}: results, no_results and fields are only used by json \
+                                     and html ecosystems",
+                                    name));
+            }
+            if ecosystem.allowed_chats.as_ref().map_or(false, |chats| chats.is_empty()) {
+                errors.push(format!("{}: allowed_chats is empty, remove the ecosystem instead",
+                                    name));
+            }
+
+            ecosystems.push(ecosystem);
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::Config(errors.join("\n")));
+        }
+
+        Ok(Config {
+            telegram_token: telegram_token,
+            listen_mode: listen_mode,
+            bot: bot,
+            cache: cache,
+            http: http,
+            analytics: analytics,
+            state: state,
+            advisory_db: file.audit.and_then(|a| a.advisory_db).map(PathBuf::from),
+            crates_dump: crates_dump,
+            ecosystems: ecosystems,
+        })
+    }
+}
+
+#[cfg(test)]
+fn env_var(vars: &[(&str, &str)], name: &str) -> Option<String> {
+    vars.iter().find(|v| v.0 == name).map(|v| v.1.to_owned())
+}
+
+#[test]
+fn test_default_config() {
+    let config = Config::parse("", |name| env_var(&[("TELEGRAM_TOKEN", "123:abc")], name))
+        .unwrap();
+
+    assert_eq!(config.telegram_token, "123:abc");
+    assert_eq!(config.bot.workers, 4);
+    assert_eq!(config.http.user_agent, ::http::DEFAULT_USER_AGENT);
+    assert!(config.crates_dump.is_none());
+    assert!(config.advisory_db.is_none());
+    match config.state {
+        StateConfig::File(ref path) => assert_eq!(*path, PathBuf::from(DEFAULT_STATE_PATH)),
+        ref other => panic!("unexpected state store {:?}", other),
+    }
+
+    let commands = config.ecosystems
+        .iter()
+        .flat_map(|e| e.commands().into_iter().map(|c| c.to_owned()))
+        .collect::<Vec<String>>();
+    assert_eq!(commands, vec!["rust", "go", "npm", "js", "python", "pypi"]);
+
+    match config.listen_mode {
+        ListenMode::LongPoll => {}
+        ListenMode::Webhook(_) => panic!("webhook mode without a webhook section"),
+    }
+}
+
+#[test]
+fn test_example_config() {
+    let vars = [("TELEGRAM_TOKEN", "from-env"),
+                ("STATE_PATH", "/var/lib/packagesbot/state.db"),
+                ("GOPROXY", "https://goproxy.io,direct")];
+    let config = Config::parse(include_str!("../packagesbot.example.toml"),
+                               |name| env_var(&vars, name))
+        .unwrap();
+
+    assert_eq!(config.telegram_token, "from-env");
+    assert_eq!(config.bot.workers, 8);
+    assert_eq!(config.bot.inline_deadline, Duration::from_millis(5000));
+    assert_eq!(config.cache.capacity, 5000);
+    assert_eq!(config.http.user_agent,
+               "packagesbot (+https://bot.example.com; ops@example.com)");
+    assert_eq!(config.http.read_timeout, Duration::from_secs(15));
+    assert_eq!(config.crates_dump.as_ref().map(|d| d.refresh),
+               Some(Duration::from_secs(86400)));
+
+    match config.state {
+        StateConfig::Sqlite(ref path) => {
+            assert_eq!(*path, PathBuf::from("/var/lib/packagesbot/state.db"))
+        }
+        ref other => panic!("unexpected state store {:?}", other),
+    }
+
+    let go = config.ecosystems.iter().find(|e| e.kind == EcosystemKind::Go).unwrap();
+    assert_eq!(go.endpoint, Some("https://goproxy.io".to_owned()));
+
+    let hex = config.ecosystems.iter().find(|e| e.kind == EcosystemKind::Json).unwrap();
+    assert_eq!(hex.command, "hex");
+    assert!(hex.results.is_none());
+    let fields = hex.fields.as_ref().unwrap();
+    assert_eq!(fields.license, Some("meta.licenses.0".to_owned()));
+    assert_eq!(fields.attributes.get("source code"),
+               Some(&"meta.links.GitHub".to_owned()));
+
+    let portal = config.ecosystems.iter().find(|e| e.kind == EcosystemKind::Html).unwrap();
+    assert_eq!(portal.results, Some("table#results tbody tr.result".to_owned()));
+    assert_eq!(portal.no_results, Some("div.empty-state".to_owned()));
+
+    let private = config.ecosystems.iter().find(|e| e.command == "ourcrates").unwrap();
+    assert_eq!(private.allowed_chats, Some(vec![-1001234567890]));
+    assert!(private.cache_ttl.is_none());
+}
+
+#[test]
+fn test_invalid_config() {
+    let data = r#"
+        [bot]
+        workers = 0
+
+        [http]
+        user_agent = ""
+
+        [analytics]
+        sink = "jsonl"
+
+        [[ecosystem]]
+        kind = "maven"
+        command = "java"
+
+        [[ecosystem]]
+        kind = "npm"
+        command = "info"
+        index = "https://index.example.com/"
+
+        [[ecosystem]]
+        kind = "json"
+        command = "hex"
+        endpoint = "https://hex.pm/api/packages"
+        results = "packages"
+
+        [[ecosystem]]
+        kind = "html"
+        command = "portal"
+        endpoint = "https://packages.example.com/search?q={query}"
+        results = "table#results tr["
+
+        [ecosystem.fields]
+        name = "a.package-name"
+    "#;
+
+    let err = match Config::parse(data, |_| None) {
+        Err(Error::Config(msg)) => msg,
+        _ => panic!("the config is invalid"),
+    };
+    assert_eq!(err.lines().collect::<Vec<&str>>(),
+               vec!["telegram.token is missing, set it in the file or TELEGRAM_TOKEN",
+                    "bot.workers must be at least 1",
+                    "http.user_agent must be a non-empty single line",
+                    "analytics.path is required by the jsonl and sqlite sinks",
+                    "ecosystem java: unknown kind \"maven\", expected crates, go, npm, pypi, \
+                     json or html",
+                    "ecosystem info: the /info command is taken",
+                    "ecosystem info: index and token are only used by crates ecosystems",
+                    "ecosystem hex: endpoint must be a url containing {query}",
+                    "ecosystem hex: fields.name is required",
+                    "ecosystem portal: invalid selector `table#results tr[`: expected a name \
+                     after `[`"]);
+
+    match Config::parse("[cache]\ncapacty = 10",
+                        |name| env_var(&[("TELEGRAM_TOKEN", "t")], name)) {
+        Err(Error::Config(msg)) => assert_eq!(msg, "unknown settings: cache.capacty"),
+        _ => panic!("misspelled settings are errors"),
+    }
+    assert!(Config::parse("[bot\nworkers = 1", |_| None).is_err());
+}
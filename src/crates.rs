@@ -1,14 +1,79 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::Read;
-use hyper::client::{Client, IntoUrl};
-use hyper::client::response::Response;
-use hyper::header::{ContentType, Headers};
+use std::sync::Arc;
+use hyper::client::IntoUrl;
+use hyper::header::{Authorization, ContentType};
+use hyper::status::StatusCode;
 use hyper::Url;
 use rustc_serialize::Decodable;
-use rustc_serialize::json;
-use {SearchRepo, Package};
-use error::Error;
+use rustc_serialize::json::{self, DecoderError, Json};
+use {SearchRepo, Package, Release};
+use cargoindex::{self, HttpIndex, LocalIndex, SparseIndex};
+use error::{self, Error};
+use http::{HttpClient, HttpResponse, WithHttp};
 
-const CRATES_API_ENDPOINT: &'static str = "https://crates.io/api/v1/crates";
+const CRATES_IO_API: &'static str = "https://crates.io";
+const CRATES_API_PATH: &'static str = "api/v1/crates";
+
+/// Location of a Cargo registry, the same values as in the registry's `config.json`
+#[derive(Clone)]
+pub struct Registry {
+    /// Base url of the web API, e.g. `https://crates.io`
+    pub api_url: String,
+    /// Sparse index url, `sparse+` prefix is allowed. `file://` urls point
+    /// to a local directory with index files.
+    pub index_url: String,
+    /// Sent in the `Authorization` header to the API and the index
+    pub token: Option<String>,
+}
+
+// Configs get logged, the token is left out
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("api_url", &self.api_url)
+            .field("index_url", &self.index_url)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Registry {
+    pub fn crates_io() -> Registry {
+        Registry {
+            api_url: CRATES_IO_API.to_owned(),
+            index_url: cargoindex::CRATES_INDEX_URL.to_owned(),
+            token: None,
+        }
+    }
+
+    fn crates_endpoint(&self) -> Result<Url, Error> {
+        let base = format!("{}/", self.api_url.trim_right_matches('/'));
+        match Url::parse(&base).and_then(|u| u.join(CRATES_API_PATH)) {
+            Ok(url) => Ok(url),
+            Err(e) => Err(Error::Config(format!("invalid registry url {}: {}", self.api_url, e))),
+        }
+    }
+
+    fn index(&self, http: &HttpClient) -> Result<SparseIndex, Error> {
+        let index_url = self.index_url.trim_left_matches("sparse+");
+
+        if index_url.starts_with("file://") {
+            match Url::parse(index_url).ok().and_then(|u| u.to_file_path().ok()) {
+                Some(path) => Ok(SparseIndex::new(Arc::new(LocalIndex::new(path)))),
+                None => Err(Error::Config(format!("invalid index path {}", index_url))),
+            }
+        } else {
+            let mut index = try!(HttpIndex::new(index_url)).with_http(http.clone());
+            if let Some(ref token) = self.token {
+                index = index.with_token(token);
+            }
+
+            Ok(SparseIndex::new(Arc::new(index)))
+        }
+    }
+}
 
 
 #[derive(RustcEncodable, RustcDecodable, Clone)]
@@ -19,7 +84,7 @@ pub struct EncodableCrate {
     pub versions: Option<Vec<i32>>,
     pub keywords: Option<Vec<String>>,
     pub created_at: String,
-    pub downloads: i32,
+    pub downloads: u64,
     pub max_version: String,
     pub description: Option<String>,
     pub homepage: Option<String>,
@@ -49,57 +114,251 @@ struct Meta {
 }
 
 #[derive(Clone)]
-pub struct CratesRepo {}
+pub struct CratesRepo {
+    registry: Registry,
+    endpoint: Url,
+    index: SparseIndex,
+    http: HttpClient,
+}
 
-fn api_request_headers() -> Headers {
-    let mut headers = Headers::new();
-    headers.set(ContentType::json());
-    headers
+impl CratesRepo {
+    pub fn new(registry: Registry) -> Result<CratesRepo, Error> {
+        let http = HttpClient::shared();
+        let endpoint = try!(registry.crates_endpoint());
+        let index = try!(registry.index(&http));
+
+        Ok(CratesRepo {
+            registry: registry,
+            endpoint: endpoint,
+            index: index,
+            http: http,
+        })
+    }
+
+    /// Sends the API and index requests through `http`
+    pub fn with_http(mut self, http: HttpClient) -> CratesRepo {
+        // The registry was checked by `new`
+        self.index = self.registry.index(&http).unwrap();
+        self.http = http;
+        self
+    }
+
+    pub fn crates_io() -> CratesRepo {
+        CratesRepo::new(Registry::crates_io()).unwrap()
+    }
+
+    fn execute_api_request<U: IntoUrl>(&self, url: U) -> Result<HttpResponse, Error> {
+        let mut request = self.http.get(url).header(ContentType::json());
+        if let Some(ref token) = self.registry.token {
+            request = request.header(Authorization(token.clone()));
+        }
+
+        request.send().map_err(Error::from)
+    }
+
+    // The API has no per-version details, so they're taken from the index
+    fn add_index_details(&self, pkg: &mut Package) -> Result<(), Error> {
+        let versions = match try!(self.index.versions(&pkg.name)) {
+            Some(versions) => versions,
+            None => return Ok(()),
+        };
+
+        let yanked = versions.iter().filter(|v| v.yanked).count();
+        pkg.attributes.insert("versions".to_owned(),
+                              format!("{} ({} yanked)", versions.len(), yanked));
+
+        if let Some(latest) = cargoindex::latest_version(&versions) {
+            pkg.attributes.extend(latest.release().attributes);
+        }
+
+        Ok(())
+    }
 }
 
-fn execute_api_request<U: IntoUrl>(url: U) -> Result<Response, Error> {
-    let client = Client::new();
-    client.get(url).headers(api_request_headers()).send().map_err(Error::from)
+impl From<EncodableCrate> for Package {
+    fn from(krate: EncodableCrate) -> Package {
+        let mut attributes = BTreeMap::new();
+        attributes.insert("created".to_owned(), krate.created_at);
+
+        Package {
+            name: krate.name,
+            repository: krate.repository,
+            documentation: krate.documentation,
+            description: krate.description,
+            version: Some(krate.max_version),
+            license: krate.license,
+            downloads: Some(krate.downloads),
+            homepage: krate.homepage,
+            keywords: krate.keywords.unwrap_or(Vec::new()),
+            updated_at: Some(krate.updated_at),
+            attributes: attributes,
+        }
+    }
 }
 
-fn from_api_response<T: Decodable>(mut resp: Response) -> Result<T, Error> {
+fn from_api_response<T: Decodable>(mut resp: HttpResponse) -> Result<T, Error> {
     let mut data: String = String::new();
     try!(resp.read_to_string(&mut data));
 
     json::decode(&data).map_err(|e| Error::from(e))
 }
 
+// `crate` is a keyword, so the single crate response can't be decoded
+// into a struct directly.
+fn crate_from_api_response(mut resp: HttpResponse) -> Result<EncodableCrate, Error> {
+    let mut data: String = String::new();
+    try!(resp.read_to_string(&mut data));
+
+    let body = try!(Json::from_str(&data).map_err(DecoderError::ParseError));
+    match body.find("crate") {
+        Some(krate) => {
+            let mut decoder = json::Decoder::new(krate.clone());
+            Decodable::decode(&mut decoder).map_err(Error::from)
+        }
+        None => Err(Error::from(DecoderError::MissingFieldError("crate".to_owned()))),
+    }
+}
+
 impl SearchRepo for CratesRepo {
     fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
-        let mut endpoint = Url::parse(CRATES_API_ENDPOINT).unwrap();
+        let mut endpoint = self.endpoint.clone();
         endpoint.query_pairs_mut().append_pair("q", query);
         endpoint.query_pairs_mut().append_pair("page", "1");
         endpoint.query_pairs_mut().append_pair("per_page", "50");
 
-        let resp = try!(execute_api_request(endpoint));
+        let resp = try!(self.execute_api_request(endpoint));
+        try!(error::check_status(resp.status, &self.registry.api_url));
         let r: R = try!(from_api_response(resp));
 
         let crates: Vec<Package> = r.crates
             .into_iter()
-            .map(|krate| {
-                Package {
-                    name: krate.name,
-                    repository: krate.repository,
-                    documentation: krate.documentation,
-                    description: krate.description,
-                }
-            })
+            .map(Package::from)
             .collect();
 
         Ok(crates)
     }
+
+    fn lookup(&self, name: &str) -> Result<Option<Package>, Error> {
+        let mut endpoint = self.endpoint.clone();
+        endpoint.path_segments_mut().unwrap().push(name);
+
+        let resp = try!(self.execute_api_request(endpoint));
+        if resp.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        try!(error::check_status(resp.status, &self.registry.api_url));
+
+        let krate = try!(crate_from_api_response(resp));
+        let mut pkg = Package::from(krate);
+
+        if let Err(err) = self.add_index_details(&mut pkg) {
+            warn!("index lookup for {} failed: {:?}", pkg.name, err);
+        }
+
+        Ok(Some(pkg))
+    }
+
+    fn releases(&self, name: &str) -> Result<Option<Vec<Release>>, Error> {
+        let versions = try!(self.index.versions(name));
+        Ok(versions.map(|versions| versions.iter().map(|v| v.release()).collect()))
+    }
+}
+
+#[cfg(test)]
+fn fixture_repo() -> CratesRepo {
+    CratesRepo::crates_io().with_http(::replay::fixture_client("fixtures/crates"))
 }
 
 #[test]
 fn test_crates_search() {
-    let repo = CratesRepo {};
-    let crates = repo.search("telegram").unwrap();
-    for krate in &crates {
-        println!("{:?}", krate.name);
-    }
-}
\ No newline at end of file
+    let crates = fixture_repo().search("serde").unwrap();
+    assert_eq!(crates.len(), 2);
+
+    let serde = &crates[0];
+    assert_eq!(serde.name, "serde");
+    assert_eq!(serde.version, Some("0.8.12".to_owned()));
+    assert_eq!(serde.downloads, Some(1893422));
+    assert_eq!(serde.keywords, vec!["serde".to_owned(), "serialization".to_owned()]);
+    assert_eq!(serde.attributes.get("created"), Some(&"2014-12-05T20:20:39Z".to_owned()));
+
+    // Past i32::MAX, as the downloads of the most popular crates are
+    assert_eq!(crates[1].downloads, Some(3284117204));
+    assert!(crates[1].homepage.is_none());
+    assert!(crates[1].keywords.is_empty());
+}
+
+#[test]
+fn test_crates_lookup() {
+    let repo = fixture_repo();
+
+    let serde = repo.lookup("serde").unwrap().unwrap();
+    assert_eq!(serde.repository, Some("https://github.com/serde-rs/serde".to_owned()));
+    assert_eq!(serde.attributes.get("versions"), Some(&"4 (1 yanked)".to_owned()));
+    assert_eq!(serde.attributes.get("dependencies"), Some(&"1".to_owned()));
+
+    assert!(repo.lookup("no-such-crate").unwrap().is_none());
+}
+
+#[test]
+fn test_crates_errors() {
+    use std::io;
+    use hyper::method::Method;
+    use error::ErrorKind;
+    use replay::{error_kind, ReplayTransport};
+
+    let search_url = "https://crates.example.com/api/v1/crates?q=serde&page=1&per_page=50";
+    let (transport, http) = ReplayTransport::new()
+        .respond(Method::Get, search_url, StatusCode::ServiceUnavailable, "")
+        .respond(Method::Get,
+                 "https://crates.example.com/api/v1/crates/serde",
+                 StatusCode::Ok,
+                 "{\"crate\": {\"name\": \"serde\"}}")
+        .fail(Method::Get,
+              "https://crates.example.com/api/v1/crates/log",
+              io::ErrorKind::TimedOut)
+        .into_client();
+
+    let registry = Registry {
+        api_url: "https://crates.example.com".to_owned(),
+        index_url: "sparse+https://crates.example.com/index/".to_owned(),
+        token: Some("secret".to_owned()),
+    };
+    let repo = CratesRepo::new(registry).unwrap().with_http(http);
+
+    assert_eq!(error_kind(repo.search("serde")), Some(ErrorKind::Unavailable));
+    assert_eq!(error_kind(repo.lookup("serde")), Some(ErrorKind::Parse));
+    assert_eq!(error_kind(repo.lookup("log")), Some(ErrorKind::Timeout));
+
+    // Private registries get the token
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].headers.get::<Authorization<String>>(),
+               Some(&Authorization("secret".to_owned())));
+}
+
+#[test]
+fn test_registry_endpoints() {
+    let registry = Registry {
+        api_url: "https://crates.example.com/".to_owned(),
+        index_url: "sparse+https://crates.example.com/index/".to_owned(),
+        token: Some("secret".to_owned()),
+    };
+    assert_eq!(registry.crates_endpoint().unwrap().as_str(),
+               "https://crates.example.com/api/v1/crates");
+    assert!(registry.index(&HttpClient::shared()).is_ok());
+    assert!(!format!("{:?}", registry).contains("secret"));
+
+    let local = Registry {
+        api_url: "https://crates.example.com".to_owned(),
+        index_url: "file:///srv/index".to_owned(),
+        token: None,
+    };
+    assert!(local.index(&HttpClient::shared()).is_ok());
+
+    let invalid = Registry {
+        api_url: "not a url".to_owned(),
+        index_url: "file:///srv/index".to_owned(),
+        token: None,
+    };
+    assert!(CratesRepo::new(invalid).is_err());
+}
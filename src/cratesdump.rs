@@ -0,0 +1,552 @@
+//! Offline crates.io search backed by the database dump published at
+//! https://static.crates.io/db-dump.tar.gz.
+//!
+//! The dump is a gzipped tarball with a CSV file per table. Only `crates.csv`,
+//! `crate_downloads.csv` and `versions.csv` are imported into an in-memory
+//! inverted index. Older dumps kept the downloads in `crates.csv`.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use csv;
+use flate2::read::GzDecoder;
+use semver::Version;
+use tar::Archive;
+use {SearchRepo, Package};
+use error::{self, Error};
+use http::HttpClient;
+
+pub const CRATES_DUMP_URL: &'static str = "https://static.crates.io/db-dump.tar.gz";
+/// First retry after a failed refresh, doubled after every further failure
+const REFRESH_RETRY_DELAY: u64 = 60;
+const SEARCH_RESULTS_LIMIT: usize = 50;
+
+const EXACT_NAME_SCORE: f64 = 100.0;
+const NAME_PREFIX_SCORE: f64 = 20.0;
+const NAME_TOKEN_SCORE: f64 = 10.0;
+const DESCRIPTION_TOKEN_SCORE: f64 = 2.0;
+
+struct DumpCrate {
+    id: String,
+    name: String,
+    normalized_name: String,
+    description: Option<String>,
+    documentation: Option<String>,
+    homepage: Option<String>,
+    repository: Option<String>,
+    downloads: u64,
+    updated_at: Option<String>,
+    max_version: Option<String>,
+}
+
+struct DumpVersion {
+    crate_id: String,
+    num: String,
+    created_at: String,
+    yanked: bool,
+}
+
+/// Full-text index over the crates of a single dump
+pub struct DumpIndex {
+    crates: Vec<DumpCrate>,
+    name_index: HashMap<String, Vec<usize>>,
+    description_index: HashMap<String, Vec<usize>>,
+}
+
+// Crate names treat `-` and `_` as the same character.
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect::<Vec<String>>();
+
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+struct CsvColumns {
+    positions: HashMap<String, usize>,
+}
+
+impl CsvColumns {
+    fn new(headers: Vec<String>) -> CsvColumns {
+        CsvColumns { positions: headers.into_iter().enumerate().map(|(i, h)| (h, i)).collect() }
+    }
+
+    fn get<'a>(&self, record: &'a [String], column: &str) -> Result<&'a str, Error> {
+        match self.positions.get(column).and_then(|&i| record.get(i)) {
+            Some(value) => Ok(value),
+            None => Err(Error::Dump(format!("the {} column is missing", column))),
+        }
+    }
+
+    fn optional<'a>(&self, record: &'a [String], column: &str) -> Option<&'a str> {
+        self.positions.get(column).and_then(|&i| record.get(i)).map(|value| value.as_str())
+    }
+}
+
+fn read_crates<R: Read>(r: R) -> Result<Vec<DumpCrate>, Error> {
+    let mut reader = csv::Reader::from_reader(r);
+    let columns = CsvColumns::new(try!(reader.headers()));
+    let mut crates = Vec::new();
+
+    for record in reader.records() {
+        let record = try!(record);
+        let name = try!(columns.get(&record, "name"));
+
+        crates.push(DumpCrate {
+            id: try!(columns.get(&record, "id")).to_owned(),
+            name: name.to_owned(),
+            normalized_name: normalize_name(name),
+            description: non_empty(try!(columns.get(&record, "description"))),
+            documentation: non_empty(try!(columns.get(&record, "documentation"))),
+            homepage: non_empty(try!(columns.get(&record, "homepage"))),
+            repository: non_empty(try!(columns.get(&record, "repository"))),
+            downloads: columns.optional(&record, "downloads")
+                .and_then(|d| d.parse().ok())
+                .unwrap_or(0),
+            updated_at: non_empty(try!(columns.get(&record, "updated_at"))),
+            max_version: None,
+        });
+    }
+
+    Ok(crates)
+}
+
+fn read_crate_downloads<R: Read>(r: R) -> Result<HashMap<String, u64>, Error> {
+    let mut reader = csv::Reader::from_reader(r);
+    let columns = CsvColumns::new(try!(reader.headers()));
+    let mut downloads = HashMap::new();
+
+    for record in reader.records() {
+        let record = try!(record);
+        let crate_id = try!(columns.get(&record, "crate_id"));
+        let count = try!(columns.get(&record, "downloads")).parse().unwrap_or(0);
+        downloads.insert(crate_id.to_owned(), count);
+    }
+
+    Ok(downloads)
+}
+
+fn read_versions<R: Read>(r: R) -> Result<Vec<DumpVersion>, Error> {
+    let mut reader = csv::Reader::from_reader(r);
+    let columns = CsvColumns::new(try!(reader.headers()));
+    let mut versions = Vec::new();
+
+    for record in reader.records() {
+        let record = try!(record);
+
+        versions.push(DumpVersion {
+            crate_id: try!(columns.get(&record, "crate_id")).to_owned(),
+            num: try!(columns.get(&record, "num")).to_owned(),
+            created_at: try!(columns.get(&record, "created_at")).to_owned(),
+            yanked: try!(columns.get(&record, "yanked")) == "t",
+        });
+    }
+
+    Ok(versions)
+}
+
+/// The highest version that is not a pre-release like `cargoindex::latest_version`,
+/// by publication date when no version number parses
+fn latest_version(versions: Vec<DumpVersion>) -> Option<DumpVersion> {
+    let (mut parsed, unparsed): (Vec<_>, Vec<_>) = versions.into_iter()
+        .map(|v| (Version::parse(&v.num).ok(), v))
+        .partition(|&(ref semver, _)| semver.is_some());
+
+    if parsed.is_empty() {
+        return unparsed.into_iter().map(|(_, v)| v).max_by_key(|v| v.created_at.clone());
+    }
+
+    parsed.sort_by(|a, b| a.0.cmp(&b.0));
+    let stable = parsed.iter()
+        .rposition(|&(ref semver, _)| !semver.as_ref().unwrap().is_prerelease());
+    let i = stable.unwrap_or(parsed.len() - 1);
+    Some(parsed.swap_remove(i).1)
+}
+
+impl DumpIndex {
+    /// Imports the `db-dump.tar.gz` stream
+    pub fn import<R: Read>(r: R) -> Result<DumpIndex, Error> {
+        let mut archive = Archive::new(try!(GzDecoder::new(r)));
+        let mut crates = None;
+        let mut downloads = None;
+        let mut versions = None;
+
+        for entry in try!(archive.entries()) {
+            let entry = try!(entry);
+            let path = try!(entry.path()).into_owned();
+
+            if path.ends_with("data/crates.csv") {
+                crates = Some(try!(read_crates(entry)));
+            } else if path.ends_with("data/crate_downloads.csv") {
+                downloads = Some(try!(read_crate_downloads(entry)));
+            } else if path.ends_with("data/versions.csv") {
+                versions = Some(try!(read_versions(entry)));
+            }
+        }
+
+        match crates {
+            Some(crates) => {
+                Ok(DumpIndex::new(crates,
+                                  downloads.unwrap_or(HashMap::new()),
+                                  versions.unwrap_or(Vec::new())))
+            }
+            None => Err(Error::Dump("crates.csv is missing".to_owned())),
+        }
+    }
+
+    fn new(mut crates: Vec<DumpCrate>,
+           downloads: HashMap<String, u64>,
+           versions: Vec<DumpVersion>)
+           -> DumpIndex {
+        let mut published: HashMap<String, Vec<DumpVersion>> = HashMap::new();
+        for version in versions.into_iter().filter(|v| !v.yanked) {
+            published.entry(version.crate_id.clone()).or_insert_with(Vec::new).push(version);
+        }
+
+        let mut name_index: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut description_index: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for (i, krate) in crates.iter_mut().enumerate() {
+            krate.max_version = published.remove(&krate.id)
+                .and_then(latest_version)
+                .map(|v| v.num);
+            if let Some(&count) = downloads.get(&krate.id) {
+                krate.downloads = count;
+            }
+
+            for token in tokenize(&krate.name) {
+                name_index.entry(token).or_insert_with(Vec::new).push(i);
+            }
+
+            if let Some(ref description) = krate.description {
+                for token in tokenize(description) {
+                    description_index.entry(token).or_insert_with(Vec::new).push(i);
+                }
+            }
+        }
+
+        DumpIndex {
+            crates: crates,
+            name_index: name_index,
+            description_index: description_index,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.crates.len()
+    }
+
+    /// Ranks crates by matches of the query in their name and description,
+    /// popular crates win between equally good matches
+    pub fn search(&self, query: &str) -> Vec<Package> {
+        let normalized = normalize_name(query.trim());
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+
+        for token in tokenize(query) {
+            if let Some(postings) = self.name_index.get(&token) {
+                for &i in postings {
+                    *scores.entry(i).or_insert(0.0) += NAME_TOKEN_SCORE;
+                }
+            }
+
+            if let Some(postings) = self.description_index.get(&token) {
+                for &i in postings {
+                    *scores.entry(i).or_insert(0.0) += DESCRIPTION_TOKEN_SCORE;
+                }
+            }
+        }
+
+        let mut ranked = scores.into_iter()
+            .map(|(i, score)| {
+                let krate = &self.crates[i];
+                let name_score = if krate.normalized_name == normalized {
+                    EXACT_NAME_SCORE
+                } else if krate.normalized_name.starts_with(&normalized) {
+                    NAME_PREFIX_SCORE
+                } else {
+                    0.0
+                };
+
+                (i, score + name_score + (krate.downloads as f64 + 1.0).ln())
+            })
+            .collect::<Vec<(usize, f64)>>();
+
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        ranked.into_iter()
+            .take(SEARCH_RESULTS_LIMIT)
+            .map(|(i, _)| self.package(&self.crates[i]))
+            .collect()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Package> {
+        let normalized = normalize_name(name);
+        self.crates
+            .iter()
+            .find(|krate| krate.normalized_name == normalized)
+            .map(|krate| self.package(krate))
+    }
+
+    fn package(&self, krate: &DumpCrate) -> Package {
+        let mut pkg = Package::empty();
+
+        pkg.name = krate.name.clone();
+        pkg.description = krate.description.clone();
+        pkg.documentation = krate.documentation.clone();
+        pkg.homepage = krate.homepage.clone();
+        pkg.repository = krate.repository.clone();
+        pkg.downloads = Some(krate.downloads);
+        pkg.updated_at = krate.updated_at.clone();
+        pkg.version = krate.max_version.clone();
+
+        pkg
+    }
+}
+
+/// Delay before retrying a refresh that failed `failures` times in a row
+fn retry_delay(failures: u32, interval: Duration) -> Duration {
+    let delay = Duration::from_secs(REFRESH_RETRY_DELAY << failures.min(16));
+    if delay < interval { delay } else { interval }
+}
+
+/// Search repository answering from the latest imported dump
+#[derive(Clone)]
+pub struct DumpRepo {
+    index: Arc<RwLock<Option<Arc<DumpIndex>>>>,
+}
+
+impl DumpRepo {
+    pub fn new() -> DumpRepo {
+        DumpRepo { index: Arc::new(RwLock::new(None)) }
+    }
+
+    /// Imports the dump file and swaps it with the current index
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let index = try!(DumpRepo::import(path));
+        self.swap(index);
+
+        Ok(())
+    }
+
+    fn import<P: AsRef<Path>>(path: P) -> Result<DumpIndex, Error> {
+        let file = try!(File::open(path));
+        DumpIndex::import(io::BufReader::new(file))
+    }
+
+    fn swap(&self, index: DumpIndex) {
+        info!("crates.io dump is imported, {} crates", index.len());
+        *self.index.write().unwrap() = Some(Arc::new(index));
+    }
+
+    /// Downloads a fresh dump and imports it. The download replaces the
+    /// local copy only once it's imported, so a broken dump leaves both the
+    /// previous index and the previous file in place.
+    pub fn refresh<P: AsRef<Path>>(&self, url: &str, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("download");
+
+        let imported = DumpRepo::download(url, &tmp_path).and_then(|_| DumpRepo::import(&tmp_path));
+        let index = match imported {
+            Ok(index) => index,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+
+        try!(fs::rename(&tmp_path, path));
+        self.swap(index);
+
+        Ok(())
+    }
+
+    fn download(url: &str, path: &Path) -> Result<(), Error> {
+        let mut resp = try!(HttpClient::shared().get(url).send());
+        try!(error::check_status(resp.status, url));
+        let mut file = try!(File::create(path));
+        try!(io::copy(&mut resp, &mut file));
+
+        Ok(())
+    }
+
+    /// Loads the local copy of the dump and keeps it fresh in a background
+    /// thread. Failed refreshes are retried with a growing delay, the stale
+    /// local copy is served in the meantime.
+    pub fn refresh_every(&self, url: &str, path: PathBuf, interval: Duration) {
+        let repo = self.clone();
+        let url = url.to_owned();
+
+        thread::spawn(move || {
+            let mut failures = 0;
+
+            loop {
+                let age = fs::metadata(&path)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+                let result = match age {
+                    Some(age) if age < interval && repo.is_empty() => repo.load(&path),
+                    Some(age) if age < interval => Ok(()),
+                    _ => repo.refresh(&url, &path),
+                };
+
+                match result {
+                    Ok(()) => {
+                        failures = 0;
+                        thread::sleep(interval);
+                    }
+                    Err(err) => {
+                        error!("crates.io dump refresh failed: {:?}", err);
+                        if repo.is_empty() && path.exists() {
+                            if let Err(err) = repo.load(&path) {
+                                error!("loading the previous crates.io dump failed: {:?}", err);
+                            }
+                        }
+
+                        thread::sleep(retry_delay(failures, interval));
+                        failures += 1;
+                    }
+                }
+            }
+        });
+    }
+
+    fn is_empty(&self) -> bool {
+        self.index.read().unwrap().is_none()
+    }
+
+    fn current(&self) -> Result<Arc<DumpIndex>, Error> {
+        match *self.index.read().unwrap() {
+            Some(ref index) => Ok(index.clone()),
+            None => Err(Error::Unavailable("crates.io dump".to_owned())),
+        }
+    }
+}
+
+impl SearchRepo for DumpRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let index = try!(self.current());
+        Ok(index.search(query))
+    }
+
+    fn lookup(&self, name: &str) -> Result<Option<Package>, Error> {
+        let index = try!(self.current());
+        Ok(index.lookup(name))
+    }
+}
+
+#[test]
+fn test_dump_import() {
+    let dump: &[u8] = include_bytes!("../fixtures/crates/db-dump.tar.gz");
+    let index = DumpIndex::import(dump).unwrap();
+    assert_eq!(index.len(), 5);
+
+    let serde = index.lookup("serde").unwrap();
+    assert_eq!(serde.version, Some("0.8.12".to_owned()));
+    assert_eq!(serde.downloads, Some(1927561));
+    assert_eq!(serde.homepage, Some("https://serde.rs".to_owned()));
+
+    let telegram = index.lookup("telegram_bot").unwrap();
+    assert_eq!(telegram.name, "telegram-bot");
+}
+
+#[test]
+fn test_current_dump_import() {
+    // Downloads moved from `crates.csv` to `crate_downloads.csv`
+    let dump: &[u8] = include_bytes!("../fixtures/crates/db-dump-current.tar.gz");
+    let index = DumpIndex::import(dump).unwrap();
+    assert_eq!(index.len(), 3);
+
+    // The 0.9 backport was published last
+    let serde = index.lookup("serde").unwrap();
+    assert_eq!(serde.version, Some("1.0.199".to_owned()));
+    assert_eq!(serde.downloads, Some(312345678901));
+
+    let names = index.search("json").into_iter().map(|p| p.name).collect::<Vec<String>>();
+    assert_eq!(names, vec!["json", "serde_json"]);
+}
+
+#[test]
+fn test_latest_dump_version() {
+    let versions = |nums: &[&str]| {
+        nums.iter()
+            .enumerate()
+            .map(|(i, num)| {
+                DumpVersion {
+                    crate_id: "1".to_owned(),
+                    num: num.to_string(),
+                    created_at: format!("2024-04-0{} 10:00:00", i + 1),
+                    yanked: false,
+                }
+            })
+            .collect::<Vec<DumpVersion>>()
+    };
+    let num = |nums: &[&str]| latest_version(versions(nums)).map(|v| v.num);
+
+    assert_eq!(num(&["1.2.0", "0.9.7", "2.0.0-alpha.1"]), Some("1.2.0".to_owned()));
+    assert_eq!(num(&["0.1.0-beta", "0.1.0-alpha"]), Some("0.1.0-beta".to_owned()));
+    assert_eq!(num(&["first", "second"]), Some("second".to_owned()));
+    assert_eq!(num(&[]), None);
+}
+
+#[test]
+fn test_dump_search_ranking() {
+    let dump: &[u8] = include_bytes!("../fixtures/crates/db-dump.tar.gz");
+    let index = DumpIndex::import(dump).unwrap();
+
+    let names = |query| index.search(query).into_iter().map(|p| p.name).collect::<Vec<String>>();
+
+    assert_eq!(names("serde"), vec!["serde", "serde_json", "serde_yaml"]);
+    assert_eq!(names("json"), vec!["json", "serde_json"]);
+    assert!(names("nothing matches").is_empty());
+}
+
+#[test]
+fn test_dump_repo_not_imported() {
+    let repo = DumpRepo::new();
+    assert!(repo.search("serde").is_err());
+}
+
+#[test]
+fn test_failed_refresh_keeps_dump() {
+    let path = ::std::env::temp_dir().join("packagesbot-test-failed-refresh.tar.gz");
+    fs::copy("fixtures/crates/db-dump.tar.gz", &path).unwrap();
+
+    let repo = DumpRepo::new();
+    repo.load(&path).unwrap();
+    assert!(repo.refresh("http://127.0.0.1:1/db-dump.tar.gz", &path).is_err());
+
+    assert!(path.exists());
+    assert!(!path.with_extension("download").exists());
+    assert_eq!(repo.search("serde").unwrap()[0].name, "serde");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_retry_delay() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    assert_eq!(retry_delay(0, day), Duration::from_secs(60));
+    assert_eq!(retry_delay(3, day), Duration::from_secs(480));
+    assert_eq!(retry_delay(40, day), day);
+    assert_eq!(retry_delay(0, Duration::from_secs(30)), Duration::from_secs(30));
+}
@@ -1,62 +1,202 @@
 use std::error::Error as StdError;
-use std::fmt::{self, Debug, Display, Formatter};
 use std::io;
+use csv;
 use hyper::Error as HyperError;
-use rustc_serialize::json::DecoderError;
+use hyper::status::StatusCode;
+use rusqlite;
+use rustc_serialize::json::{DecoderError, EncoderError};
 use telegram_bot;
+use telegram::botanio::BotanError;
 
-pub enum Error {
-    General(String),
+/// Coarse classification of errors for deciding how to react to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The upstream service can't be reached or answers with a server error
+    Unavailable,
+    Timeout,
+    RateLimited,
+    /// The upstream response doesn't have the expected format
+    Parse,
+    Telegram,
+    Analytics,
+    Storage,
+    Config,
+    /// The user asked for something that can't exist, e.g. a malformed name
+    InvalidInput,
+    Other,
 }
 
-impl From<HyperError> for Error {
-    fn from(e: HyperError) -> Error {
-        Error::General(e.description().to_owned())
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Http(err: HyperError) {
+            from()
+            cause(err)
+            description(err.description())
+            display("http error: {}", err)
+        }
+        Status(service: String, status: StatusCode) {
+            description("unexpected response status")
+            display("{} answered {}", service, status)
+        }
+        Decode(err: DecoderError) {
+            from()
+            cause(err)
+            description(err.description())
+            display("decoding error: {}", err)
+        }
+        Encode(err: EncoderError) {
+            from()
+            cause(err)
+            description(err.description())
+            display("encoding error: {}", err)
+        }
+        Csv(err: csv::Error) {
+            from()
+            cause(err)
+            description(err.description())
+            display("csv error: {}", err)
+        }
+        Scrape(msg: String) {
+            description("unexpected page layout")
+            display("scraping error: {}", msg)
+        }
+        Toml(msg: String) {
+            description("invalid toml")
+            display("toml error: {}", msg)
+        }
+        GoMod(msg: String) {
+            description("invalid go.mod")
+            display("go.mod error: {}", msg)
+        }
+        Dump(msg: String) {
+            description("invalid crates.io dump")
+            display("crates.io dump error: {}", msg)
+        }
+        Unavailable(what: String) {
+            description("not available")
+            display("{} is not available", what)
+        }
+        Telegram(err: telegram_bot::Error) {
+            from()
+            cause(err)
+            description(err.description())
+            display("telegram error: {}", err)
+        }
+        TelegramApi(method: String, code: Option<i64>, reason: String) {
+            description("telegram api call failed")
+            display("{} failed: {}", method, reason)
+        }
+        Botan(err: BotanError) {
+            from()
+            cause(err)
+            description(err.description())
+            display("botan error: {}", err)
+        }
+        Sqlite(err: rusqlite::Error) {
+            from()
+            cause(err)
+            description(err.description())
+            display("sqlite error: {}", err)
+        }
+        Config(msg: String) {
+            description("invalid configuration")
+            display("invalid configuration: {}", msg)
+        }
+        InvalidInput(msg: String) {
+            description("invalid input")
+            display("invalid input: {}", msg)
+        }
+        Io(err: io::Error) {
+            from()
+            cause(err)
+            description(err.description())
+            display("io error: {}", err)
+        }
+        FileTooLarge(limit: u64) {
+            description("file too large")
+            display("the file is larger than {} bytes", limit)
+        }
+        NotCommand(text: String) {
+            description("not a command")
+            display("not a command: {}", text)
+        }
     }
 }
 
-impl From<DecoderError> for Error {
-    fn from(e: DecoderError) -> Error {
-        Error::General(e.description().to_owned())
-    }
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
 }
 
-impl From<io::Error> for Error {
-    fn from(e: io::Error) -> Error {
-        Error::General(e.description().to_owned())
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::Http(HyperError::Io(ref e)) if is_timeout(e) => ErrorKind::Timeout,
+            Error::Http(HyperError::Io(_)) => ErrorKind::Unavailable,
+            Error::Http(_) => ErrorKind::Other,
+            Error::Status(_, status) if status == StatusCode::TooManyRequests => {
+                ErrorKind::RateLimited
+            }
+            Error::Status(_, status) if status.is_server_error() => ErrorKind::Unavailable,
+            Error::Status(..) => ErrorKind::Other,
+            Error::Decode(_) | Error::Csv(_) | Error::Scrape(_) | Error::Toml(_) |
+            Error::GoMod(_) | Error::Dump(_) => ErrorKind::Parse,
+            Error::Unavailable(_) => ErrorKind::Unavailable,
+            Error::TelegramApi(_, Some(429), _) => ErrorKind::RateLimited,
+            Error::TelegramApi(_, Some(code), _) if code >= 500 => ErrorKind::Unavailable,
+            Error::Telegram(_) | Error::TelegramApi(..) => ErrorKind::Telegram,
+            Error::Botan(_) => ErrorKind::Analytics,
+            Error::Sqlite(_) => ErrorKind::Storage,
+            Error::Config(_) => ErrorKind::Config,
+            Error::InvalidInput(_) => ErrorKind::InvalidInput,
+            Error::Io(ref e) if is_timeout(e) => ErrorKind::Timeout,
+            Error::Encode(_) | Error::Io(_) | Error::FileTooLarge(_) | Error::NotCommand(_) => {
+                ErrorKind::Other
+            }
+        }
     }
 }
 
-impl From<telegram_bot::Error> for Error {
-    fn from(e: telegram_bot::Error) -> Error {
-        Error::General(e.description().to_owned())
+/// Turns unsuccessful upstream responses into errors, callers handle
+/// expected statuses like 404 before checking the rest
+pub fn check_status(status: StatusCode, service: &str) -> Result<(), Error> {
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(Error::Status(service.to_owned(), status))
     }
 }
 
-impl Debug for Error {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        match *self {
-            Error::General(ref msg) => write!(f, "error: {}", msg).unwrap(),
-        };
+#[test]
+fn test_check_status() {
+    assert!(check_status(StatusCode::Ok, "crates.io").is_ok());
 
-        Ok(())
-    }
-}
+    let err = check_status(StatusCode::TooManyRequests, "crates.io").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RateLimited);
+    assert_eq!(err.to_string(), "crates.io answered 429 Too Many Requests");
 
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        match *self {
-            Error::General(ref msg) => write!(f, "error: {}", msg).unwrap(),
-        };
+    let err = check_status(StatusCode::BadGateway, "npm").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unavailable);
 
-        Ok(())
-    }
+    let err = check_status(StatusCode::Forbidden, "npm").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
 }
 
-impl StdError for Error {
-    fn description(&self) -> &str {
-        match *self {
-            Error::General(ref msg) => msg,
-        }
-    }
-}
\ No newline at end of file
+#[test]
+fn test_error_kind() {
+    let timeout = io::Error::new(io::ErrorKind::TimedOut, "read timed out");
+    assert_eq!(Error::from(HyperError::Io(timeout)).kind(), ErrorKind::Timeout);
+
+    let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+    let err = Error::from(HyperError::Io(refused));
+    assert_eq!(err.kind(), ErrorKind::Unavailable);
+    assert!(err.cause().is_some());
+
+    let err = Error::from(DecoderError::MissingFieldError("crate".to_owned()));
+    assert_eq!(err.kind(), ErrorKind::Parse);
+
+    let flood = Error::TelegramApi("sendMessage".to_owned(), Some(429), "Too Many".to_owned());
+    assert_eq!(flood.kind(), ErrorKind::RateLimited);
+    let blocked = Error::TelegramApi("sendMessage".to_owned(), Some(403), "blocked".to_owned());
+    assert_eq!(blocked.kind(), ErrorKind::Telegram);
+}
@@ -0,0 +1,41 @@
+use {SearchRepo, Package, Release};
+use error::Error;
+
+/// Answers from the secondary repository whenever the primary one fails
+#[derive(Clone)]
+pub struct FallbackRepo {
+    primary: Box<SearchRepo>,
+    fallback: Box<SearchRepo>,
+}
+
+impl FallbackRepo {
+    pub fn new(primary: Box<SearchRepo>, fallback: Box<SearchRepo>) -> FallbackRepo {
+        FallbackRepo {
+            primary: primary,
+            fallback: fallback,
+        }
+    }
+}
+
+impl SearchRepo for FallbackRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        self.primary.search(query).or_else(|err| {
+            warn!("search failed, using fallback: {:?}", err);
+            self.fallback.search(query)
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Option<Package>, Error> {
+        self.primary.lookup(name).or_else(|err| {
+            warn!("lookup failed, using fallback: {:?}", err);
+            self.fallback.lookup(name)
+        })
+    }
+
+    fn releases(&self, name: &str) -> Result<Option<Vec<Release>>, Error> {
+        self.primary.releases(name).or_else(|err| {
+            warn!("releases lookup failed, using fallback: {:?}", err);
+            self.fallback.releases(name)
+        })
+    }
+}
@@ -0,0 +1,514 @@
+//! Parsing of `go.mod` files (https://go.dev/ref/mod#go-mod-file) and a
+//! check of the required modules against a module proxy.
+
+use std::mem;
+
+use goproxy::{self, GoRepo};
+use error::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requirement {
+    pub path: String,
+    pub version: String,
+    pub indirect: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    pub path: String,
+    /// `None` replaces every version of the module
+    pub version: Option<String>,
+    pub new_path: String,
+    /// `None` if the replacement is a directory
+    pub new_version: Option<String>,
+}
+
+impl Replacement {
+    fn applies(&self, req: &Requirement) -> bool {
+        self.path == req.path && self.version.as_ref().map_or(true, |v| *v == req.version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retraction {
+    pub low: String,
+    pub high: String,
+    pub rationale: Option<String>,
+}
+
+impl Retraction {
+    pub fn contains(&self, version: &str) -> bool {
+        match (goproxy::semver(version), goproxy::semver(&self.low), goproxy::semver(&self.high)) {
+            (Some(v), Some(low), Some(high)) => low <= v && v <= high,
+            _ => version == self.low || version == self.high,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GoMod {
+    pub module: Option<String>,
+    pub go: Option<String>,
+    pub require: Vec<Requirement>,
+    pub replace: Vec<Replacement>,
+    pub exclude: Vec<(String, String)>,
+    pub retract: Vec<Retraction>,
+}
+
+fn syntax_error(line: usize, msg: &str) -> Error {
+    Error::GoMod(format!("line {}: {}", line, msg))
+}
+
+fn unquote(token: &str) -> String {
+    token.trim_matches(|c| c == '"' || c == '`').to_owned()
+}
+
+fn parse_retraction(args: &str, rationale: Option<String>) -> Option<Retraction> {
+    let args = args.trim();
+
+    let (low, high) = if args.starts_with('[') && args.ends_with(']') {
+        let mut bounds = args[1..args.len() - 1].splitn(2, ',');
+        match (bounds.next(), bounds.next()) {
+            (Some(low), Some(high)) => (low.trim().to_owned(), high.trim().to_owned()),
+            _ => return None,
+        }
+    } else if !args.is_empty() && !args.contains(char::is_whitespace) {
+        (args.to_owned(), args.to_owned())
+    } else {
+        return None;
+    };
+
+    Some(Retraction {
+        low: low,
+        high: high,
+        rationale: rationale,
+    })
+}
+
+impl GoMod {
+    /// `before` are the comment lines right above the directive, `comment`
+    /// the one trailing it
+    fn directive(&mut self,
+                 verb: &str,
+                 args: &str,
+                 before: &[String],
+                 comment: Option<String>,
+                 line: usize)
+                 -> Result<(), Error> {
+        let tokens = args.split_whitespace().map(unquote).collect::<Vec<String>>();
+
+        match verb {
+            "module" if tokens.len() == 1 => self.module = Some(tokens[0].clone()),
+            "go" if tokens.len() == 1 => self.go = Some(tokens[0].clone()),
+            "require" if tokens.len() == 2 => {
+                self.require.push(Requirement {
+                    path: tokens[0].clone(),
+                    version: tokens[1].clone(),
+                    indirect: comment.as_ref().map_or(false, |c| c == "indirect"),
+                })
+            }
+            "exclude" if tokens.len() == 2 => {
+                self.exclude.push((tokens[0].clone(), tokens[1].clone()))
+            }
+            "replace" => {
+                let arrow = match tokens.iter().position(|t| t == "=>") {
+                    Some(arrow) => arrow,
+                    None => return Err(syntax_error(line, "replace without =>")),
+                };
+                let (old, new) = (&tokens[..arrow], &tokens[arrow + 1..]);
+                if old.is_empty() || old.len() > 2 || new.is_empty() || new.len() > 2 {
+                    return Err(syntax_error(line, "invalid replace"));
+                }
+
+                self.replace.push(Replacement {
+                    path: old[0].clone(),
+                    version: old.get(1).cloned(),
+                    new_path: new[0].clone(),
+                    new_version: new.get(1).cloned(),
+                });
+            }
+            "retract" => {
+                // The rationale may span the lines above and the trailing comment
+                let mut lines = before.to_vec();
+                lines.extend(comment);
+                lines.retain(|l| !l.is_empty());
+                let rationale = if lines.is_empty() {
+                    None
+                } else {
+                    Some(lines.join(" "))
+                };
+
+                match parse_retraction(args, rationale) {
+                    Some(retraction) => self.retract.push(retraction),
+                    None => return Err(syntax_error(line, "invalid retract")),
+                }
+            }
+            "module" | "go" | "require" | "exclude" => {
+                return Err(syntax_error(line, &format!("invalid {}", verb)))
+            }
+            // toolchain, godebug and future directives don't matter here
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses both the single line and the block form of the directives
+pub fn parse_go_mod(data: &str) -> Result<GoMod, Error> {
+    let mut go_mod = GoMod::default();
+    // The verb of the open block and the comment lines above it
+    let mut block: Option<(String, Vec<String>)> = None;
+    // Comment lines since the last directive, a blank line drops them
+    let mut comments = Vec::new();
+
+    for (i, line) in data.lines().enumerate() {
+        let (code, comment) = match line.find("//") {
+            Some(pos) => (&line[..pos], Some(line[pos + 2..].trim().to_owned())),
+            None => (line, None),
+        };
+        let code = code.trim();
+        if code.is_empty() {
+            match comment {
+                Some(comment) => comments.push(comment),
+                None => comments.clear(),
+            }
+            continue;
+        }
+        let before = mem::replace(&mut comments, Vec::new());
+
+        if code == ")" {
+            if block.take().is_none() {
+                return Err(syntax_error(i + 1, "unexpected )"));
+            }
+            continue;
+        }
+
+        if let Some((ref verb, ref block_comments)) = block {
+            // Uncommented lines of a block take the comments above the block
+            let before = if before.is_empty() && comment.is_none() {
+                block_comments
+            } else {
+                &before
+            };
+            try!(go_mod.directive(verb, code, before, comment, i + 1));
+            continue;
+        }
+
+        let mut parts = code.splitn(2, char::is_whitespace);
+        let verb = parts.next().unwrap_or("");
+        let args = parts.next().unwrap_or("").trim();
+
+        if args == "(" {
+            block = Some((verb.to_owned(), before));
+        } else {
+            try!(go_mod.directive(verb, args, &before, comment, i + 1));
+        }
+    }
+
+    if block.is_some() {
+        return Err(Error::GoMod("unterminated block".to_owned()));
+    }
+
+    Ok(go_mod)
+}
+
+/// Splits the major version suffix off a module path, e.g.
+/// `github.com/go-redis/redis/v8` has the major version 8. Paths without a
+/// suffix are at major version 0 or 1 and `None` is returned for them.
+pub fn major_version(path: &str) -> (&str, Option<u64>) {
+    if let Some(pos) = path.rfind("/v") {
+        if let Ok(major) = path[pos + 2..].parse::<u64>() {
+            if major >= 2 && !path[pos + 2..].starts_with('0') {
+                return (&path[..pos], Some(major));
+            }
+        }
+    }
+
+    (path, None)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoFinding {
+    Upgrade(String),
+    Retracted(Option<String>),
+    /// A module path with a newer major version and its latest version
+    MajorVersion(String, String),
+    /// The module is replaced by a directory
+    Replaced(String),
+    /// The module is missing from the proxy or the lookup failed
+    Unchecked(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct GoModEntry {
+    pub path: String,
+    pub version: String,
+    pub findings: Vec<GoFinding>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GoModReport {
+    pub module: Option<String>,
+    pub requirements: usize,
+    /// Modules with findings only
+    pub entries: Vec<GoModEntry>,
+}
+
+impl GoModReport {
+    pub fn count<F: Fn(&GoFinding) -> bool>(&self, f: F) -> usize {
+        self.entries.iter().filter(|e| e.findings.iter().any(|finding| f(finding))).count()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{}{} modules: {} upgradable, {} retracted, {} with a new major version",
+                self.module.as_ref().map_or(String::new(), |m| format!("{}, ", m)),
+                self.requirements,
+                self.count(|f| match *f {
+                    GoFinding::Upgrade(_) => true,
+                    _ => false,
+                }),
+                self.count(|f| match *f {
+                    GoFinding::Retracted(_) => true,
+                    _ => false,
+                }),
+                self.count(|f| match *f {
+                    GoFinding::MajorVersion(..) => true,
+                    _ => false,
+                }))
+    }
+
+    /// Plain text version of the report for downloading
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", self.summary());
+
+        for entry in &self.entries {
+            text.push_str(&format!("\n{} {}\n", entry.path, entry.version));
+
+            for finding in &entry.findings {
+                let line = match *finding {
+                    GoFinding::Upgrade(ref latest) => format!("  upgrade to {}", latest),
+                    GoFinding::Retracted(ref rationale) => {
+                        format!("  retracted {}", rationale.as_ref().map_or("", |r| r.as_ref()))
+                    }
+                    GoFinding::MajorVersion(ref path, ref version) => {
+                        format!("  new major version {} {}", path, version)
+                    }
+                    GoFinding::Replaced(ref dir) => format!("  replaced by {}", dir),
+                    GoFinding::Unchecked(ref reason) => format!("  not checked: {}", reason),
+                };
+                text.push_str(line.trim_right());
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+/// Versions of a module known to the proxy, the newest last
+fn known_versions(repo: &GoRepo, path: &str) -> Result<Vec<String>, Error> {
+    if let Some(versions) = try!(repo.versions(path)) {
+        if !versions.is_empty() {
+            return Ok(versions);
+        }
+    }
+
+    // Modules without tagged versions only have a pseudo-version at @latest
+    Ok(try!(repo.latest(path)).into_iter().map(|info| info.Version).collect())
+}
+
+fn check_requirement(repo: &GoRepo,
+                     go_mod: &GoMod,
+                     path: &str,
+                     version: &str,
+                     findings: &mut Vec<GoFinding>)
+                     -> Result<(), Error> {
+    let versions = try!(known_versions(repo, path));
+    let newest = match versions.last() {
+        Some(newest) => newest,
+        None => {
+            findings.push(GoFinding::Unchecked("not found in the module proxy".to_owned()));
+            return Ok(());
+        }
+    };
+
+    // Retractions are published in the go.mod of the newest version
+    let retractions = match try!(repo.go_mod(path, newest)) {
+        Some(data) => {
+            match parse_go_mod(&data) {
+                Ok(newest_mod) => newest_mod.retract,
+                Err(err) => {
+                    warn!("invalid go.mod of {} {}: {:?}", path, newest, err);
+                    Vec::new()
+                }
+            }
+        }
+        None => Vec::new(),
+    };
+
+    if let Some(retraction) = retractions.iter().find(|r| r.contains(version)) {
+        findings.push(GoFinding::Retracted(retraction.rationale.clone()));
+    }
+
+    let current = goproxy::semver(version);
+    let upgrade = versions.iter()
+        .rev()
+        .filter_map(|v| goproxy::semver(v).map(|s| (s, v)))
+        .filter(|&(ref s, _)| !s.is_prerelease())
+        .filter(|&(_, v)| !retractions.iter().any(|r| r.contains(v)))
+        .find(|&(_, v)| !go_mod.exclude.iter().any(|e| e.0 == path && e.1 == *v));
+    if let (Some(current), Some((latest, v))) = (current, upgrade) {
+        if latest > current {
+            findings.push(GoFinding::Upgrade(v.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Looks for `/vN` paths past the major version of the module
+fn check_major_version(repo: &GoRepo, path: &str, findings: &mut Vec<GoFinding>) {
+    if path.starts_with("gopkg.in/") {
+        return;
+    }
+
+    let (base, major) = major_version(path);
+    let mut next = major.unwrap_or(1) + 1;
+    let mut newest = None;
+
+    loop {
+        let next_path = format!("{}/v{}", base, next);
+        match known_versions(repo, &next_path) {
+            Ok(ref versions) if !versions.is_empty() => {
+                newest = Some((next_path, versions[versions.len() - 1].clone()));
+                next += 1;
+            }
+            _ => break,
+        }
+    }
+
+    if let Some((path, version)) = newest {
+        findings.push(GoFinding::MajorVersion(path, version));
+    }
+}
+
+/// Checks every required module for newer versions, retractions and newer
+/// major versions. Replaced modules are checked at their replacement.
+pub fn check(repo: &GoRepo, go_mod: &GoMod) -> GoModReport {
+    let mut entries = Vec::new();
+
+    for req in &go_mod.require {
+        let mut findings = Vec::new();
+        let replacement = go_mod.replace.iter().rev().find(|r| r.applies(req));
+
+        let (path, version): (&str, &str) = match replacement {
+            Some(&Replacement { ref new_path, new_version: Some(ref new_version), .. }) => {
+                (new_path, new_version)
+            }
+            Some(&Replacement { ref new_path, new_version: None, .. }) => {
+                findings.push(GoFinding::Replaced(new_path.clone()));
+                ("", "")
+            }
+            None => (&req.path, &req.version),
+        };
+
+        if !path.is_empty() {
+            if let Err(err) = check_requirement(repo, go_mod, path, version, &mut findings) {
+                warn!("checking {} {} failed: {:?}", path, version, err);
+                findings.push(GoFinding::Unchecked(format!("{}", err)));
+            }
+            check_major_version(repo, path, &mut findings);
+        }
+
+        if !findings.is_empty() {
+            entries.push(GoModEntry {
+                path: req.path.clone(),
+                version: req.version.clone(),
+                findings: findings,
+            });
+        }
+    }
+
+    GoModReport {
+        module: go_mod.module.clone(),
+        requirements: go_mod.require.len(),
+        entries: entries,
+    }
+}
+
+#[test]
+fn test_parse_go_mod() {
+    let go_mod = parse_go_mod(include_str!("../fixtures/gomod/go.mod")).unwrap();
+
+    assert_eq!(go_mod.module, Some("example.com/app".to_owned()));
+    assert_eq!(go_mod.go, Some("1.21".to_owned()));
+    assert_eq!(go_mod.require.len(), 5);
+    assert_eq!(go_mod.require[0],
+               Requirement {
+                   path: "github.com/gorilla/mux".to_owned(),
+                   version: "v1.6.2".to_owned(),
+                   indirect: false,
+               });
+    assert!(go_mod.require[4].indirect);
+    assert_eq!(go_mod.exclude,
+               vec![("github.com/gorilla/mux".to_owned(), "v1.8.0".to_owned())]);
+    assert_eq!(go_mod.replace[0].new_path, "../local");
+    assert_eq!(go_mod.replace[0].version, None);
+
+    assert_eq!(go_mod.retract[0],
+               Retraction {
+                   low: "v0.1.0".to_owned(),
+                   high: "v0.2.0".to_owned(),
+                   rationale: Some("Broken builds".to_owned()),
+               });
+    assert!(go_mod.retract[0].contains("v0.1.5"));
+    assert!(!go_mod.retract[0].contains("v0.3.0"));
+
+    let rationales = go_mod.retract.iter().map(|r| r.rationale.clone()).collect::<Vec<_>>();
+    assert_eq!(rationales,
+               vec![Some("Broken builds".to_owned()),
+                    Some("Published too early, use v0.3.1".to_owned()),
+                    Some("Leaked credentials".to_owned()),
+                    Some("Wrong module path".to_owned())]);
+    assert_eq!(parse_go_mod("// Unrelated\n\nretract v1.0.0").unwrap().retract[0].rationale,
+               None);
+
+    assert!(parse_go_mod("require (\n  example.com/a v1.0.0\n").is_err());
+    assert!(parse_go_mod("replace example.com/a v1.0.0").is_err());
+    assert!(parse_go_mod("require example.com/a").is_err());
+}
+
+#[test]
+fn test_major_version() {
+    assert_eq!(major_version("github.com/go-redis/redis/v8"),
+               ("github.com/go-redis/redis", Some(8)));
+    assert_eq!(major_version("github.com/gorilla/mux"), ("github.com/gorilla/mux", None));
+    assert_eq!(major_version("example.com/v1"), ("example.com/v1", None));
+    assert_eq!(major_version("example.com/vendor"), ("example.com/vendor", None));
+}
+
+#[test]
+fn test_check_go_mod() {
+    let go_mod = parse_go_mod(include_str!("../fixtures/gomod/go.mod")).unwrap();
+    let report = check(&goproxy::fixture_repo(), &go_mod);
+
+    let findings = report.entries
+        .iter()
+        .map(|e| (e.path.as_ref(), e.findings.clone()))
+        .collect::<Vec<(&str, Vec<GoFinding>)>>();
+    assert_eq!(findings,
+               vec![("github.com/gorilla/mux", vec![GoFinding::Upgrade("v1.7.4".to_owned())]),
+                    ("example.com/retracted",
+                     vec![GoFinding::Retracted(Some("Published too early".to_owned())),
+                          GoFinding::Upgrade("v1.1.0".to_owned()),
+                          GoFinding::MajorVersion("example.com/retracted/v2".to_owned(),
+                                                  "v2.0.0".to_owned())]),
+                    ("example.com/local", vec![GoFinding::Replaced("../local".to_owned())]),
+                    ("example.com/missing",
+                     vec![GoFinding::Unchecked("not found in the module proxy".to_owned())])]);
+
+    assert_eq!(report.summary(),
+               "example.com/app, 5 modules: 2 upgradable, 1 retracted, 1 with a new major \
+                version");
+}
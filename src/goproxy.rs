@@ -0,0 +1,426 @@
+//! Go modules backend.
+//!
+//! Exact module lookups go through a module proxy speaking the GOPROXY
+//! protocol (https://golang.org/ref/mod#goproxy-protocol), search results are
+//! scraped from pkg.go.dev.
+
+use std::fs::File;
+use std::io::{self, Read};
+use hyper::status::StatusCode;
+use hyper::Url;
+use rustc_serialize::json;
+use select::node::Node;
+use select::predicate::{Attr, Class, Name};
+use semver::Version;
+use {SearchRepo, Package, Release};
+use error::{self, Error};
+use http::{HttpClient, WithHttp};
+use scrape::document_from_read;
+
+pub const GOPROXY_URL: &'static str = "https://proxy.golang.org/";
+const PKG_GO_DEV_ENDPOINT: &'static str = "https://pkg.go.dev";
+
+#[derive(RustcDecodable, Debug, Clone)]
+#[allow(non_snake_case)]
+pub struct ModuleInfo {
+    pub Version: String,
+    pub Time: Option<String>,
+}
+
+/// Upper case letters in module paths are escaped as `!` followed by the
+/// lower case letter, so that paths are safe on case insensitive file systems
+pub fn escape_module_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c.is_uppercase() {
+            escaped.push('!');
+            escaped.extend(c.to_lowercase());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Checks the path against the rules of the go command: slash separated
+/// elements of ASCII letters, digits and `-._~`, none of them empty or
+/// starting or ending with a dot. Keeps user input from escaping the proxy
+/// with `..` or an absolute path.
+pub fn is_valid_module_path(path: &str) -> bool {
+    !path.is_empty() && !path.starts_with('-') &&
+    path.split('/').all(|element| {
+        !element.is_empty() && !element.starts_with('.') && !element.ends_with('.') &&
+        element.bytes().all(|b| match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => true,
+            _ => false,
+        })
+    })
+}
+
+/// Orders module versions by semantic versioning rules, the leading `v` is ignored
+pub fn semver(version: &str) -> Option<Version> {
+    Version::parse(version.trim_left_matches('v')).ok()
+}
+
+fn go_directive(go_mod: &str) -> Option<String> {
+    go_mod.lines()
+        .map(|l| l.trim())
+        .find(|l| l.starts_with("go "))
+        .map(|l| l[3..].trim().to_owned())
+}
+
+#[derive(Clone)]
+pub struct GoRepo {
+    proxy: Url,
+    /// pkg.go.dev compatible site used for searches and documentation links
+    site: Url,
+    http: HttpClient,
+}
+
+impl GoRepo {
+    /// Creates a repo using the module proxy at `proxy_url`, `file://` urls
+    /// are read from the local file system like the go command does
+    pub fn new(proxy_url: &str) -> Result<GoRepo, Error> {
+        let mut proxy = match Url::parse(proxy_url) {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                return Err(Error::Config(format!("invalid proxy url {}: {}", proxy_url, e)))
+            }
+        };
+
+        if !proxy.path().ends_with('/') {
+            let path = format!("{}/", proxy.path());
+            proxy.set_path(&path);
+        }
+
+        Ok(GoRepo {
+            proxy: proxy,
+            site: Url::parse(PKG_GO_DEV_ENDPOINT).unwrap(),
+            http: HttpClient::shared(),
+        })
+    }
+
+    /// Searches and links documentation on another pkg.go.dev instance
+    pub fn with_search_endpoint(mut self, site_url: &str) -> Result<GoRepo, Error> {
+        self.site = match Url::parse(site_url) {
+            Ok(site) => site,
+            Err(e) => return Err(Error::Config(format!("invalid search url {}: {}", site_url, e))),
+        };
+
+        Ok(self)
+    }
+
+    fn docs_url(&self, module: &str) -> String {
+        format!("{}/{}", self.site.as_str().trim_right_matches('/'), module)
+    }
+
+    /// Invalid module paths are reported as missing modules
+    fn fetch(&self, module: &str, endpoint: &str) -> Result<Option<String>, Error> {
+        if !is_valid_module_path(module) {
+            return Ok(None);
+        }
+
+        let path = format!("{}/{}", escape_module_path(module), endpoint);
+        let url = match self.proxy.join(&path) {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+
+        let mut data = String::new();
+
+        if url.scheme() == "file" {
+            let file_path = match url.to_file_path() {
+                Ok(file_path) => file_path,
+                Err(_) => return Err(Error::Config(format!("invalid proxy path {}", url))),
+            };
+
+            let mut file = match File::open(file_path) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(Error::from(e)),
+            };
+            try!(file.read_to_string(&mut data));
+        } else {
+            let mut resp = try!(self.http.get(url).send());
+
+            // The proxy answers 410 Gone for modules it refuses to serve
+            if resp.status == StatusCode::NotFound || resp.status == StatusCode::Gone {
+                return Ok(None);
+            }
+            try!(error::check_status(resp.status, "module proxy"));
+            try!(resp.read_to_string(&mut data));
+        }
+
+        Ok(Some(data))
+    }
+
+    /// `@latest` info of the module
+    pub fn latest(&self, module: &str) -> Result<Option<ModuleInfo>, Error> {
+        match try!(self.fetch(module, "@latest")) {
+            Some(data) => json::decode(&data).map(Some).map_err(Error::from),
+            None => Ok(None),
+        }
+    }
+
+    /// All known versions of the module sorted from the oldest to the newest
+    pub fn versions(&self, module: &str) -> Result<Option<Vec<String>>, Error> {
+        let data = match try!(self.fetch(module, "@v/list")) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let mut versions = data.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_owned())
+            .collect::<Vec<String>>();
+        versions.sort_by(|a, b| semver(a).cmp(&semver(b)));
+
+        Ok(Some(versions))
+    }
+
+    pub fn info(&self, module: &str, version: &str) -> Result<Option<ModuleInfo>, Error> {
+        match try!(self.fetch(module, &format!("@v/{}.info", version))) {
+            Some(data) => json::decode(&data).map(Some).map_err(Error::from),
+            None => Ok(None),
+        }
+    }
+
+    /// Contents of the `go.mod` file of the module version
+    pub fn go_mod(&self, module: &str, version: &str) -> Result<Option<String>, Error> {
+        self.fetch(module, &format!("@v/{}.mod", version))
+    }
+
+    fn parse_page<R: Read>(&self, r: R) -> Result<Vec<Package>, Error> {
+        let doc = try!(document_from_read(r));
+
+        match doc.find(Class("SearchResults")).first() {
+            Some(ref n) => Ok(self.parse_packages_table(n)),
+            None => Err(Error::Scrape("Invalid pkg.go.dev page".to_owned())),
+        }
+    }
+
+    fn parse_packages_table<'a>(&self, results: &Node<'a>) -> Vec<Package> {
+        results.find(Class("SearchSnippet"))
+            .iter()
+            .filter_map(|n| self.parse_packages_row(&n))
+            .collect::<Vec<Package>>()
+    }
+
+    fn parse_packages_row<'a>(&self, snippet: &Node<'a>) -> Option<Package> {
+        let mut pkg: Package = Package::empty();
+
+        if let Some(n) = snippet.find(Attr("data-test-id", "snippet-title")).first() {
+            match n.attr("href") {
+                Some(href) => pkg.name = href.trim_matches('/').to_owned(),
+                None => return None,
+            }
+        } else {
+            return None;
+        }
+
+        pkg.documentation = Some(self.docs_url(&pkg.name));
+        if pkg.name.split('/').next().map_or(false, |host| host.contains('.')) {
+            pkg.repository = Some(format!("https://{}", pkg.name));
+        }
+
+        if let Some(n) = snippet.find(Class("SearchSnippet-synopsis")).first() {
+            let synopsis = n.text().trim().to_owned();
+            if !synopsis.is_empty() {
+                pkg.description = Some(synopsis);
+            }
+        }
+
+        if let Some(info) = snippet.find(Class("SearchSnippet-infoLabel")).first() {
+            pkg.version = info.find(Name("strong"))
+                .iter()
+                .map(|n| n.text().trim().to_owned())
+                .find(|t| semver(t).is_some());
+
+            if let Some(n) = info.find(Attr("data-test-id", "snippet-published")).first() {
+                pkg.updated_at = Some(n.text().trim().to_owned());
+            }
+
+            if let Some(n) = info.find(Attr("data-test-id", "snippet-license")).first() {
+                pkg.license = Some(n.text().trim().to_owned());
+            }
+
+            if let Some(n) = info.find(Attr("aria-label", "Go to Imported By")).first() {
+                if let Some(count) = n.find(Name("strong")).first() {
+                    let imported_by = count.text().trim().to_owned();
+                    pkg.attributes.insert("imported by".to_owned(), imported_by);
+                }
+            }
+        }
+
+        if !pkg.name.is_empty() {
+            Some(pkg)
+        } else {
+            None
+        }
+    }
+}
+
+impl WithHttp for GoRepo {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+impl SearchRepo for GoRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let mut endpoint = self.site.clone();
+        endpoint.set_path("/search");
+        endpoint.query_pairs_mut().append_pair("q", query);
+        endpoint.query_pairs_mut().append_pair("m", "module");
+
+        let resp = try!(self.http.get(endpoint).send());
+        try!(error::check_status(resp.status, "pkg.go.dev"));
+
+        self.parse_page(resp)
+    }
+
+    fn lookup(&self, module: &str) -> Result<Option<Package>, Error> {
+        let latest = match try!(self.latest(module)) {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+
+        let mut pkg = Package::empty();
+        pkg.name = module.to_owned();
+        pkg.documentation = Some(self.docs_url(module));
+        if module.split('/').next().map_or(false, |host| host.contains('.')) {
+            pkg.repository = Some(format!("https://{}", module));
+        }
+
+        if let Some(go_mod) = try!(self.go_mod(module, &latest.Version)) {
+            if let Some(go_version) = go_directive(&go_mod) {
+                pkg.attributes.insert("go version".to_owned(), go_version);
+            }
+        }
+
+        pkg.version = Some(latest.Version);
+        pkg.updated_at = latest.Time;
+
+        Ok(Some(pkg))
+    }
+
+    fn releases(&self, module: &str) -> Result<Option<Vec<Release>>, Error> {
+        let versions = try!(self.versions(module));
+        Ok(versions.map(|versions| versions.iter().map(|v| Release::new(v)).collect()))
+    }
+}
+
+#[cfg(test)]
+pub fn fixture_repo() -> GoRepo {
+    let root = ::std::env::current_dir().unwrap().join("fixtures/goproxy");
+    GoRepo::new(Url::from_directory_path(root).unwrap().as_str()).unwrap()
+}
+
+#[test]
+fn test_escape_module_path() {
+    assert_eq!(escape_module_path("github.com/BurntSushi/toml"),
+               "github.com/!burnt!sushi/toml");
+    assert_eq!(escape_module_path("golang.org/x/net"), "golang.org/x/net");
+}
+
+#[test]
+fn test_is_valid_module_path() {
+    for path in &["github.com/gorilla/mux", "golang.org/x/net", "gopkg.in/yaml.v2",
+                  "github.com/BurntSushi/toml", "example.com/a_b~c"] {
+        assert!(is_valid_module_path(path), "{}", path);
+    }
+    for path in &["", "/etc/passwd", "github.com/../../etc", "a//b", "github.com/gorilla/",
+                  "-flag", "github.com/x/.hidden", "github.com/é", "a b", "a?b=c"] {
+        assert!(!is_valid_module_path(path), "{}", path);
+    }
+}
+
+#[test]
+fn test_goproxy_lookup() {
+    let repo = fixture_repo();
+
+    let pkg = repo.lookup("github.com/gorilla/mux").unwrap().unwrap();
+    assert_eq!(pkg.version, Some("v1.8.0".to_owned()));
+    assert_eq!(pkg.updated_at, Some("2020-08-22T03:20:06Z".to_owned()));
+    assert_eq!(pkg.attributes.get("go version"), Some(&"1.12".to_owned()));
+    assert_eq!(pkg.repository, Some("https://github.com/gorilla/mux".to_owned()));
+
+    let toml = repo.lookup("github.com/BurntSushi/toml").unwrap().unwrap();
+    assert_eq!(toml.version, Some("v0.3.1".to_owned()));
+    assert!(toml.attributes.get("go version").is_none());
+
+    assert!(repo.lookup("example.com/missing").unwrap().is_none());
+    assert!(repo.lookup("../goproxy/github.com/gorilla/mux").unwrap().is_none());
+    assert!(repo.lookup("/etc/passwd").unwrap().is_none());
+}
+
+#[test]
+fn test_goproxy_versions() {
+    let repo = fixture_repo();
+
+    let versions = repo.versions("github.com/gorilla/mux").unwrap().unwrap();
+    assert_eq!(versions, vec!["v1.6.0", "v1.6.2", "v1.7.4", "v1.8.0"]);
+
+    let info = repo.info("github.com/gorilla/mux", "v1.8.0").unwrap().unwrap();
+    assert_eq!(info.Version, "v1.8.0");
+}
+
+#[test]
+fn test_pkg_go_dev_parse_page() {
+    let repo = fixture_repo();
+
+    let page: &[u8] = include_bytes!("../fixtures/pkggodev/search.html");
+    let packages = repo.parse_page(page).unwrap();
+    assert_eq!(packages.len(), 2);
+
+    let mux = &packages[0];
+    assert_eq!(mux.name, "github.com/gorilla/mux");
+    assert_eq!(mux.version, Some("v1.8.0".to_owned()));
+    assert_eq!(mux.license, Some("BSD-3-Clause".to_owned()));
+    assert_eq!(mux.updated_at, Some("Aug 22, 2020".to_owned()));
+    assert_eq!(mux.attributes.get("imported by"), Some(&"20,121".to_owned()));
+    assert_eq!(mux.description,
+               Some("Package mux implements a request router and dispatcher.".to_owned()));
+
+    assert!(packages[1].description.is_none());
+    assert!(packages[1].license.is_none());
+
+    let empty: &[u8] = include_bytes!("../fixtures/pkggodev/search_empty.html");
+    assert!(repo.parse_page(empty).unwrap().is_empty());
+
+    let broken: &[u8] = b"<html><body>Bad gateway</body></html>";
+    assert!(repo.parse_page(broken).is_err());
+}
+
+#[test]
+fn test_pkg_go_dev_search() {
+    let repo = fixture_repo().with_http(::replay::fixture_client("fixtures/pkggodev"));
+
+    let packages = repo.search("mux").unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].name, "github.com/gorilla/mux");
+    assert!(repo.search("xqzvq").unwrap().is_empty());
+}
+
+#[test]
+fn test_pkg_go_dev_search_errors() {
+    use std::io::ErrorKind as IoErrorKind;
+    use hyper::method::Method;
+    use error::ErrorKind;
+    use replay::{error_kind, ReplayTransport};
+
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Get,
+                 "https://pkg.go.dev/search?q=mux&m=module",
+                 StatusCode::Ok,
+                 "<html><body>Bad gateway</body></html>")
+        .fail(Method::Get,
+              "https://pkg.go.dev/search?q=chi&m=module",
+              IoErrorKind::ConnectionRefused)
+        .into_client();
+    let repo = fixture_repo().with_http(http);
+
+    assert_eq!(error_kind(repo.search("mux")), Some(ErrorKind::Parse));
+    assert_eq!(error_kind(repo.search("chi")), Some(ErrorKind::Unavailable));
+}
@@ -0,0 +1,363 @@
+//! Repositories scraping search pages, defined in the config file: a search
+//! url template, the selector of the result rows and the fields making up a
+//! package.
+
+use hyper::Url;
+use select::document::Document;
+use select::node::Node;
+use {SearchRepo, Package};
+use config::FieldMapping;
+use error::{self, Error};
+use http::{HttpClient, WithHttp};
+use scrape::{document_from_read, Selector};
+use urltemplate::UrlTemplate;
+
+/// Where a field is found inside a result row, written as `selector`,
+/// `selector@attribute` or `@attribute` for an attribute of the row itself.
+/// Without an attribute the text of the element is used.
+#[derive(Debug, Clone)]
+pub struct FieldSelector {
+    selector: Option<Selector>,
+    attr: Option<String>,
+}
+
+impl FieldSelector {
+    pub fn parse(spec: &str) -> Result<FieldSelector, Error> {
+        let (selector, attr) = match spec.rfind('@') {
+            Some(at) if !spec[at..].contains(']') => (&spec[..at], Some(&spec[at + 1..])),
+            _ => (spec, None),
+        };
+
+        if attr.map_or(false, |a| a.trim().is_empty()) {
+            return Err(Error::Config(format!("invalid field `{}`: the attribute is empty", spec)));
+        }
+
+        Ok(FieldSelector {
+            selector: if selector.trim().is_empty() {
+                None
+            } else {
+                Some(try!(Selector::parse(selector)))
+            },
+            attr: attr.map(|a| a.trim().to_owned()),
+        })
+    }
+
+    fn extract(&self, row: &Node) -> Option<String> {
+        let node = match self.selector {
+            Some(ref selector) => selector.find_first(row),
+            None => Some(*row),
+        };
+
+        let value = match self.attr {
+            Some(ref attr) => node.and_then(|n| n.attr(attr).map(|v| v.trim().to_owned())),
+            None => node.map(|n| n.text().trim().to_owned()),
+        };
+
+        value.and_then(|v| if v.is_empty() { None } else { Some(v) })
+    }
+}
+
+#[derive(Clone)]
+struct HtmlFields {
+    name: FieldSelector,
+    version: Option<FieldSelector>,
+    description: Option<FieldSelector>,
+    repository: Option<FieldSelector>,
+    documentation: Option<FieldSelector>,
+    homepage: Option<FieldSelector>,
+    license: Option<FieldSelector>,
+    downloads: Option<FieldSelector>,
+    keywords: Option<FieldSelector>,
+    updated_at: Option<FieldSelector>,
+    attributes: Vec<(String, FieldSelector)>,
+}
+
+fn parse_field(spec: &Option<String>) -> Result<Option<FieldSelector>, Error> {
+    match *spec {
+        Some(ref spec) => FieldSelector::parse(spec).map(Some),
+        None => Ok(None),
+    }
+}
+
+impl HtmlFields {
+    fn parse(fields: &FieldMapping) -> Result<HtmlFields, Error> {
+        let mut attributes = Vec::new();
+        for (attribute, spec) in &fields.attributes {
+            attributes.push((attribute.clone(), try!(FieldSelector::parse(spec))));
+        }
+
+        Ok(HtmlFields {
+            name: try!(FieldSelector::parse(&fields.name)),
+            version: try!(parse_field(&fields.version)),
+            description: try!(parse_field(&fields.description)),
+            repository: try!(parse_field(&fields.repository)),
+            documentation: try!(parse_field(&fields.documentation)),
+            homepage: try!(parse_field(&fields.homepage)),
+            license: try!(parse_field(&fields.license)),
+            downloads: try!(parse_field(&fields.downloads)),
+            keywords: try!(parse_field(&fields.keywords)),
+            updated_at: try!(parse_field(&fields.updated_at)),
+            attributes: attributes,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct HtmlRepo {
+    name: String,
+    url: UrlTemplate,
+    rows: Selector,
+    no_results: Option<Selector>,
+    fields: HtmlFields,
+    http: HttpClient,
+}
+
+impl HtmlRepo {
+    /// `name` is used in error messages, `rows` selects the results on the
+    /// page and `no_results` the element telling that nothing was found.
+    /// Without it a page without results is reported as a scraping error.
+    pub fn new(name: &str,
+               url_template: &str,
+               rows: &str,
+               no_results: Option<&str>,
+               fields: &FieldMapping)
+               -> Result<HtmlRepo, Error> {
+        if fields.name.is_empty() {
+            return Err(Error::Config(format!("{}: the name field is required", name)));
+        }
+
+        Ok(HtmlRepo {
+            name: name.to_owned(),
+            url: try!(UrlTemplate::parse(name, url_template)),
+            rows: try!(Selector::parse(rows)),
+            no_results: match no_results {
+                Some(selector) => Some(try!(Selector::parse(selector))),
+                None => None,
+            },
+            fields: try!(HtmlFields::parse(fields)),
+            http: HttpClient::shared(),
+        })
+    }
+
+    fn parse_page(&self, document: &Document, page_url: &Url) -> Result<Vec<Package>, Error> {
+        let rows = self.rows.find_all(document);
+
+        if rows.is_empty() {
+            let no_results = self.no_results.as_ref();
+            if no_results.map_or(false, |s| !s.find_all(document).is_empty()) {
+                return Ok(Vec::new());
+            }
+
+            return Err(Error::Scrape(format!("{}: the rows selector `{}` matched nothing",
+                                             self.name,
+                                             self.rows.as_str())));
+        }
+
+        let mut packages = Vec::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            match self.parse_row(row, page_url) {
+                Some(pkg) => packages.push(pkg),
+                None => {
+                    return Err(Error::Scrape(format!("{}: the name field matched nothing in \
+                                                      result {}",
+                                                     self.name,
+                                                     i + 1)))
+                }
+            }
+        }
+
+        Ok(packages)
+    }
+
+    // Rows without a name mean the selectors don't fit the page
+    fn parse_row(&self, row: &Node, page_url: &Url) -> Option<Package> {
+        let field = |selector: &Option<FieldSelector>| {
+            selector.as_ref().and_then(|s| s.extract(row))
+        };
+        // Links are usually relative to the search page
+        let link = |selector: &Option<FieldSelector>| {
+            field(selector).map(|href| match page_url.join(&href) {
+                Ok(url) => url.to_string(),
+                Err(_) => href,
+            })
+        };
+
+        let mut pkg = Package::empty();
+        pkg.name = match self.fields.name.extract(row) {
+            Some(name) => name,
+            None => return None,
+        };
+        pkg.version = field(&self.fields.version);
+        pkg.description = field(&self.fields.description);
+        pkg.repository = link(&self.fields.repository);
+        pkg.documentation = link(&self.fields.documentation);
+        pkg.homepage = link(&self.fields.homepage);
+        pkg.license = field(&self.fields.license);
+        pkg.downloads = field(&self.fields.downloads).and_then(|d| {
+            d.chars().filter(|c| c.is_digit(10)).collect::<String>().parse().ok()
+        });
+        pkg.keywords = field(&self.fields.keywords)
+            .map(|keywords| {
+                keywords.split(',')
+                    .map(|k| k.trim())
+                    .filter(|k| !k.is_empty())
+                    .map(|k| k.to_owned())
+                    .collect()
+            })
+            .unwrap_or(Vec::new());
+        pkg.updated_at = field(&self.fields.updated_at);
+
+        for &(ref attribute, ref selector) in &self.fields.attributes {
+            if let Some(value) = selector.extract(row) {
+                pkg.attributes.insert(attribute.clone(), value);
+            }
+        }
+
+        Some(pkg)
+    }
+}
+
+impl WithHttp for HtmlRepo {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+impl SearchRepo for HtmlRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let url = try!(self.url.expand(query));
+
+        let resp = try!(self.http.get(url.clone()).send());
+        try!(error::check_status(resp.status, &self.name));
+
+        let document = try!(document_from_read(resp));
+        self.parse_page(&document, &url)
+    }
+}
+
+#[cfg(test)]
+fn fixture_repo(rows: &str) -> HtmlRepo {
+    let mut fields = FieldMapping::default();
+    fields.name = "a.package-name".to_owned();
+    fields.version = Some(".version".to_owned());
+    fields.description = Some("p.summary".to_owned());
+    fields.documentation = Some("a.package-name@href".to_owned());
+    fields.repository = Some("a[data-kind=\"source\"]@href".to_owned());
+    fields.downloads = Some(".downloads".to_owned());
+    fields.keywords = Some(".tags".to_owned());
+    fields.updated_at = Some("time@datetime".to_owned());
+    fields.attributes.insert("team".to_owned(), "@data-team".to_owned());
+
+    HtmlRepo::new("portal",
+                  "https://packages.example.com/search?q={query}",
+                  rows,
+                  Some("div.empty-state"),
+                  &fields)
+        .unwrap()
+}
+
+#[cfg(test)]
+fn fixture_page(name: &str) -> Document {
+    let path = format!("fixtures/htmlrepo/{}", name);
+    document_from_read(::std::fs::File::open(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_html_parse_page() {
+    let repo = fixture_repo("table#results tbody tr.result");
+    let url = Url::parse("https://packages.example.com/search?q=auth").unwrap();
+    let packages = repo.parse_page(&fixture_page("search.html"), &url).unwrap();
+    assert_eq!(packages.len(), 2);
+
+    let auth = &packages[0];
+    assert_eq!(auth.name, "auth-client");
+    assert_eq!(auth.version, Some("3.2.0".to_owned()));
+    assert_eq!(auth.description,
+               Some("Client for the internal authentication service".to_owned()));
+    assert_eq!(auth.documentation,
+               Some("https://packages.example.com/p/auth-client".to_owned()));
+    assert_eq!(auth.repository,
+               Some("https://git.example.com/platform/auth-client".to_owned()));
+    assert_eq!(auth.downloads, Some(12408));
+    assert_eq!(auth.keywords, vec!["auth".to_owned(), "oauth2".to_owned()]);
+    assert_eq!(auth.updated_at, Some("2024-02-11".to_owned()));
+    assert_eq!(auth.attributes.get("team"), Some(&"platform".to_owned()));
+
+    let tokens = &packages[1];
+    assert_eq!(tokens.name, "auth-tokens");
+    assert!(tokens.description.is_none());
+    assert!(tokens.repository.is_none());
+    assert!(tokens.keywords.is_empty());
+    assert!(tokens.attributes.is_empty());
+}
+
+#[test]
+fn test_html_parse_empty_page() {
+    let repo = fixture_repo("table#results tbody tr.result");
+    let url = Url::parse("https://packages.example.com/search?q=nothing").unwrap();
+
+    let packages = repo.parse_page(&fixture_page("search_empty.html"), &url).unwrap();
+    assert!(packages.is_empty());
+}
+
+#[test]
+fn test_html_selector_mismatch() {
+    let url = Url::parse("https://packages.example.com/search?q=auth").unwrap();
+
+    let repo = fixture_repo("table.packages tr");
+    match repo.parse_page(&fixture_page("search.html"), &url) {
+        Err(Error::Scrape(msg)) => {
+            assert_eq!(msg, "portal: the rows selector `table.packages tr` matched nothing")
+        }
+        _ => panic!("the rows selector doesn't match the page"),
+    }
+
+    let repo = fixture_repo("table#results tr");
+    match repo.parse_page(&fixture_page("search.html"), &url) {
+        Err(Error::Scrape(msg)) => {
+            assert_eq!(msg, "portal: the name field matched nothing in result 1")
+        }
+        _ => panic!("the header row has no name"),
+    }
+}
+
+#[test]
+fn test_html_search() {
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use error::ErrorKind;
+    use replay::{error_kind, fixture_client, ReplayTransport};
+
+    let repo = fixture_repo("table#results tbody tr.result");
+
+    let recorded = repo.clone().with_http(fixture_client("fixtures/htmlrepo"));
+    let packages = recorded.search("auth").unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].documentation,
+               Some("https://packages.example.com/p/auth-client".to_owned()));
+    assert!(recorded.search("nothing").unwrap().is_empty());
+
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Get,
+                 "https://packages.example.com/search?q=auth",
+                 StatusCode::Ok,
+                 "<html><body><h1>Please log in</h1></body></html>")
+        .respond(Method::Get,
+                 "https://packages.example.com/search?q=tokens",
+                 StatusCode::ServiceUnavailable,
+                 "<html><body>Down for maintenance</body></html>")
+        .into_client();
+    let repo = repo.with_http(http);
+
+    assert_eq!(error_kind(repo.search("auth")), Some(ErrorKind::Parse));
+    assert_eq!(error_kind(repo.search("tokens")), Some(ErrorKind::Unavailable));
+}
+
+#[test]
+fn test_field_selector() {
+    assert!(FieldSelector::parse("a.title@href").unwrap().selector.is_some());
+    assert!(FieldSelector::parse("@data-name").unwrap().selector.is_none());
+    assert_eq!(FieldSelector::parse("a[title=\"a@b\"]").unwrap().attr, None);
+    assert!(FieldSelector::parse("a.title@").is_err());
+    assert!(FieldSelector::parse("a..title").is_err());
+}
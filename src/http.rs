@@ -0,0 +1,235 @@
+//! HTTP client shared by the registries, the index, the analytics and the
+//! Telegram file API, so that connections are kept alive between requests
+//! instead of paying a TLS handshake on every query.
+//!
+//! Requests go through a `Transport`, tests swap the network for recorded
+//! responses, see the `replay` module.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use hyper;
+use hyper::client::{Client, IntoUrl};
+use hyper::client::pool::{self, Pool};
+use hyper::header::{Header, HeaderFormat, Headers, UserAgent};
+use hyper::method::Method;
+use hyper::net::{HttpsConnector, OpensslClient};
+use hyper::status::StatusCode;
+use hyper::Url;
+use url::ParseError as UrlError;
+
+/// crates.io rejects requests without a User-Agent, deployments should add
+/// a way to contact them in the config file
+pub const DEFAULT_USER_AGENT: &'static str = concat!("packagesbot/", env!("CARGO_PKG_VERSION"));
+
+lazy_static!(
+    static ref SHARED: RwLock<HttpClient> = RwLock::new(HttpClient::new(&HttpConfig::default()));
+);
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    pub connect_timeout: Duration,
+    /// Applies to every read and write on a connection, not whole requests
+    pub read_timeout: Duration,
+    /// Idle connections kept open per host
+    pub max_idle_connections: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> HttpConfig {
+        HttpConfig {
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            max_idle_connections: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+/// Status and body of a response, the body is read as it arrives
+pub struct HttpResponse {
+    pub status: StatusCode,
+    body: Box<Read + Send>,
+}
+
+impl HttpResponse {
+    pub fn new<R: Read + Send + 'static>(status: StatusCode, body: R) -> HttpResponse {
+        HttpResponse {
+            status: status,
+            body: Box::new(body),
+        }
+    }
+}
+
+impl Read for HttpResponse {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// Sends the requests of an `HttpClient`
+pub trait Transport: Send + Sync {
+    fn send(&self, request: &HttpRequest) -> hyper::Result<HttpResponse>;
+}
+
+// Tries every address of the host in turn like `TcpStream::connect` does
+fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err = None;
+
+    for addr in try!((host, port).to_socket_addrs()) {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("{} has no addresses", host))))
+}
+
+/// Talks to the network over keep-alive connections
+pub struct HyperTransport {
+    client: Client,
+}
+
+impl HyperTransport {
+    pub fn new(config: &HttpConfig) -> HyperTransport {
+        let connect_timeout = config.connect_timeout;
+        let connector = move |host: &str, port: u16, _scheme: &str| {
+            connect(host, port, connect_timeout)
+        };
+        let pool = Pool::with_connector(pool::Config { max_idle: config.max_idle_connections },
+                                        HttpsConnector::with_connector(OpensslClient::default(),
+                                                                       connector));
+
+        let mut client = Client::with_connector(pool);
+        client.set_read_timeout(Some(config.read_timeout));
+        client.set_write_timeout(Some(config.read_timeout));
+
+        HyperTransport { client: client }
+    }
+}
+
+impl Transport for HyperTransport {
+    fn send(&self, request: &HttpRequest) -> hyper::Result<HttpResponse> {
+        let resp = try!(self.client
+            .request(request.method.clone(), request.url.clone())
+            .headers(request.headers.clone())
+            .body(&request.body[..])
+            .send());
+
+        Ok(HttpResponse::new(resp.status, resp))
+    }
+}
+
+/// Client adding the User-Agent to every request, clones share the
+/// transport and its connections
+#[derive(Clone)]
+pub struct HttpClient {
+    transport: Arc<Transport>,
+    user_agent: String,
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpConfig) -> HttpClient {
+        HttpClient::with_transport(Arc::new(HyperTransport::new(config)), &config.user_agent)
+    }
+
+    pub fn with_transport(transport: Arc<Transport>, user_agent: &str) -> HttpClient {
+        HttpClient {
+            transport: transport,
+            user_agent: user_agent.to_owned(),
+        }
+    }
+
+    /// The client configured at startup
+    pub fn shared() -> HttpClient {
+        SHARED.read().unwrap().clone()
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::Get, url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(Method::Post, url)
+    }
+
+    fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        let mut headers = Headers::new();
+        headers.set(UserAgent(self.user_agent.clone()));
+
+        RequestBuilder {
+            transport: &*self.transport,
+            method: method,
+            url: url.into_url(),
+            headers: headers,
+            body: Vec::new(),
+        }
+    }
+}
+
+/// Sends its requests through a replaceable client, tests swap in one
+/// answering from recorded responses
+pub trait WithHttp: Sized {
+    fn http_mut(&mut self) -> &mut HttpClient;
+
+    fn with_http(mut self, http: HttpClient) -> Self {
+        *self.http_mut() = http;
+        self
+    }
+}
+
+/// A request being built, invalid urls are reported by `send`
+pub struct RequestBuilder<'a> {
+    transport: &'a Transport,
+    method: Method,
+    url: Result<Url, UrlError>,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl<'a> RequestBuilder<'a> {
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> RequestBuilder<'a> {
+        self.headers.set(header);
+        self
+    }
+
+    pub fn body<B: AsRef<[u8]>>(mut self, body: B) -> RequestBuilder<'a> {
+        self.body = body.as_ref().to_vec();
+        self
+    }
+
+    pub fn send(self) -> hyper::Result<HttpResponse> {
+        let request = HttpRequest {
+            method: self.method,
+            url: try!(self.url.map_err(hyper::Error::Uri)),
+            headers: self.headers,
+            body: self.body,
+        };
+
+        self.transport.send(&request)
+    }
+}
+
+impl fmt::Debug for HttpClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpClient").field("user_agent", &self.user_agent).finish()
+    }
+}
+
+/// Replaces the shared client, repositories created afterwards use the new one
+pub fn configure(config: &HttpConfig) {
+    *SHARED.write().unwrap() = HttpClient::new(config);
+}
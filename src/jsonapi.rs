@@ -0,0 +1,249 @@
+//! Repositories defined in the config file: a search url template, the
+//! path to the results array and the fields making up a package.
+
+use std::io::Read;
+use rustc_serialize::json::{DecoderError, Json};
+use {SearchRepo, Package};
+use config::FieldMapping;
+use error::{self, Error};
+use http::{HttpClient, WithHttp};
+use urltemplate::UrlTemplate;
+
+#[derive(Clone)]
+pub struct JsonApiRepo {
+    name: String,
+    url: UrlTemplate,
+    results: Vec<String>,
+    fields: FieldMapping,
+    http: HttpClient,
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split('.').filter(|s| !s.is_empty()).map(|s| s.to_owned()).collect()
+}
+
+fn find<'a>(json: &'a Json, path: &[String]) -> Option<&'a Json> {
+    path.iter().fold(Some(json), |value, key| {
+        value.and_then(|value| match *value {
+            Json::Object(ref object) => object.get(key),
+            Json::Array(ref array) => key.parse::<usize>().ok().and_then(|i| array.get(i)),
+            _ => None,
+        })
+    })
+}
+
+fn scalar(json: &Json) -> Option<String> {
+    match *json {
+        Json::String(ref s) if !s.is_empty() => Some(s.clone()),
+        Json::I64(n) => Some(n.to_string()),
+        Json::U64(n) => Some(n.to_string()),
+        Json::F64(n) => Some(n.to_string()),
+        Json::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn field(json: &Json, path: Option<&String>) -> Option<String> {
+    path.and_then(|path| find(json, &split_path(path))).and_then(scalar)
+}
+
+impl JsonApiRepo {
+    /// `name` is used in error messages and `results` is the path to the
+    /// array of results, empty when the response itself is the array
+    pub fn new(name: &str,
+               url_template: &str,
+               results: &str,
+               fields: FieldMapping)
+               -> Result<JsonApiRepo, Error> {
+        let url = try!(UrlTemplate::parse(name, url_template));
+
+        if fields.name.is_empty() {
+            return Err(Error::Config(format!("{}: the name field is required", name)));
+        }
+
+        Ok(JsonApiRepo {
+            name: name.to_owned(),
+            url: url,
+            results: split_path(results),
+            fields: fields,
+            http: HttpClient::shared(),
+        })
+    }
+
+    fn parse_response(&self, data: &str) -> Result<Vec<Package>, Error> {
+        let body = try!(Json::from_str(data).map_err(DecoderError::ParseError));
+
+        let results = match find(&body, &self.results).and_then(|r| r.as_array()) {
+            Some(results) => results,
+            None => {
+                let path = self.results.join(".");
+                return Err(Error::from(DecoderError::MissingFieldError(path)));
+            }
+        };
+
+        Ok(results.iter().filter_map(|result| self.to_package(result)).collect())
+    }
+
+    // Results without a name are skipped
+    fn to_package(&self, result: &Json) -> Option<Package> {
+        let mut pkg = Package::empty();
+
+        pkg.name = match field(result, Some(&self.fields.name)) {
+            Some(name) => name,
+            None => return None,
+        };
+        pkg.version = field(result, self.fields.version.as_ref());
+        pkg.description = field(result, self.fields.description.as_ref());
+        pkg.repository = field(result, self.fields.repository.as_ref());
+        pkg.documentation = field(result, self.fields.documentation.as_ref());
+        pkg.homepage = field(result, self.fields.homepage.as_ref());
+        pkg.license = field(result, self.fields.license.as_ref());
+        pkg.downloads = field(result, self.fields.downloads.as_ref()).and_then(|d| d.parse().ok());
+        pkg.updated_at = field(result, self.fields.updated_at.as_ref());
+
+        let keywords = self.fields.keywords.as_ref().and_then(|p| find(result, &split_path(p)));
+        pkg.keywords = match keywords {
+            Some(&Json::Array(ref keywords)) => keywords.iter().filter_map(scalar).collect(),
+            Some(&Json::String(ref keywords)) => {
+                keywords.split(',')
+                    .map(|k| k.trim())
+                    .filter(|k| !k.is_empty())
+                    .map(|k| k.to_owned())
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        for (attribute, path) in &self.fields.attributes {
+            if let Some(value) = field(result, Some(path)) {
+                pkg.attributes.insert(attribute.clone(), value);
+            }
+        }
+
+        Some(pkg)
+    }
+}
+
+impl WithHttp for JsonApiRepo {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+impl SearchRepo for JsonApiRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let url = try!(self.url.expand(query));
+        let mut resp = try!(self.http.get(url).send());
+        try!(error::check_status(resp.status, &self.name));
+
+        let mut data = String::new();
+        try!(resp.read_to_string(&mut data));
+
+        self.parse_response(&data)
+    }
+}
+
+#[cfg(test)]
+fn fixture_fields() -> FieldMapping {
+    let mut attributes = ::std::collections::BTreeMap::new();
+    attributes.insert("maintainer".to_owned(), "maintainers.0.name".to_owned());
+
+    FieldMapping {
+        name: "name".to_owned(),
+        version: Some("latest.version".to_owned()),
+        description: Some("summary".to_owned()),
+        repository: Some("links.source".to_owned()),
+        license: Some("license".to_owned()),
+        downloads: Some("stats.downloads".to_owned()),
+        keywords: Some("tags".to_owned()),
+        attributes: attributes,
+        ..FieldMapping::default()
+    }
+}
+
+#[test]
+fn test_jsonapi_parse_response() {
+    let repo = JsonApiRepo::new("hex",
+                                "https://packages.example.com/api/search?q={query}",
+                                "data.packages",
+                                fixture_fields())
+        .unwrap();
+    let packages = repo.parse_response(include_str!("../fixtures/jsonapi/search.json")).unwrap();
+    assert_eq!(packages.len(), 2);
+
+    let plug = &packages[0];
+    assert_eq!(plug.name, "plug");
+    assert_eq!(plug.version, Some("1.14.2".to_owned()));
+    assert_eq!(plug.description,
+               Some("Compose web applications with functions".to_owned()));
+    assert_eq!(plug.repository, Some("https://github.com/elixir-plug/plug".to_owned()));
+    assert_eq!(plug.license, Some("Apache-2.0".to_owned()));
+    assert_eq!(plug.downloads, Some(154300021));
+    assert_eq!(plug.keywords, vec!["http".to_owned(), "web".to_owned()]);
+    assert_eq!(plug.attributes.get("maintainer"), Some(&"José Valim".to_owned()));
+
+    let cowboy = &packages[1];
+    assert_eq!(cowboy.name, "plug_cowboy");
+    assert!(cowboy.description.is_none());
+    assert!(cowboy.repository.is_none());
+    assert_eq!(cowboy.keywords, vec!["http".to_owned(), "cowboy".to_owned()]);
+    assert!(cowboy.attributes.is_empty());
+}
+
+#[test]
+fn test_jsonapi_invalid_response() {
+    let repo = JsonApiRepo::new("hex", "https://example.com/?q={query}", "items", fixture_fields())
+        .unwrap();
+
+    match repo.parse_response(include_str!("../fixtures/jsonapi/search.json")) {
+        Err(err) => assert_eq!(err.kind(), ::error::ErrorKind::Parse),
+        Ok(_) => panic!("the response has no items"),
+    }
+}
+
+#[test]
+fn test_jsonapi_search() {
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use error::ErrorKind;
+    use replay::{error_kind, fixture_client, ReplayTransport};
+
+    let repo = JsonApiRepo::new("hex",
+                                "https://packages.example.com/api/search?q={query}",
+                                "data.packages",
+                                fixture_fields())
+        .unwrap();
+
+    let recorded = repo.clone().with_http(fixture_client("fixtures/jsonapi"));
+    let packages = recorded.search("plug").unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].name, "plug");
+
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Get,
+                 "https://packages.example.com/api/search?q=cowboy",
+                 StatusCode::InternalServerError,
+                 "")
+        .respond(Method::Get,
+                 "https://packages.example.com/api/search?q=ecto",
+                 StatusCode::Ok,
+                 "{\"data\": {\"packages\": [")
+        .respond(Method::Get,
+                 "https://packages.example.com/api/search?q=phoenix",
+                 StatusCode::Ok,
+                 "{\"data\": {\"packages\": {}}}")
+        .into_client();
+    let repo = repo.with_http(http);
+
+    assert_eq!(error_kind(repo.search("cowboy")), Some(ErrorKind::Unavailable));
+    assert_eq!(error_kind(repo.search("ecto")), Some(ErrorKind::Parse));
+    assert_eq!(error_kind(repo.search("phoenix")), Some(ErrorKind::Parse));
+}
+
+#[test]
+fn test_jsonapi_invalid_definition() {
+    assert!(JsonApiRepo::new("hex", "https://example.com/search", "", fixture_fields()).is_err());
+    assert!(JsonApiRepo::new("hex", "example.com/?q={query}", "", fixture_fields()).is_err());
+    assert!(JsonApiRepo::new("hex", "https://example.com/?q={query}", "", FieldMapping::default())
+        .is_err());
+}
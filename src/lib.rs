@@ -1,29 +1,79 @@
 #[macro_use]
 extern crate log;
+extern crate chan_signal;
+extern crate csv;
 extern crate env_logger;
+extern crate flate2;
 extern crate hyper;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate quick_error;
 extern crate regex;
+extern crate rusqlite;
 extern crate rustc_serialize;
 extern crate select;
+extern crate semver;
+extern crate tar;
 extern crate telegram_bot;
 extern crate tendril;
 extern crate threadpool;
+extern crate toml;
 extern crate url;
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use chan_signal::Signal;
+
+mod audit;
+mod cache;
+mod cargoindex;
 mod crates;
+mod config;
+mod cratesdump;
 mod error;
-mod godoc;
+mod fallback;
+mod gomod;
+mod goproxy;
+mod htmlrepo;
+mod http;
+mod jsonapi;
+mod npm;
+mod pypi;
+#[cfg(test)]
+mod replay;
+mod rustsec;
+mod scrape;
+mod state;
 mod telegram;
+mod urltemplate;
+
+pub use cache::CacheStats;
+pub use config::Config;
+use config::{EcosystemConfig, EcosystemKind};
+pub use crates::Registry;
+pub use http::HttpConfig;
+pub use state::StateConfig;
+pub use telegram::analytics::AnalyticsConfig;
+pub use telegram::bot::ListenMode;
+pub use telegram::webhook::{watch_signals, WebhookConfig};
 
+#[derive(Clone)]
 pub struct Package {
     pub name: String,
     pub repository: Option<String>,
     pub documentation: Option<String>,
     pub description: Option<String>,
+    pub version: Option<String>,
+    pub license: Option<String>,
+    pub downloads: Option<u64>,
+    pub homepage: Option<String>,
+    pub keywords: Vec<String>,
+    pub updated_at: Option<String>,
+    /// Ecosystem specific details, e.g. an import path or a minimal compiler version
+    pub attributes: BTreeMap<String, String>,
 }
 
 impl Package {
@@ -33,12 +83,57 @@ impl Package {
             repository: None,
             documentation: None,
             description: None,
+            version: None,
+            license: None,
+            downloads: None,
+            homepage: None,
+            keywords: Vec::new(),
+            updated_at: None,
+            attributes: BTreeMap::new(),
+        }
+    }
+}
+
+/// A single published version of a package
+#[derive(Clone)]
+pub struct Release {
+    pub version: String,
+    pub yanked: bool,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Release {
+    fn new(version: &str) -> Release {
+        Release {
+            version: version.to_owned(),
+            yanked: false,
+            attributes: BTreeMap::new(),
         }
     }
 }
 
 pub trait SearchRepo: SearchRepoClone + Send {
     fn search(&self, query: &str) -> Result<Vec<Package>, error::Error>;
+
+    /// Looks up a single package by its exact name.
+    ///
+    /// Repositories without a dedicated endpoint fall back to picking the
+    /// exact match out of the search results.
+    fn lookup(&self, name: &str) -> Result<Option<Package>, error::Error> {
+        let packages = try!(self.search(name));
+        Ok(packages.into_iter().find(|pkg| pkg.name == name))
+    }
+
+    /// Lists every published version of the package in publication order,
+    /// `None` means the package is unknown or the repository has no version data.
+    fn releases(&self, _name: &str) -> Result<Option<Vec<Release>>, error::Error> {
+        Ok(None)
+    }
+
+    /// Hit and miss counters of a caching repository
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
 }
 
 pub trait SearchRepoClone {
@@ -59,10 +154,182 @@ impl Clone for Box<SearchRepo> {
     }
 }
 
-pub fn main(telegram_token: &str, botanio_token: &str) {
-    let repos: Vec<(&str, Box<SearchRepo>)> = vec![("rust", Box::new(crates::CratesRepo {})),
-                                                   ("go", Box::new(godoc::GodocRepo {}))];
+/// Search repository registered under a bot command
+pub struct Ecosystem {
+    pub command: String,
+    /// Command the aliases of an ecosystem share, watches are kept under it
+    pub canonical: String,
+    pub repo: Box<SearchRepo>,
+    /// Chats that may use the command, `None` makes it available everywhere
+    pub allowed_chats: Option<Vec<i64>>,
+}
+
+impl Ecosystem {
+    pub fn public(command: &str, repo: Box<SearchRepo>) -> Ecosystem {
+        Ecosystem {
+            command: command.to_owned(),
+            canonical: command.to_owned(),
+            repo: repo,
+            allowed_chats: None,
+        }
+    }
+
+    pub fn private(command: &str, repo: Box<SearchRepo>, allowed_chats: Vec<i64>) -> Ecosystem {
+        Ecosystem {
+            command: command.to_owned(),
+            canonical: command.to_owned(),
+            repo: repo,
+            allowed_chats: Some(allowed_chats),
+        }
+    }
+}
+
+fn cached(repo: Box<SearchRepo>,
+          ttl: Option<Duration>,
+          settings: &config::CacheSettings)
+          -> Box<SearchRepo> {
+    match ttl {
+        Some(ttl) => {
+            let config = cache::CacheConfig {
+                capacity: settings.capacity,
+                ttl: ttl,
+                stale_ttl: settings.stale_ttl,
+            };
+            Box::new(cache::CachedRepo::new(repo, config))
+        }
+        None => repo,
+    }
+}
+
+fn crates_repo(ecosystem: &EcosystemConfig,
+               crates_dump: &Option<config::CratesDumpConfig>)
+               -> Result<Box<SearchRepo>, error::Error> {
+    if ecosystem.endpoint.is_none() && ecosystem.index.is_none() {
+        let crates_io = crates::CratesRepo::crates_io();
+
+        return Ok(match *crates_dump {
+            Some(ref dump) => {
+                let dump_repo = cratesdump::DumpRepo::new();
+                dump_repo.refresh_every(&dump.url, dump.path.clone(), dump.refresh);
+                Box::new(fallback::FallbackRepo::new(Box::new(crates_io), Box::new(dump_repo)))
+            }
+            None => Box::new(crates_io),
+        });
+    }
+
+    let mut registry = Registry::crates_io();
+    if let Some(ref endpoint) = ecosystem.endpoint {
+        registry.api_url = endpoint.clone();
+    }
+    if let Some(ref index) = ecosystem.index {
+        registry.index_url = index.clone();
+    }
+    registry.token = ecosystem.token.clone();
+
+    Ok(Box::new(try!(crates::CratesRepo::new(registry))))
+}
+
+fn go_repo(ecosystem: &EcosystemConfig) -> Result<goproxy::GoRepo, error::Error> {
+    let repo = try!(goproxy::GoRepo::new(ecosystem.endpoint
+        .as_ref()
+        .map_or(goproxy::GOPROXY_URL, |e| e.as_str())));
+
+    match ecosystem.search_endpoint {
+        Some(ref search_endpoint) => repo.with_search_endpoint(search_endpoint),
+        None => Ok(repo),
+    }
+}
+
+fn ecosystem_repo(ecosystem: &EcosystemConfig,
+                  crates_dump: &Option<config::CratesDumpConfig>)
+                  -> Result<Box<SearchRepo>, error::Error> {
+    Ok(match ecosystem.kind {
+        EcosystemKind::Crates => try!(crates_repo(ecosystem, crates_dump)),
+        EcosystemKind::Go => Box::new(try!(go_repo(ecosystem))),
+        EcosystemKind::Npm => {
+            match ecosystem.endpoint {
+                Some(ref endpoint) => Box::new(try!(npm::NpmRepo::new(endpoint))),
+                None => Box::new(npm::NpmRepo::npmjs()),
+            }
+        }
+        EcosystemKind::Pypi => {
+            match ecosystem.endpoint {
+                Some(ref endpoint) => Box::new(try!(pypi::PypiRepo::new(endpoint))),
+                None => Box::new(pypi::PypiRepo::pypi_org()),
+            }
+        }
+        EcosystemKind::Json => {
+            let endpoint = ecosystem.endpoint.as_ref().map_or("", |e| e.as_str());
+            let results = ecosystem.results.as_ref().map_or("", |r| r.as_str());
+            let fields = ecosystem.fields.clone().unwrap_or(config::FieldMapping::default());
+            Box::new(try!(jsonapi::JsonApiRepo::new(&ecosystem.command, endpoint, results, fields)))
+        }
+        EcosystemKind::Html => {
+            let endpoint = ecosystem.endpoint.as_ref().map_or("", |e| e.as_str());
+            let rows = ecosystem.results.as_ref().map_or("", |r| r.as_str());
+            let no_results = ecosystem.no_results.as_ref().map(|s| s.as_str());
+            let fields = ecosystem.fields.clone().unwrap_or(config::FieldMapping::default());
+            Box::new(try!(htmlrepo::HtmlRepo::new(&ecosystem.command,
+                                                  endpoint,
+                                                  rows,
+                                                  no_results,
+                                                  &fields)))
+        }
+    })
+}
+
+/// Runs the bot until SIGINT or SIGTERM, `signals` comes from
+/// `watch_signals` called before any thread was started
+pub fn main(config: Config, signals: Receiver<Signal>) -> Result<(), error::Error> {
+    // Before any repository takes a handle to the shared client
+    http::configure(&config.http);
+
+    let mut ecosystems = Vec::new();
+    for ecosystem in &config.ecosystems {
+        // Aliases share a single cache
+        let repo = cached(try!(ecosystem_repo(ecosystem, &config.crates_dump)),
+                          ecosystem.cache_ttl,
+                          &config.cache);
+
+        for command in ecosystem.commands() {
+            ecosystems.push(Ecosystem {
+                command: command.to_owned(),
+                canonical: ecosystem.command.clone(),
+                repo: repo.clone(),
+                allowed_chats: ecosystem.allowed_chats.clone(),
+            });
+        }
+    }
+
+    // Go audits use the proxy of the first public go ecosystem, audits are
+    // available in every chat and mustn't reach private proxies
+    let go_proxy = match config.ecosystems
+        .iter()
+        .find(|e| e.kind == EcosystemKind::Go && e.allowed_chats.is_none()) {
+        Some(ecosystem) => try!(go_repo(ecosystem)),
+        None => try!(goproxy::GoRepo::new(goproxy::GOPROXY_URL)),
+    };
+
+    // Audits still report outdated and yanked crates without advisories
+    let advisories = config.advisory_db.and_then(|path| match rustsec::AdvisoryDb::load(&path) {
+        Ok(db) => {
+            info!("loaded {} advisories from {}", db.len(), path.display());
+            Some(Arc::new(db))
+        }
+        Err(err) => {
+            error!("failed to load advisories from {}: {:?}", path.display(), err);
+            None
+        }
+    });
+    let auditor = audit::Auditor::new(cargoindex::SparseIndex::crates_io(), advisories, go_proxy);
 
-    let bot = telegram::bot::PkgsBot::new(telegram_token, botanio_token, repos).unwrap();
-    bot.run();
+    let analytics = try!(config.analytics.open());
+    let state = try!(config.state.open());
+    let bot = try!(telegram::bot::PkgsBot::new(&config.telegram_token,
+                                               config.bot,
+                                               analytics,
+                                               state,
+                                               ecosystems,
+                                               auditor));
+    bot.run(&config.listen_mode, signals)
 }
@@ -0,0 +1,190 @@
+use std::io::Read;
+use hyper::Url;
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, DecoderError, Json};
+use {SearchRepo, Package};
+use error::{self, Error};
+use http::{HttpClient, WithHttp};
+
+const NPM_SEARCH_ENDPOINT: &'static str = "https://registry.npmjs.org/-/v1/search";
+const NPM_SEARCH_SIZE: &'static str = "50";
+
+#[derive(RustcDecodable, Clone)]
+pub struct NpmPackage {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub keywords: Option<Vec<String>>,
+    pub date: Option<String>,
+    pub links: NpmLinks,
+}
+
+#[derive(RustcDecodable, Clone)]
+pub struct NpmLinks {
+    pub npm: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub bugs: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct NpmRepo {
+    endpoint: Url,
+    http: HttpClient,
+}
+
+impl NpmRepo {
+    /// Creates a repo using the search endpoint of a registry implementing
+    /// the `/-/v1/search` API
+    pub fn new(endpoint: &str) -> Result<NpmRepo, Error> {
+        match Url::parse(endpoint) {
+            Ok(url) => {
+                Ok(NpmRepo {
+                    endpoint: url,
+                    http: HttpClient::shared(),
+                })
+            }
+            Err(e) => Err(Error::Config(format!("invalid npm endpoint {}: {}", endpoint, e))),
+        }
+    }
+
+    pub fn npmjs() -> NpmRepo {
+        NpmRepo::new(NPM_SEARCH_ENDPOINT).unwrap()
+    }
+
+    // Search objects are decoded by hand because the score is stored under
+    // the `final` key which is a reserved word.
+    fn parse_response(&self, data: &str) -> Result<Vec<Package>, Error> {
+        let body = try!(Json::from_str(data).map_err(DecoderError::ParseError));
+
+        let objects = match body.find("objects").and_then(|o| o.as_array()) {
+            Some(objects) => objects,
+            None => return Err(Error::from(DecoderError::MissingFieldError("objects".to_owned()))),
+        };
+
+        let mut packages = Vec::with_capacity(objects.len());
+        for object in objects {
+            let npm_pkg = match object.find("package") {
+                Some(p) => {
+                    let mut decoder = json::Decoder::new(p.clone());
+                    let npm_pkg: NpmPackage = try!(Decodable::decode(&mut decoder));
+                    npm_pkg
+                }
+                None => continue,
+            };
+
+            let score = object.find_path(&["score", "final"]).and_then(|s| s.as_f64());
+            packages.push(self.to_package(npm_pkg, score));
+        }
+
+        Ok(packages)
+    }
+
+    fn to_package(&self, npm_pkg: NpmPackage, score: Option<f64>) -> Package {
+        let mut pkg = Package::empty();
+
+        pkg.name = npm_pkg.name;
+        pkg.version = Some(npm_pkg.version);
+        pkg.description = npm_pkg.description;
+        pkg.keywords = npm_pkg.keywords.unwrap_or(Vec::new());
+        pkg.updated_at = npm_pkg.date;
+        pkg.repository = npm_pkg.links.repository;
+        pkg.homepage = npm_pkg.links.homepage;
+        pkg.documentation = npm_pkg.links.npm;
+
+        if let Some(score) = score {
+            pkg.attributes.insert("score".to_owned(), format!("{:.2}", score));
+        }
+
+        pkg
+    }
+}
+
+impl WithHttp for NpmRepo {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+impl SearchRepo for NpmRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let mut endpoint = self.endpoint.clone();
+        endpoint.query_pairs_mut().append_pair("text", query);
+        endpoint.query_pairs_mut().append_pair("size", NPM_SEARCH_SIZE);
+
+        let mut resp = try!(self.http.get(endpoint).send());
+        try!(error::check_status(resp.status, "npm registry"));
+
+        let mut data = String::new();
+        try!(resp.read_to_string(&mut data));
+
+        self.parse_response(&data)
+    }
+}
+
+#[test]
+fn test_npm_parse_response() {
+    let repo = NpmRepo::npmjs();
+    let packages = repo.parse_response(include_str!("../fixtures/npm/search.json")).unwrap();
+    assert_eq!(packages.len(), 2);
+
+    let express = &packages[0];
+    assert_eq!(express.name, "express");
+    assert_eq!(express.version, Some("4.14.0".to_owned()));
+    assert_eq!(express.repository,
+               Some("https://github.com/expressjs/express".to_owned()));
+    assert_eq!(express.homepage, Some("http://expressjs.com/".to_owned()));
+    assert_eq!(express.documentation,
+               Some("https://www.npmjs.com/package/express".to_owned()));
+    assert_eq!(express.keywords.len(), 5);
+    assert_eq!(express.attributes.get("score"), Some(&"0.96".to_owned()));
+
+    let types = &packages[1];
+    assert_eq!(types.name, "@types/express");
+    assert!(types.repository.is_none());
+    assert!(types.keywords.is_empty());
+}
+
+#[test]
+fn test_npm_parse_empty_and_invalid_response() {
+    let repo = NpmRepo::npmjs();
+    let packages = repo.parse_response(include_str!("../fixtures/npm/empty.json")).unwrap();
+    assert!(packages.is_empty());
+
+    assert!(repo.parse_response("{\"total\": 0}").is_err());
+    assert!(repo.parse_response("<html>").is_err());
+}
+
+#[test]
+fn test_npm_search() {
+    let repo = NpmRepo::npmjs().with_http(::replay::fixture_client("fixtures/npm"));
+
+    let packages = repo.search("express").unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].name, "express");
+    assert!(repo.search("xqzvq").unwrap().is_empty());
+}
+
+#[test]
+fn test_npm_search_errors() {
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use error::ErrorKind;
+    use replay::{error_kind, ReplayTransport};
+
+    let url = |query: &str| {
+        format!("https://registry.example.com/-/v1/search?text={}&size=50", query)
+    };
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Get, &url("down"), StatusCode::BadGateway, "")
+        .respond(Method::Get, &url("limited"), StatusCode::TooManyRequests, "")
+        .respond(Method::Get, &url("html"), StatusCode::Ok, "<html>maintenance</html>")
+        .into_client();
+    let repo = NpmRepo::new("https://registry.example.com/-/v1/search")
+        .unwrap()
+        .with_http(http);
+
+    assert_eq!(error_kind(repo.search("down")), Some(ErrorKind::Unavailable));
+    assert_eq!(error_kind(repo.search("limited")), Some(ErrorKind::RateLimited));
+    assert_eq!(error_kind(repo.search("html")), Some(ErrorKind::Parse));
+}
@@ -0,0 +1,353 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use hyper::status::StatusCode;
+use hyper::Url;
+use rustc_serialize::json;
+use select::node::Node;
+use select::predicate::{Class, Name};
+use {SearchRepo, Package};
+use error::{self, Error};
+use http::{HttpClient, WithHttp};
+use scrape::document_from_read;
+
+const PYPI_ENDPOINT: &'static str = "https://pypi.org";
+// The search page lacks licenses and project urls, the first page of results
+// is completed from the JSON API with a few lookups at a time
+const ENRICHED_RESULTS: usize = 10;
+const LOOKUP_THREADS: usize = 4;
+
+#[derive(RustcDecodable, Clone)]
+pub struct PypiProject {
+    pub info: PypiInfo,
+    pub urls: Vec<PypiFile>,
+}
+
+#[derive(RustcDecodable, Clone)]
+pub struct PypiInfo {
+    pub name: String,
+    pub version: String,
+    pub summary: Option<String>,
+    pub license: Option<String>,
+    pub home_page: Option<String>,
+    pub keywords: Option<String>,
+    pub requires_python: Option<String>,
+    pub docs_url: Option<String>,
+    pub package_url: Option<String>,
+    pub project_urls: Option<BTreeMap<String, String>>,
+}
+
+#[derive(RustcDecodable, Clone)]
+pub struct PypiFile {
+    pub filename: String,
+    pub upload_time: String,
+}
+
+#[derive(Clone)]
+pub struct PypiRepo {
+    endpoint: Url,
+    http: HttpClient,
+}
+
+// PyPI compares project names case insensitively and treats `-`, `_` and `.`
+// as the same character.
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase().replace('_', "-").replace('.', "-")
+}
+
+// The JSON API wins, the search page only fills what the project lacks
+fn merge_project(scraped: Package, mut pkg: Package) -> Package {
+    pkg.version = pkg.version.or(scraped.version);
+    pkg.description = pkg.description.or(scraped.description);
+    pkg.documentation = pkg.documentation.or(scraped.documentation);
+    pkg.updated_at = pkg.updated_at.or(scraped.updated_at);
+    pkg
+}
+
+impl PypiRepo {
+    /// Creates a repo using a PyPI compatible site serving the search page
+    /// and the JSON API
+    pub fn new(endpoint: &str) -> Result<PypiRepo, Error> {
+        match Url::parse(endpoint) {
+            Ok(url) => {
+                Ok(PypiRepo {
+                    endpoint: url,
+                    http: HttpClient::shared(),
+                })
+            }
+            Err(e) => Err(Error::Config(format!("invalid PyPI endpoint {}: {}", endpoint, e))),
+        }
+    }
+
+    pub fn pypi_org() -> PypiRepo {
+        PypiRepo::new(PYPI_ENDPOINT).unwrap()
+    }
+
+    fn parse_project(&self, data: &str) -> Result<Package, Error> {
+        let project: PypiProject = try!(json::decode(data));
+        let info = project.info;
+        let mut pkg = Package::empty();
+
+        pkg.name = info.name;
+        pkg.version = Some(info.version);
+        pkg.description = info.summary.and_then(|s| if s.is_empty() { None } else { Some(s) });
+        pkg.license = info.license.and_then(|l| if l.is_empty() { None } else { Some(l) });
+        pkg.homepage = info.home_page.and_then(|u| if u.is_empty() { None } else { Some(u) });
+        pkg.documentation = info.docs_url.or(info.package_url);
+        pkg.updated_at = project.urls.iter().map(|f| f.upload_time.clone()).max();
+
+        if let Some(keywords) = info.keywords {
+            pkg.keywords = keywords.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|k| !k.is_empty())
+                .map(|k| k.to_owned())
+                .collect();
+        }
+
+        if let Some(requires_python) = info.requires_python {
+            if !requires_python.is_empty() {
+                pkg.attributes.insert("requires python".to_owned(), requires_python);
+            }
+        }
+
+        for (label, url) in info.project_urls.unwrap_or(BTreeMap::new()) {
+            match label.to_lowercase().as_ref() {
+                "source" | "source code" | "repository" | "code" => pkg.repository = Some(url),
+                "documentation" | "docs" => pkg.documentation = Some(url),
+                "homepage" | "home" => pkg.homepage = Some(url),
+                _ => {
+                    pkg.attributes.insert(label, url);
+                }
+            }
+        }
+
+        Ok(pkg)
+    }
+
+    fn parse_search_page<R: Read>(&self, r: R) -> Result<Vec<Package>, Error> {
+        let doc = try!(document_from_read(r));
+        let snippets = doc.find(Class("package-snippet"));
+
+        let no_results = doc.find(Class("callout-block")).first().is_some();
+
+        if snippets.first().is_none() && !no_results {
+            return Err(Error::Scrape("Invalid PyPI search page".to_owned()));
+        }
+
+        Ok(snippets.iter().filter_map(|n| self.parse_search_snippet(&n)).collect())
+    }
+
+    /// Replaces the first results with their JSON API projects, results whose
+    /// lookup fails are kept as found on the search page
+    fn enrich(&self, packages: &mut Vec<Package>) {
+        let count = cmp::min(packages.len(), ENRICHED_RESULTS);
+        let pending = packages[..count].iter().map(|pkg| pkg.name.clone()).enumerate();
+        let pending = Arc::new(Mutex::new(pending.collect::<Vec<_>>()));
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..cmp::min(count, LOOKUP_THREADS) {
+            let repo = self.clone();
+            let pending = pending.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                loop {
+                    let next = pending.lock().unwrap().pop();
+                    let (i, name) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    if tx.send((i, repo.lookup(&name))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        for (i, result) in rx {
+            match result {
+                Ok(Some(pkg)) => {
+                    let scraped = mem::replace(&mut packages[i], Package::empty());
+                    packages[i] = merge_project(scraped, pkg);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("PyPI lookup of {} failed: {:?}", packages[i].name, e),
+            }
+        }
+    }
+
+    fn parse_search_snippet<'a>(&self, snippet: &Node<'a>) -> Option<Package> {
+        let mut pkg = Package::empty();
+
+        match snippet.find(Class("package-snippet__name")).first() {
+            Some(n) => pkg.name = n.text().trim().to_owned(),
+            None => return None,
+        }
+
+        if let Some(n) = snippet.find(Class("package-snippet__version")).first() {
+            pkg.version = Some(n.text().trim().to_owned());
+        }
+
+        if let Some(n) = snippet.find(Class("package-snippet__description")).first() {
+            let description = n.text().trim().to_owned();
+            if !description.is_empty() {
+                pkg.description = Some(description);
+            }
+        }
+
+        if let Some(n) = snippet.find(Name("time")).first() {
+            pkg.updated_at = n.attr("datetime").map(|t| t.to_owned());
+        }
+
+        if let Some(href) = snippet.attr("href") {
+            pkg.documentation = self.endpoint.join(href).ok().map(|url| url.to_string());
+        }
+
+        if !pkg.name.is_empty() {
+            Some(pkg)
+        } else {
+            None
+        }
+    }
+}
+
+impl WithHttp for PypiRepo {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+impl SearchRepo for PypiRepo {
+    fn search(&self, query: &str) -> Result<Vec<Package>, Error> {
+        let mut endpoint = self.endpoint.clone();
+        endpoint.set_path("/search/");
+        endpoint.query_pairs_mut().append_pair("q", query);
+
+        let resp = try!(self.http.get(endpoint).send());
+        try!(error::check_status(resp.status, "PyPI"));
+        let mut packages = try!(self.parse_search_page(resp));
+
+        // The exact match goes in front of the rest
+        let normalized = normalize_name(query);
+        if let Some(i) = packages.iter().position(|pkg| normalize_name(&pkg.name) == normalized) {
+            let pkg = packages.remove(i);
+            packages.insert(0, pkg);
+        }
+
+        self.enrich(&mut packages);
+
+        Ok(packages)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Option<Package>, Error> {
+        let mut endpoint = self.endpoint.clone();
+        endpoint.path_segments_mut().unwrap().extend(&["pypi", name, "json"]);
+
+        let mut resp = try!(self.http.get(endpoint).send());
+        if resp.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        try!(error::check_status(resp.status, "PyPI"));
+
+        let mut data = String::new();
+        try!(resp.read_to_string(&mut data));
+
+        self.parse_project(&data).map(Some)
+    }
+}
+
+#[test]
+fn test_pypi_parse_project() {
+    let repo = PypiRepo::pypi_org();
+    let pkg = repo.parse_project(include_str!("../fixtures/pypi/requests.json")).unwrap();
+
+    assert_eq!(pkg.name, "requests");
+    assert_eq!(pkg.version, Some("2.11.1".to_owned()));
+    assert_eq!(pkg.license, Some("Apache 2.0".to_owned()));
+    assert_eq!(pkg.repository,
+               Some("https://github.com/kennethreitz/requests".to_owned()));
+    assert_eq!(pkg.documentation,
+               Some("http://docs.python-requests.org".to_owned()));
+    assert_eq!(pkg.updated_at, Some("2016-08-17T12:45:48".to_owned()));
+    assert_eq!(pkg.keywords, vec!["http".to_owned(), "requests".to_owned()]);
+    assert_eq!(pkg.attributes.get("requires python"),
+               Some(&">=2.6, !=3.0.*, !=3.1.*, !=3.2.*".to_owned()));
+}
+
+#[test]
+fn test_pypi_parse_search_page() {
+    let repo = PypiRepo::pypi_org();
+    let page: &[u8] = include_bytes!("../fixtures/pypi/search.html");
+    let packages = repo.parse_search_page(page).unwrap();
+
+    assert_eq!(packages.len(), 3);
+    assert_eq!(packages[0].name, "requests");
+    assert_eq!(packages[0].version, Some("2.11.1".to_owned()));
+    assert_eq!(packages[0].description, Some("Python HTTP for Humans.".to_owned()));
+    assert_eq!(packages[1].documentation,
+               Some("https://pypi.org/project/requests-oauthlib/".to_owned()));
+    assert!(packages[2].description.is_none());
+    assert!(packages[2].updated_at.is_none());
+
+    let empty: &[u8] = include_bytes!("../fixtures/pypi/search_empty.html");
+    assert!(repo.parse_search_page(empty).unwrap().is_empty());
+
+    let broken: &[u8] = b"<html><body>Service unavailable</body></html>";
+    assert!(repo.parse_search_page(broken).is_err());
+}
+
+#[test]
+fn test_pypi_normalize_name() {
+    assert_eq!(normalize_name("Flask_SQLAlchemy"), "flask-sqlalchemy");
+    assert_eq!(normalize_name("zope.interface"), "zope-interface");
+}
+
+#[test]
+fn test_pypi_search() {
+    let repo = PypiRepo::pypi_org().with_http(::replay::fixture_client("fixtures/pypi"));
+
+    // The results come from the JSON API when it knows them
+    let packages = repo.search("requests").unwrap();
+    assert_eq!(packages.len(), 3);
+    assert_eq!(packages[0].name, "requests");
+    assert_eq!(packages[0].license, Some("Apache 2.0".to_owned()));
+    assert_eq!(packages[1].name, "requests-oauthlib");
+    assert_eq!(packages[1].license, Some("ISC".to_owned()));
+    assert_eq!(packages[2].name, "requests-mock");
+    assert!(packages[2].license.is_none());
+    assert_eq!(packages[2].version, Some("1.1.0".to_owned()));
+
+    assert!(repo.search("xqzvq").unwrap().is_empty());
+    assert!(repo.lookup("xqzvq").unwrap().is_none());
+}
+
+#[test]
+fn test_pypi_errors() {
+    use hyper::method::Method;
+    use error::ErrorKind;
+    use replay::{error_kind, ReplayTransport};
+
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Get,
+                 "https://pypi.example.com/search/?q=flask",
+                 StatusCode::Ok,
+                 "<html><body>Service unavailable</body></html>")
+        .respond(Method::Get,
+                 "https://pypi.example.com/pypi/flask/json",
+                 StatusCode::ServiceUnavailable,
+                 "")
+        .respond(Method::Get,
+                 "https://pypi.example.com/pypi/django/json",
+                 StatusCode::Ok,
+                 "{\"info\": ")
+        .into_client();
+    let repo = PypiRepo::new("https://pypi.example.com")
+        .unwrap()
+        .with_http(http);
+
+    assert_eq!(error_kind(repo.search("flask")), Some(ErrorKind::Parse));
+    assert_eq!(error_kind(repo.lookup("flask")), Some(ErrorKind::Unavailable));
+    assert_eq!(error_kind(repo.lookup("django")), Some(ErrorKind::Parse));
+}
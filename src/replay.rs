@@ -0,0 +1,327 @@
+//! Recorded HTTP responses for deterministic tests of the backends.
+//!
+//! A fixture directory has a `responses.txt` with one exchange per line:
+//!
+//! ```text
+//! GET https://registry.npmjs.org/-/v1/search?text=express&size=50 200 search.json
+//! ```
+//!
+//! that is the method, the url, the status and the file holding the body,
+//! relative to the directory. With `PACKAGESBOT_RECORD=1` the tests talk to
+//! the real services instead and save their answers into the directory.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use hyper;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::Url;
+use error::{Error, ErrorKind};
+use http::{self, HttpClient, HttpConfig, HttpRequest, HttpResponse, HyperTransport, Transport};
+
+pub const RECORD_VAR: &'static str = "PACKAGESBOT_RECORD";
+const RESPONSES_FILE: &'static str = "responses.txt";
+
+lazy_static!(
+    // Tests run in parallel and every client recording into a directory
+    // rewrites its `responses.txt`
+    static ref RECORD_LOCKS: Mutex<BTreeMap<PathBuf, Arc<Mutex<()>>>> =
+        Mutex::new(BTreeMap::new());
+);
+
+fn record_lock(dir: &Path) -> Arc<Mutex<()>> {
+    let dir = dir.canonicalize().unwrap_or(dir.to_path_buf());
+    let mut locks = RECORD_LOCKS.lock().unwrap();
+    locks.entry(dir).or_insert_with(|| Arc::new(Mutex::new(()))).clone()
+}
+
+enum Exchange {
+    Response(StatusCode, Vec<u8>),
+    Failure(io::ErrorKind),
+}
+
+fn request_key(method: &Method, url: &Url) -> String {
+    format!("{} {}", method, url)
+}
+
+struct Entry {
+    key: String,
+    status: StatusCode,
+    file: String,
+}
+
+fn invalid_line(n: usize, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("{} line {}: {}", RESPONSES_FILE, n, reason))
+}
+
+fn read_responses(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut data = String::new();
+    match File::open(dir.join(RESPONSES_FILE)) {
+        Ok(mut file) => try!(file.read_to_string(&mut data)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if parts.len() != 4 {
+            return Err(invalid_line(i + 1, "expected a method, a url, a status and a file"));
+        }
+
+        let method = try!(parts[0].parse::<Method>().map_err(|_| invalid_line(i + 1, "method")));
+        let url = try!(Url::parse(parts[1]).map_err(|_| invalid_line(i + 1, "invalid url")));
+        let status = try!(parts[2].parse::<u16>().map_err(|_| invalid_line(i + 1, "status")));
+
+        entries.push(Entry {
+            key: request_key(&method, &url),
+            status: StatusCode::from_u16(status),
+            file: parts[3].to_owned(),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn write_responses(dir: &Path, entries: &[Entry]) -> io::Result<()> {
+    let mut file = try!(File::create(dir.join(RESPONSES_FILE)));
+    for entry in entries {
+        try!(writeln!(file, "{} {} {}", entry.key, entry.status.to_u16(), entry.file));
+    }
+
+    Ok(())
+}
+
+// e.g. `get_crates.io_api_v1_crates_q_serde.json`
+fn body_file_name(request: &HttpRequest, body: &[u8]) -> String {
+    let url = request.url.as_str();
+    let url = url.splitn(2, "://").nth(1).unwrap_or(url);
+    let name = url.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .take(120)
+        .collect::<String>();
+
+    let extension = match body.iter().find(|b| !(**b as char).is_whitespace()) {
+        Some(&b'{') | Some(&b'[') => "json",
+        Some(&b'<') => "html",
+        _ => "txt",
+    };
+
+    format!("{}_{}.{}",
+            request.method.to_string().to_lowercase(),
+            name.trim_right_matches('_'),
+            extension)
+}
+
+/// Answers with canned responses and keeps the requests for inspection
+pub struct ReplayTransport {
+    exchanges: BTreeMap<String, Exchange>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl ReplayTransport {
+    pub fn new() -> ReplayTransport {
+        ReplayTransport {
+            exchanges: BTreeMap::new(),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Loads the responses recorded in `dir`
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<ReplayTransport> {
+        let dir = dir.as_ref();
+        let mut transport = ReplayTransport::new();
+
+        for entry in try!(read_responses(dir)) {
+            let mut body = Vec::new();
+            try!(try!(File::open(dir.join(&entry.file))).read_to_end(&mut body));
+            transport.exchanges.insert(entry.key, Exchange::Response(entry.status, body));
+        }
+
+        Ok(transport)
+    }
+
+    pub fn respond(mut self,
+                   method: Method,
+                   url: &str,
+                   status: StatusCode,
+                   body: &str)
+                   -> ReplayTransport {
+        let key = request_key(&method, &Url::parse(url).unwrap());
+        self.exchanges.insert(key, Exchange::Response(status, body.as_bytes().to_vec()));
+        self
+    }
+
+    /// Fails the request like a broken connection of the given kind
+    pub fn fail(mut self, method: Method, url: &str, kind: io::ErrorKind) -> ReplayTransport {
+        let key = request_key(&method, &Url::parse(url).unwrap());
+        self.exchanges.insert(key, Exchange::Failure(kind));
+        self
+    }
+
+    /// Every request sent so far, in order
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// A client answering from the transport, which is kept to inspect the
+    /// requests afterwards
+    pub fn into_client(self) -> (Arc<ReplayTransport>, HttpClient) {
+        let transport = Arc::new(self);
+        let client = HttpClient::with_transport(transport.clone(), http::DEFAULT_USER_AGENT);
+        (transport, client)
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, request: &HttpRequest) -> hyper::Result<HttpResponse> {
+        self.requests.lock().unwrap().push(request.clone());
+
+        let key = request_key(&request.method, &request.url);
+        match self.exchanges.get(&key) {
+            Some(&Exchange::Response(status, ref body)) => {
+                Ok(HttpResponse::new(status, io::Cursor::new(body.clone())))
+            }
+            Some(&Exchange::Failure(kind)) => {
+                Err(hyper::Error::Io(io::Error::new(kind, "replayed failure")))
+            }
+            None => {
+                let reason = format!("no recorded response for {}, record it with {}=1",
+                                     key,
+                                     RECORD_VAR);
+                Err(hyper::Error::Io(io::Error::new(io::ErrorKind::Other, reason)))
+            }
+        }
+    }
+}
+
+/// Sends requests to the network and saves the responses as fixtures
+pub struct RecordTransport {
+    live: HyperTransport,
+    dir: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl RecordTransport {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> RecordTransport {
+        let dir = dir.into();
+        RecordTransport {
+            live: HyperTransport::new(&HttpConfig::default()),
+            lock: record_lock(&dir),
+            dir: dir,
+        }
+    }
+}
+
+impl Transport for RecordTransport {
+    fn send(&self, request: &HttpRequest) -> hyper::Result<HttpResponse> {
+        let mut resp = try!(self.live.send(request));
+        let mut body = Vec::new();
+        try!(resp.read_to_end(&mut body));
+
+        let _guard = self.lock.lock().unwrap();
+        let mut entries = try!(read_responses(&self.dir));
+        let key = request_key(&request.method, &request.url);
+
+        // Known requests keep their body file, so the parsing tests reading
+        // it directly see the fresh response as well
+        let file = match entries.iter().position(|e| e.key == key) {
+            Some(i) => {
+                entries[i].status = resp.status;
+                entries[i].file.clone()
+            }
+            None => {
+                let file = body_file_name(request, &body);
+                entries.push(Entry {
+                    key: key,
+                    status: resp.status,
+                    file: file.clone(),
+                });
+                file
+            }
+        };
+
+        try!(try!(File::create(self.dir.join(&file))).write_all(&body));
+        try!(write_responses(&self.dir, &entries));
+
+        Ok(HttpResponse::new(resp.status, io::Cursor::new(body)))
+    }
+}
+
+/// Client replaying the responses recorded in `dir`, or recording them when
+/// `PACKAGESBOT_RECORD` is set
+pub fn fixture_client(dir: &str) -> HttpClient {
+    let transport: Arc<Transport> = if env::var_os(RECORD_VAR).is_some() {
+        Arc::new(RecordTransport::new(dir))
+    } else {
+        Arc::new(ReplayTransport::load(dir).unwrap())
+    };
+
+    HttpClient::with_transport(transport, http::DEFAULT_USER_AGENT)
+}
+
+/// The kind of the error, for asserting on failed results
+pub fn error_kind<T>(result: Result<T, Error>) -> Option<ErrorKind> {
+    result.err().map(|e| e.kind())
+}
+
+#[test]
+fn test_replay_transport() {
+    let (transport, client) = ReplayTransport::load("fixtures/npm")
+        .unwrap()
+        .fail(Method::Get, "https://example.com/down", io::ErrorKind::TimedOut)
+        .into_client();
+
+    let url = "https://registry.npmjs.org/-/v1/search?text=express&size=50";
+    let mut resp = client.get(url).send().unwrap();
+    assert_eq!(resp.status, StatusCode::Ok);
+    let mut body = String::new();
+    resp.read_to_string(&mut body).unwrap();
+    assert_eq!(body, include_str!("../fixtures/npm/search.json"));
+
+    match client.get("https://example.com/down").send() {
+        Err(hyper::Error::Io(ref e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+        _ => panic!("the failure is replayed"),
+    }
+    match client.get("https://example.com/missing").send() {
+        Err(hyper::Error::Io(ref e)) => assert!(e.to_string().contains(RECORD_VAR)),
+        _ => panic!("unknown requests fail"),
+    }
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].url.as_str(), url);
+    assert!(requests[0].headers.get::<::hyper::header::UserAgent>().is_some());
+}
+
+#[test]
+fn test_body_file_name() {
+    let request = HttpRequest {
+        method: Method::Get,
+        url: Url::parse("https://crates.io/api/v1/crates?q=serde&page=1").unwrap(),
+        headers: ::hyper::header::Headers::new(),
+        body: Vec::new(),
+    };
+
+    assert_eq!(body_file_name(&request, b" {}"),
+               "get_crates.io_api_v1_crates_q_serde_page_1.json");
+    assert_eq!(body_file_name(&request, b"<html>"),
+               "get_crates.io_api_v1_crates_q_serde_page_1.html");
+}
+
+#[test]
+fn test_record_lock() {
+    let lock = record_lock(Path::new("fixtures/npm"));
+    assert!(Arc::ptr_eq(&lock, &record_lock(Path::new("fixtures/../fixtures/npm"))));
+    assert!(!Arc::ptr_eq(&lock, &record_lock(Path::new("fixtures/pypi"))));
+}
@@ -0,0 +1,188 @@
+//! Offline copy of the RustSec advisory database, a checkout of
+//! https://github.com/rustsec/advisory-db
+//!
+//! Advisories are kept in `crates/<name>/` either as Markdown files with a
+//! TOML front matter block or, in older checkouts, as plain TOML files.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use semver::{Version, VersionReq};
+use config::decode_toml;
+use error::Error;
+
+#[derive(RustcDecodable)]
+struct AdvisoryFile {
+    advisory: AdvisoryMetadata,
+    versions: Option<AdvisoryVersions>,
+}
+
+#[derive(RustcDecodable)]
+struct AdvisoryMetadata {
+    id: String,
+    package: String,
+    title: Option<String>,
+    url: Option<String>,
+    withdrawn: Option<String>,
+    patched_versions: Option<Vec<String>>,
+    unaffected_versions: Option<Vec<String>>,
+}
+
+#[derive(RustcDecodable)]
+struct AdvisoryVersions {
+    patched: Option<Vec<String>>,
+    unaffected: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct Advisory {
+    pub id: String,
+    pub package: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub patched: Vec<VersionReq>,
+    pub unaffected: Vec<VersionReq>,
+    withdrawn: bool,
+}
+
+impl Advisory {
+    pub fn affects(&self, version: &Version) -> bool {
+        !self.withdrawn && !self.patched.iter().any(|r| r.matches(version)) &&
+        !self.unaffected.iter().any(|r| r.matches(version))
+    }
+}
+
+fn parse_reqs(reqs: Option<Vec<String>>) -> Result<Vec<VersionReq>, Error> {
+    let mut parsed = Vec::new();
+
+    for req in reqs.unwrap_or(Vec::new()) {
+        match VersionReq::parse(&req) {
+            Ok(r) => parsed.push(r),
+            Err(e) => return Err(Error::Toml(format!("invalid requirement {}: {:?}", req, e))),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Parses an advisory, `markdown` files keep the TOML in a ```toml block
+/// followed by the title heading
+pub fn parse_advisory(data: &str, markdown: bool) -> Result<Advisory, Error> {
+    let (front_matter, title) = if markdown {
+        let start = match data.find("```toml") {
+            Some(start) => start + "```toml".len(),
+            None => return Err(Error::Toml("advisory has no ```toml block".to_owned())),
+        };
+        let end = match data[start..].find("```") {
+            Some(end) => start + end,
+            None => return Err(Error::Toml("unterminated ```toml block".to_owned())),
+        };
+
+        let title = data[end + 3..]
+            .lines()
+            .map(|l| l.trim())
+            .find(|l| l.starts_with("# "))
+            .map(|l| l[2..].trim().to_owned());
+
+        (&data[start..end], title)
+    } else {
+        (data, None)
+    };
+
+    let file: AdvisoryFile = try!(decode_toml(front_matter));
+    let metadata = file.advisory;
+    let (patched, unaffected) = match file.versions {
+        Some(versions) => (versions.patched, versions.unaffected),
+        None => (metadata.patched_versions, metadata.unaffected_versions),
+    };
+
+    Ok(Advisory {
+        title: title.or(metadata.title).unwrap_or(metadata.id.clone()),
+        id: metadata.id,
+        package: metadata.package,
+        url: metadata.url,
+        patched: try!(parse_reqs(patched)),
+        unaffected: try!(parse_reqs(unaffected)),
+        withdrawn: metadata.withdrawn.is_some(),
+    })
+}
+
+pub struct AdvisoryDb {
+    advisories: HashMap<String, Vec<Advisory>>,
+}
+
+impl AdvisoryDb {
+    /// Loads every advisory below `<root>/crates`, files that can't be
+    /// parsed are skipped with a warning
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<AdvisoryDb, Error> {
+        let mut advisories: HashMap<String, Vec<Advisory>> = HashMap::new();
+
+        for crate_dir in try!(fs::read_dir(root.as_ref().join("crates"))) {
+            let crate_dir = try!(crate_dir).path();
+            if !crate_dir.is_dir() {
+                continue;
+            }
+
+            for entry in try!(fs::read_dir(&crate_dir)) {
+                let path = try!(entry).path();
+                let markdown = match path.extension().and_then(|e| e.to_str()) {
+                    Some("md") => true,
+                    Some("toml") => false,
+                    _ => continue,
+                };
+
+                let mut data = String::new();
+                try!(try!(File::open(&path)).read_to_string(&mut data));
+
+                match parse_advisory(&data, markdown) {
+                    Ok(advisory) => {
+                        advisories.entry(advisory.package.clone())
+                            .or_insert(Vec::new())
+                            .push(advisory)
+                    }
+                    Err(err) => warn!("skipping advisory {}: {:?}", path.display(), err),
+                }
+            }
+        }
+
+        Ok(AdvisoryDb { advisories: advisories })
+    }
+
+    pub fn len(&self) -> usize {
+        self.advisories.values().map(|a| a.len()).sum()
+    }
+
+    /// Advisories that apply to the version of the crate
+    pub fn affecting(&self, name: &str, version: &Version) -> Vec<&Advisory> {
+        match self.advisories.get(name) {
+            Some(advisories) => advisories.iter().filter(|a| a.affects(version)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn test_advisory_db() {
+    let db = AdvisoryDb::load("fixtures/advisory-db").unwrap();
+    assert_eq!(db.len(), 2);
+
+    let affected = db.affecting("smallvec", &Version::parse("0.6.9").unwrap());
+    assert_eq!(affected.len(), 1);
+    assert_eq!(affected[0].id, "RUSTSEC-2019-0009");
+    assert_eq!(affected[0].title, "Double-free and use-after-free in SmallVec::grow()");
+
+    assert!(db.affecting("smallvec", &Version::parse("0.6.10").unwrap()).is_empty());
+    assert!(db.affecting("smallvec", &Version::parse("0.6.1").unwrap()).is_empty());
+
+    let hyper = db.affecting("hyper", &Version::parse("0.9.1").unwrap());
+    assert_eq!(hyper[0].title,
+               "HTTPS MitM vulnerability due to lack of hostname verification");
+    assert!(db.affecting("serde", &Version::parse("0.8.11").unwrap()).is_empty());
+}
+
+#[test]
+fn test_parse_invalid_advisory() {
+    assert!(parse_advisory("# No front matter", true).is_err());
+    assert!(parse_advisory("[advisory]\nid = \"RUSTSEC-2016-0002\"", false).is_err());
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Read};
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Class, Predicate};
+use tendril::{ByteTendril, ReadExt};
+use error::Error;
+
+/// Reads a whole HTML page into a `Document` that can be queried with predicates
+pub fn document_from_read<R: Read>(mut readable: R) -> io::Result<Document> {
+    let mut byte_tendril = ByteTendril::new();
+    try!(readable.read_to_tendril(&mut byte_tendril));
+
+    match byte_tendril.try_reinterpret() {
+        Ok(str_tendril) => Ok(Document::from(str_tendril)),
+        Err(_) => {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                               "stream did not contain valid UTF-8"))
+        }
+    }
+}
+
+// Element matched by a single step of a selector, e.g. `a.title[href]`
+#[derive(Debug, Clone, PartialEq)]
+struct Compound {
+    name: Option<String>,
+    classes: Vec<String>,
+    attrs: Vec<(String, Option<String>)>,
+}
+
+impl Compound {
+    fn matches(&self, node: &Node) -> bool {
+        let name_matches = match self.name {
+            Some(ref name) => node.name() == Some(name.as_str()),
+            None => node.name().is_some(),
+        };
+
+        name_matches && self.classes.iter().all(|class| Class(class.as_str()).matches(node)) &&
+        self.attrs.iter().all(|&(ref attr, ref value)| match *value {
+            Some(ref value) => node.attr(attr) == Some(value.as_str()),
+            None => node.attr(attr).is_some(),
+        })
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// The subset of CSS selectors needed to pick elements out of search pages:
+/// tag names, `*`, `.class`, `#id`, `[attr]`, `[attr=value]` and the
+/// descendant combinator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    source: String,
+    steps: Vec<Compound>,
+}
+
+impl Selector {
+    pub fn parse(source: &str) -> Result<Selector, Error> {
+        let invalid = |reason: &str| {
+            Error::Config(format!("invalid selector `{}`: {}", source, reason))
+        };
+
+        let mut steps = Vec::new();
+        let mut chars = source.trim().chars().peekable();
+
+        while chars.peek().is_some() {
+            let mut step = Compound {
+                name: None,
+                classes: Vec::new(),
+                attrs: Vec::new(),
+            };
+
+            if chars.peek() == Some(&'*') {
+                chars.next();
+            }
+
+            loop {
+                let kind = match chars.peek() {
+                    Some(&c) if c.is_whitespace() => break,
+                    Some(&c) => c,
+                    None => break,
+                };
+
+                if is_ident_char(kind) {
+                    if step.name.is_some() || !step.classes.is_empty() || !step.attrs.is_empty() {
+                        return Err(invalid("the tag name must come first"));
+                    }
+                } else {
+                    chars.next();
+                }
+
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !is_ident_char(c) {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                if ident.is_empty() {
+                    return Err(invalid(&format!("expected a name after `{}`", kind)));
+                }
+
+                match kind {
+                    '.' => step.classes.push(ident),
+                    '#' => step.attrs.push(("id".to_owned(), Some(ident))),
+                    '[' => {
+                        let value = match chars.next() {
+                            Some(']') => None,
+                            Some('=') => {
+                                let quoted = chars.peek() == Some(&'"');
+                                if quoted {
+                                    chars.next();
+                                }
+
+                                let mut value = String::new();
+                                let mut closed = false;
+                                while let Some(c) = chars.next() {
+                                    if (quoted && c == '"') || (!quoted && c == ']') {
+                                        closed = true;
+                                        break;
+                                    }
+                                    value.push(c);
+                                }
+                                if !closed || (quoted && chars.next() != Some(']')) {
+                                    return Err(invalid("unterminated attribute"));
+                                }
+                                Some(value)
+                            }
+                            _ => return Err(invalid("unterminated attribute")),
+                        };
+                        step.attrs.push((ident, value));
+                    }
+                    _ if is_ident_char(kind) => step.name = Some(ident.to_lowercase()),
+                    other => return Err(invalid(&format!("unexpected `{}`", other))),
+                }
+            }
+
+            steps.push(step);
+            while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                chars.next();
+            }
+        }
+
+        if steps.is_empty() {
+            return Err(invalid("the selector is empty"));
+        }
+
+        Ok(Selector {
+            source: source.to_owned(),
+            steps: steps,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Every matching element of the document in document order
+    pub fn find_all<'a>(&self, document: &'a Document) -> Vec<Node<'a>> {
+        let mut selection = document.find(|n: &Node| self.steps[0].matches(n));
+        for step in &self.steps[1..] {
+            selection = selection.find(|n: &Node| step.matches(n));
+        }
+
+        let indexes = selection.iter().map(|n| n.index()).collect::<Vec<usize>>();
+        indexes.into_iter().filter_map(|i| document.nth(i)).collect()
+    }
+
+    /// The first matching descendant of `node`
+    pub fn find_first<'a>(&self, node: &Node<'a>) -> Option<Node<'a>> {
+        let mut selection = node.find(|n: &Node| self.steps[0].matches(n));
+        for step in &self.steps[1..] {
+            selection = selection.find(|n: &Node| step.matches(n));
+        }
+
+        selection.first()
+    }
+}
+
+#[test]
+fn test_parse_selector() {
+    let selector = Selector::parse("div#results  a.title.main[href][data-kind=\"a b\"]").unwrap();
+    assert_eq!(selector.steps,
+               vec![Compound {
+                        name: Some("div".to_owned()),
+                        classes: vec![],
+                        attrs: vec![("id".to_owned(), Some("results".to_owned()))],
+                    },
+                    Compound {
+                        name: Some("a".to_owned()),
+                        classes: vec!["title".to_owned(), "main".to_owned()],
+                        attrs: vec![("href".to_owned(), None),
+                                    ("data-kind".to_owned(), Some("a b".to_owned()))],
+                    }]);
+
+    assert_eq!(Selector::parse("*[role=row]").unwrap().steps[0].attrs,
+               vec![("role".to_owned(), Some("row".to_owned()))]);
+
+    for invalid in &["", ".", "a[href", "a[x=\"y]", ".title a>b", "a.title span.x#"] {
+        assert!(Selector::parse(invalid).is_err(), "{:?} is invalid", invalid);
+    }
+}
+
+#[test]
+fn test_selector_find() {
+    let html = r#"<ul class="packages">
+        <li class="package"><a class="title" href="/p/one">one</a></li>
+        <li class="package featured"><a class="title" href="/p/two">two</a></li>
+        <li class="ad"><a class="title" href="/ads">buy</a></li>
+    </ul>"#;
+    let document = document_from_read(html.as_bytes()).unwrap();
+
+    let rows = Selector::parse("ul.packages li.package").unwrap().find_all(&document);
+    assert_eq!(rows.len(), 2);
+
+    let title = Selector::parse("a[href]").unwrap().find_first(&rows[1]).unwrap();
+    assert_eq!(title.text(), "two");
+    assert!(Selector::parse("span").unwrap().find_first(&rows[0]).is_none());
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use rustc_serialize::json;
+use state::{Scope, StateStore};
+use state::memory::{self, Entries};
+use error::Error;
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Record {
+    uid: i64,
+    chat_id: i64,
+    key: String,
+    value: String,
+}
+
+/// All values kept in memory and written to a single JSON file on every
+/// change. The file is replaced atomically, so a crash leaves either the
+/// old or the new contents.
+pub struct FileStore {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileStore, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = BTreeMap::new();
+
+        match File::open(&path) {
+            Ok(mut file) => {
+                let mut data = String::new();
+                try!(file.read_to_string(&mut data));
+
+                let records: Vec<Record> = try!(json::decode(&data));
+                for r in records {
+                    entries.insert((Scope::new(r.uid, r.chat_id), r.key), r.value);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::from(e)),
+        }
+
+        Ok(FileStore {
+            path: path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn write(&self, entries: &Entries) -> Result<(), Error> {
+        let records = entries.iter()
+            .map(|(&(scope, ref key), value)| {
+                Record {
+                    uid: scope.uid,
+                    chat_id: scope.chat_id,
+                    key: key.clone(),
+                    value: value.clone(),
+                }
+            })
+            .collect::<Vec<Record>>();
+        let data = try!(json::encode(&records));
+
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut file = try!(File::create(&tmp_path));
+            try!(file.write_all(data.as_bytes()));
+            try!(file.sync_all());
+        }
+        try!(fs::rename(&tmp_path, &self.path));
+
+        Ok(())
+    }
+}
+
+impl StateStore for FileStore {
+    fn get(&self, scope: Scope, key: &str) -> Result<Option<String>, Error> {
+        Ok(self.entries.lock().unwrap().get(&(scope, key.to_owned())).cloned())
+    }
+
+    fn set(&self, scope: Scope, key: &str, value: &str) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert((scope, key.to_owned()), value.to_owned());
+        self.write(&entries)
+    }
+
+    fn remove(&self, scope: Scope, key: &str) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(&(scope, key.to_owned())).is_some() {
+            try!(self.write(&entries));
+        }
+
+        Ok(())
+    }
+
+    fn remove_user(&self, uid: i64) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        memory::remove_user(&mut entries, uid);
+        self.write(&entries)
+    }
+
+    fn scan(&self, key: &str) -> Result<Vec<(Scope, String)>, Error> {
+        Ok(memory::scan(&self.entries.lock().unwrap(), key))
+    }
+}
+
+#[test]
+fn test_file_store() {
+    let path = ::std::env::temp_dir().join("packagesbot-state-test.json");
+    let _ = fs::remove_file(&path);
+
+    ::state::check_store(&FileStore::open(&path).unwrap());
+
+    let store = FileStore::open(&path).unwrap();
+    assert_eq!(store.get(Scope::new(2, 10), "history").unwrap(), Some("[]".to_owned()));
+    assert!(store.get(Scope::new(1, 10), "history").unwrap().is_none());
+
+    fs::remove_file(&path).unwrap();
+}
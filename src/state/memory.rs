@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use state::{Scope, StateStore};
+use error::Error;
+
+pub type Entries = BTreeMap<(Scope, String), String>;
+
+pub struct MemoryStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore { entries: Mutex::new(BTreeMap::new()) }
+    }
+}
+
+pub fn remove_user(entries: &mut Entries, uid: i64) {
+    let keys = entries.keys()
+        .filter(|&&(scope, _)| scope.uid == uid)
+        .cloned()
+        .collect::<Vec<(Scope, String)>>();
+
+    for key in keys {
+        entries.remove(&key);
+    }
+}
+
+pub fn scan(entries: &Entries, key: &str) -> Vec<(Scope, String)> {
+    entries.iter()
+        .filter(|&(&(_, ref k), _)| k == key)
+        .map(|(&(scope, _), value)| (scope, value.clone()))
+        .collect()
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, scope: Scope, key: &str) -> Result<Option<String>, Error> {
+        Ok(self.entries.lock().unwrap().get(&(scope, key.to_owned())).cloned())
+    }
+
+    fn set(&self, scope: Scope, key: &str, value: &str) -> Result<(), Error> {
+        self.entries.lock().unwrap().insert((scope, key.to_owned()), value.to_owned());
+        Ok(())
+    }
+
+    fn remove(&self, scope: Scope, key: &str) -> Result<(), Error> {
+        self.entries.lock().unwrap().remove(&(scope, key.to_owned()));
+        Ok(())
+    }
+
+    fn remove_user(&self, uid: i64) -> Result<(), Error> {
+        remove_user(&mut self.entries.lock().unwrap(), uid);
+        Ok(())
+    }
+
+    fn scan(&self, key: &str) -> Result<Vec<(Scope, String)>, Error> {
+        Ok(scan(&self.entries.lock().unwrap(), key))
+    }
+}
+
+#[test]
+fn test_memory_store() {
+    ::state::check_store(&MemoryStore::new());
+}
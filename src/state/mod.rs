@@ -0,0 +1,161 @@
+//! Key-value state of users kept across restarts.
+//!
+//! Values belong to a user in a chat and are stored as JSON under a key,
+//! e.g. the search history or the watchlist. Backends only deal with
+//! strings, `State` adds the typed access handlers use.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use rustc_serialize::{Encodable, Decodable};
+use rustc_serialize::json;
+use error::Error;
+
+mod file;
+mod memory;
+mod sqlite;
+
+pub use self::file::FileStore;
+pub use self::memory::MemoryStore;
+pub use self::sqlite::SqliteStore;
+
+/// User and chat owning a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Scope {
+    pub uid: i64,
+    pub chat_id: i64,
+}
+
+impl Scope {
+    pub fn new(uid: i64, chat_id: i64) -> Scope {
+        Scope {
+            uid: uid,
+            chat_id: chat_id,
+        }
+    }
+}
+
+pub trait StateStore: Send + Sync {
+    fn get(&self, scope: Scope, key: &str) -> Result<Option<String>, Error>;
+    fn set(&self, scope: Scope, key: &str, value: &str) -> Result<(), Error>;
+    fn remove(&self, scope: Scope, key: &str) -> Result<(), Error>;
+    /// Removes every value of the user in all chats
+    fn remove_user(&self, uid: i64) -> Result<(), Error>;
+    /// Values stored under the key in every scope
+    fn scan(&self, key: &str) -> Result<Vec<(Scope, String)>, Error>;
+}
+
+/// Typed access to a `StateStore`
+#[derive(Clone)]
+pub struct State {
+    store: Arc<StateStore>,
+}
+
+impl State {
+    pub fn new(store: Arc<StateStore>) -> State {
+        State { store: store }
+    }
+
+    pub fn load<T: Decodable>(&self, scope: Scope, key: &str) -> Result<Option<T>, Error> {
+        match try!(self.store.get(scope, key)) {
+            Some(value) => json::decode(&value).map(Some).map_err(Error::from),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save<T: Encodable>(&self, scope: Scope, key: &str, value: &T) -> Result<(), Error> {
+        let encoded = try!(json::encode(value));
+        self.store.set(scope, key, &encoded)
+    }
+
+    pub fn remove(&self, scope: Scope, key: &str) -> Result<(), Error> {
+        self.store.remove(scope, key)
+    }
+
+    pub fn remove_user(&self, uid: i64) -> Result<(), Error> {
+        self.store.remove_user(uid)
+    }
+
+    /// Decodes the values stored under the key in every scope, values that
+    /// can't be decoded are skipped
+    pub fn scan<T: Decodable>(&self, key: &str) -> Result<Vec<(Scope, T)>, Error> {
+        let values = try!(self.store.scan(key));
+
+        Ok(values.into_iter()
+            .filter_map(|(scope, value)| match json::decode(&value) {
+                Ok(decoded) => Some((scope, decoded)),
+                Err(err) => {
+                    warn!("invalid {} state of {:?}: {:?}", key, scope, err);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+/// Backend keeping the state
+#[derive(Debug, Clone)]
+pub enum StateConfig {
+    /// Lost on restart
+    Memory,
+    File(PathBuf),
+    Sqlite(PathBuf),
+}
+
+impl StateConfig {
+    pub fn open(&self) -> Result<State, Error> {
+        let store: Arc<StateStore> = match *self {
+            StateConfig::Memory => Arc::new(MemoryStore::new()),
+            StateConfig::File(ref path) => Arc::new(try!(FileStore::open(path))),
+            StateConfig::Sqlite(ref path) => Arc::new(try!(SqliteStore::open(path))),
+        };
+
+        Ok(State::new(store))
+    }
+}
+
+/// Checks the behaviour every backend has to share
+#[cfg(test)]
+fn check_store(store: &StateStore) {
+    let alice = Scope::new(1, 10);
+    let alice_dm = Scope::new(1, 1);
+    let bob = Scope::new(2, 10);
+
+    assert!(store.get(alice, "history").unwrap().is_none());
+
+    store.set(alice, "history", "[\"serde\"]").unwrap();
+    store.set(alice, "history", "[\"tokio\"]").unwrap();
+    store.set(alice_dm, "history", "[\"mux\"]").unwrap();
+    store.set(alice, "settings", "{}").unwrap();
+    store.set(bob, "history", "[]").unwrap();
+
+    assert_eq!(store.get(alice, "history").unwrap(), Some("[\"tokio\"]".to_owned()));
+
+    let mut scanned = store.scan("history").unwrap();
+    scanned.sort();
+    assert_eq!(scanned,
+               vec![(alice_dm, "[\"mux\"]".to_owned()),
+                    (alice, "[\"tokio\"]".to_owned()),
+                    (bob, "[]".to_owned())]);
+
+    store.remove(alice, "settings").unwrap();
+    assert!(store.get(alice, "settings").unwrap().is_none());
+
+    store.remove_user(1).unwrap();
+    assert!(store.get(alice, "history").unwrap().is_none());
+    assert!(store.get(alice_dm, "history").unwrap().is_none());
+    assert!(store.get(bob, "history").unwrap().is_some());
+}
+
+#[test]
+fn test_typed_state() {
+    let state = State::new(Arc::new(MemoryStore::new()));
+    let scope = Scope::new(1, 10);
+
+    state.save(scope, "queries", &vec!["serde".to_owned()]).unwrap();
+    let queries: Option<Vec<String>> = state.load(scope, "queries").unwrap();
+    assert_eq!(queries, Some(vec!["serde".to_owned()]));
+
+    state.save(Scope::new(2, 10), "queries", &"not a list").unwrap();
+    let all: Vec<(Scope, Vec<String>)> = state.scan("queries").unwrap();
+    assert_eq!(all, vec![(scope, vec!["serde".to_owned()])]);
+}
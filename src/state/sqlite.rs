@@ -0,0 +1,87 @@
+use std::path::Path;
+use std::sync::Mutex;
+use rusqlite::{self, Connection};
+use state::{Scope, StateStore};
+use error::Error;
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, Error> {
+        SqliteStore::from_connection(try!(Connection::open(path)))
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqliteStore, Error> {
+        try!(conn.execute_batch("CREATE TABLE IF NOT EXISTS state (
+                                     uid INTEGER NOT NULL,
+                                     chat_id INTEGER NOT NULL,
+                                     key TEXT NOT NULL,
+                                     value TEXT NOT NULL,
+                                     PRIMARY KEY (uid, chat_id, key)
+                                 );"));
+
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn get(&self, scope: Scope, key: &str) -> Result<Option<String>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let value = conn.query_row("SELECT value FROM state WHERE uid = ? AND chat_id = ? AND \
+                                    key = ?",
+                                   &[&scope.uid, &scope.chat_id, &key],
+                                   |row| row.get(0));
+
+        match value {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    fn set(&self, scope: Scope, key: &str, value: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        try!(conn.execute("INSERT OR REPLACE INTO state (uid, chat_id, key, value) VALUES (?, \
+                           ?, ?, ?)",
+                          &[&scope.uid, &scope.chat_id, &key, &value]));
+
+        Ok(())
+    }
+
+    fn remove(&self, scope: Scope, key: &str) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        try!(conn.execute("DELETE FROM state WHERE uid = ? AND chat_id = ? AND key = ?",
+                          &[&scope.uid, &scope.chat_id, &key]));
+
+        Ok(())
+    }
+
+    fn remove_user(&self, uid: i64) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        try!(conn.execute("DELETE FROM state WHERE uid = ?", &[&uid]));
+
+        Ok(())
+    }
+
+    fn scan(&self, key: &str) -> Result<Vec<(Scope, String)>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = try!(conn.prepare("SELECT uid, chat_id, value FROM state WHERE key = ?"));
+        let rows = try!(stmt.query_map(&[&key],
+                                       |row| (Scope::new(row.get(0), row.get(1)), row.get(2))));
+
+        let mut values = Vec::new();
+        for row in rows {
+            values.push(try!(row));
+        }
+
+        Ok(values)
+    }
+}
+
+#[test]
+fn test_sqlite_store() {
+    let store = SqliteStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+    ::state::check_store(&store);
+}
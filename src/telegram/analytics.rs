@@ -0,0 +1,186 @@
+//! Usage analytics. Every search produces a `SearchEvent` that is handed to
+//! the configured `AnalyticsSink`.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::Connection;
+use rustc_serialize::json;
+use telegram::botanio::Botan;
+use Package;
+use error::Error;
+
+#[derive(Debug, Clone, RustcEncodable)]
+pub struct SearchEvent {
+    /// Unix timestamp in seconds
+    pub time: u64,
+    pub uid: i64,
+    pub ecosystem: String,
+    pub query: String,
+    pub results: usize,
+    pub latency_ms: u64,
+    /// Kind of the error if the search failed, e.g. `Timeout`
+    pub error: Option<String>,
+}
+
+impl SearchEvent {
+    pub fn new(uid: i64,
+               ecosystem: &str,
+               query: &str,
+               result: &Result<Vec<Package>, Error>,
+               latency: Duration)
+               -> SearchEvent {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        SearchEvent {
+            time: time,
+            uid: uid,
+            ecosystem: ecosystem.to_owned(),
+            query: query.to_owned(),
+            results: result.as_ref().map(|pkgs| pkgs.len()).unwrap_or(0),
+            latency_ms: latency.as_secs() * 1000 + (latency.subsec_nanos() / 1_000_000) as u64,
+            error: result.as_ref().err().map(|err| format!("{:?}", err.kind())),
+        }
+    }
+}
+
+pub trait AnalyticsSink: Send + Sync {
+    fn track_search(&self, event: &SearchEvent) -> Result<(), Error>;
+}
+
+/// Which sink the bot reports to
+#[derive(Debug, Clone)]
+pub enum AnalyticsConfig {
+    Disabled,
+    JsonLines(PathBuf),
+    Sqlite(PathBuf),
+    /// botan.io with the given token
+    Botan(String),
+}
+
+impl AnalyticsConfig {
+    pub fn open(&self) -> Result<Arc<AnalyticsSink>, Error> {
+        Ok(match *self {
+            AnalyticsConfig::Disabled => Arc::new(NullSink),
+            AnalyticsConfig::JsonLines(ref path) => Arc::new(try!(JsonLinesSink::open(path))),
+            AnalyticsConfig::Sqlite(ref path) => Arc::new(try!(SqliteSink::open(path))),
+            AnalyticsConfig::Botan(ref token) => Arc::new(Botan::new(token)),
+        })
+    }
+}
+
+pub struct NullSink;
+
+impl AnalyticsSink for NullSink {
+    fn track_search(&self, _event: &SearchEvent) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Appends one JSON object per event to a file
+pub struct JsonLinesSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: &PathBuf) -> Result<JsonLinesSink, Error> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(path));
+        Ok(JsonLinesSink { file: Mutex::new(file) })
+    }
+}
+
+impl AnalyticsSink for JsonLinesSink {
+    fn track_search(&self, event: &SearchEvent) -> Result<(), Error> {
+        let line = try!(json::encode(event));
+
+        let mut file = self.file.lock().unwrap();
+        try!(writeln!(file, "{}", line));
+
+        Ok(())
+    }
+}
+
+pub struct SqliteSink {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteSink {
+    pub fn open(path: &PathBuf) -> Result<SqliteSink, Error> {
+        SqliteSink::from_connection(try!(Connection::open(path)))
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqliteSink, Error> {
+        try!(conn.execute_batch("CREATE TABLE IF NOT EXISTS searches (
+                                     time INTEGER NOT NULL,
+                                     uid INTEGER NOT NULL,
+                                     ecosystem TEXT NOT NULL,
+                                     query TEXT NOT NULL,
+                                     results INTEGER NOT NULL,
+                                     latency_ms INTEGER NOT NULL,
+                                     error TEXT
+                                 );"));
+
+        Ok(SqliteSink { conn: Mutex::new(conn) })
+    }
+}
+
+impl AnalyticsSink for SqliteSink {
+    fn track_search(&self, event: &SearchEvent) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+        try!(conn.execute("INSERT INTO searches (time, uid, ecosystem, query, results, \
+                           latency_ms, error) VALUES (?, ?, ?, ?, ?, ?, ?)",
+                          &[&(event.time as i64),
+                            &event.uid,
+                            &event.ecosystem,
+                            &event.query,
+                            &(event.results as i64),
+                            &(event.latency_ms as i64),
+                            &event.error]));
+
+        Ok(())
+    }
+}
+
+impl AnalyticsSink for Botan {
+    fn track_search(&self, event: &SearchEvent) -> Result<(), Error> {
+        self.track(event.uid, "search", event).map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+fn sample_event() -> SearchEvent {
+    let mut pkg = Package::empty();
+    pkg.name = "serde".to_owned();
+
+    SearchEvent::new(42, "rust", "serde", &Ok(vec![pkg]), Duration::from_millis(1250))
+}
+
+#[test]
+fn test_search_event() {
+    let event = sample_event();
+    assert_eq!(event.results, 1);
+    assert_eq!(event.latency_ms, 1250);
+    assert!(event.error.is_none());
+
+    let failed = SearchEvent::new(42,
+                                  "go",
+                                  "mux",
+                                  &Err(Error::Scrape("Invalid pkg.go.dev page".to_owned())),
+                                  Duration::from_secs(1));
+    assert_eq!(failed.results, 0);
+    assert_eq!(failed.error, Some("Parse".to_owned()));
+}
+
+#[test]
+fn test_sqlite_sink() {
+    let sink = SqliteSink::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+    sink.track_search(&sample_event()).unwrap();
+    sink.track_search(&sample_event()).unwrap();
+
+    let conn = sink.conn.lock().unwrap();
+    let sql = "SELECT COUNT(*) FROM searches WHERE ecosystem = 'rust'";
+    let count: i64 = conn.query_row(sql, &[], |row| row.get(0)).unwrap();
+    assert_eq!(count, 2);
+}
@@ -3,43 +3,112 @@ use std::collections::BTreeMap;
 use std::ops::Deref;
 use std::iter::Iterator;
 use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use chan_signal::Signal;
+use std::thread;
+use std::time::{Duration, Instant};
 use regex::Regex;
-use rustc_serialize::{Encodable, Decodable};
-use telegram_bot::{Api, ListeningAction, ListeningMethod, MessageType};
-use telegram_bot::types::{Integer, ParseMode};
+use telegram_bot::{Api, MessageType};
+use telegram_bot::types::{Document, Integer, ParseMode};
 use threadpool::ThreadPool;
-use telegram::botanio::Botan;
-use telegram::html::HtmlMessageBuilder;
-use super::super::{SearchRepo, Package};
-use error::Error;
+use audit::{self, AuditReport, Auditor};
+use gomod::{self, GoModReport};
+use telegram::analytics::{AnalyticsSink, SearchEvent};
+use telegram::botapi::{BotApi, CallbackQuery, InlineQuery, InlineQueryResultArticle, Update};
+use telegram::files::FileApi;
+use telegram::history::{self, History};
+use telegram::html::{self, HtmlMessageBuilder};
+use telegram::paging::{self, SearchSession, SessionStore};
+use telegram::watch::Watchlist;
+use telegram::webhook::{WebhookConfig, WebhookEvent, WebhookServer};
+use super::super::{CacheStats, Ecosystem, SearchRepo, Package};
+use state::{Scope, State};
+use error::{Error, ErrorKind};
 
-const MAX_MESSAGE_LENGTH: usize = 4096;
-const SEARCH_WORKERS_COUNT: usize = 4;
+const RELEASES_LIMIT: usize = 10;
+const INLINE_RESULTS_LIMIT: usize = 50;
+const INLINE_CACHE_TIME: Integer = 300;
+/// Failed inline searches are answered with no results, retried soon
+const INLINE_FAILURE_CACHE_TIME: Integer = 5;
+const MAX_UPLOAD_SIZE: u64 = 4 * 1024 * 1024;
+const FILE_TOO_LARGE: &'static str = "The file is too large, files up to 4 MB can be audited";
+const AUDIT_REPORT_NAME: &'static str = "audit-report.txt";
+/// Pause before polling again after getUpdates failed
+const POLL_RETRY_DELAY: u64 = 5;
+
+/// Commands handled by the bot itself, ecosystems can't take them
+pub const BUILTIN_COMMANDS: &'static [&'static str] = &["info", "versions", "history", "forget",
+                                                        "watch", "unwatch", "watching",
+                                                        "cachestats", "audit"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct BotConfig {
+    /// Threads running searches and lookups
+    pub workers: usize,
+    /// Longest message sent, Telegram rejects anything above 4096 characters
+    pub max_message_length: usize,
+    /// Telegram drops inline answers that arrive too late, so inline queries
+    /// waiting longer in the queue aren't searched and slower results are
+    /// replaced with an empty answer
+    pub inline_deadline: Duration,
+    /// How often watched packages are checked for new releases
+    pub watch_interval: Duration,
+}
+
+impl Default for BotConfig {
+    fn default() -> BotConfig {
+        BotConfig {
+            workers: 4,
+            max_message_length: 4096,
+            inline_deadline: Duration::from_millis(5000),
+            watch_interval: Duration::from_secs(15 * 60),
+        }
+    }
+}
 lazy_static!(
     static ref COMMAND_RE: Regex = Regex::new(r"/(\w+)\s*(.*)").unwrap();
 );
 
-trait StateStore {
-    fn save<S: Encodable>(&self, uid: i64, cid: i64);
-    fn load<M: Decodable>(&self, uid: i64, cid: i64);
-}
-
-#[derive(Debug, RustcEncodable)]
-struct SearchStatsMessage {
-    query: String,
+/// What to tell the user when a repository request fails
+fn failure_message(err: &Error) -> &'static str {
+    match err.kind() {
+        ErrorKind::Unavailable => {
+            "The package registry is unavailable at the moment, please try again later"
+        }
+        ErrorKind::Timeout => {
+            "The package registry is taking too long to respond, please try again"
+        }
+        ErrorKind::RateLimited => {
+            "Too many requests to the package registry, please try again in a minute"
+        }
+        ErrorKind::Parse => {
+            "The package registry sent a response the bot doesn't understand, the search is \
+             broken until the bot is updated"
+        }
+        ErrorKind::InvalidInput => "This is not a valid package name",
+        _ => "Something went wrong while searching, please try again later",
+    }
 }
 
 #[derive(Clone)]
 pub struct BotContext<'a> {
     api: &'a Api,
-    tracker: &'a Botan,
+    bot_api: &'a BotApi,
+    analytics: &'a Arc<AnalyticsSink>,
+    settings: &'a BotConfig,
 }
 
 impl<'a> BotContext<'a> {
-    fn new(api: &'a Api, tracker: &'a Botan) -> BotContext<'a> {
+    fn new(api: &'a Api,
+           bot_api: &'a BotApi,
+           analytics: &'a Arc<AnalyticsSink>,
+           settings: &'a BotConfig)
+           -> BotContext<'a> {
         BotContext {
             api: api,
-            tracker: tracker,
+            bot_api: bot_api,
+            analytics: analytics,
+            settings: settings,
         }
     }
 }
@@ -79,20 +148,85 @@ impl<'a> Command<'a> {
     }
 }
 
+/// Button press on a message sent by a handler, callback data has the form
+/// `<command>:<payload>`
+pub struct Callback<'a> {
+    command: &'a str,
+    payload: &'a str,
+    message_id: Integer,
+    /// Id of the callback query, for handlers answering it later
+    query_id: &'a str,
+}
+
+impl<'a> Callback<'a> {
+    fn parse(data: &'a str, message_id: Integer, query_id: &'a str) -> Option<Callback<'a>> {
+        let mut parts = data.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(command), Some(payload)) if !command.is_empty() => {
+                Some(Callback {
+                    command: command,
+                    payload: payload,
+                    message_id: message_id,
+                    query_id: query_id,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a button press is answered, Telegram shows a spinner on the button
+/// until it is
+pub enum CallbackAnswer {
+    Done,
+    /// Shown to the user as a notification
+    Notify(String),
+    /// The handler answers the callback query itself once it's done
+    Deferred,
+}
+
 pub trait CommandHandler {
     fn handle(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error>;
+
+    /// Restricted commands are reported as unrecognized in other chats
+    fn allows(&self, _chat_id: Integer) -> bool {
+        true
+    }
+
+    /// Handles a button press, errors are shown to the user as a notification
+    fn handle_callback(&self,
+                       _ctx: &RequestContext,
+                       _callback: &Callback)
+                       -> Result<CallbackAnswer, Error> {
+        Ok(CallbackAnswer::Done)
+    }
 }
 
 struct SearchHandler {
+    name: String,
     repo: Box<SearchRepo>,
+    ecosystems: Arc<Ecosystems>,
     pool: Arc<ThreadPool>,
+    sessions: SessionStore,
+    history: History,
 }
 
 impl SearchHandler {
-    fn new(repo: Box<SearchRepo>, pool: Arc<ThreadPool>) -> SearchHandler {
+    fn new(name: &str,
+           repo: Box<SearchRepo>,
+           ecosystems: Arc<Ecosystems>,
+           pool: Arc<ThreadPool>,
+           sessions: SessionStore,
+           history: History)
+           -> SearchHandler {
         SearchHandler {
+            name: name.to_owned(),
             repo: repo,
+            ecosystems: ecosystems,
             pool: pool,
+            sessions: sessions,
+            history: history,
         }
     }
 
@@ -108,121 +242,1021 @@ impl SearchHandler {
         Ok(())
     }
 
-    fn send_packages(api: &Api, chat_id: Integer, packages: &[Package]) -> Result<(), Error> {
+    fn send_packages(bot_api: &BotApi,
+                     chat_id: Integer,
+                     sessions: &SessionStore,
+                     session: SearchSession,
+                     packages: &[Package],
+                     max_length: usize)
+                     -> Result<(), Error> {
+        let pages = SearchHandler::render_pages(packages, max_length);
+        let markup = paging::keyboard(&session.ecosystem, 0, pages.len());
+        let paginated = markup.is_some();
 
-        let mut msg = String::with_capacity(1024);
+        let sent = try!(bot_api.send_message(chat_id, &pages[0], markup.as_ref()));
 
-        for pkg in packages.iter().take(10) {
-            let msg_part = SearchHandler::prepare_message_text(pkg);
+        if paginated {
+            sessions.insert(chat_id, sent.message_id, session);
+        }
 
-            if msg.len() + msg_part.len() >= MAX_MESSAGE_LENGTH {
-                break;
-            }
+        Ok(())
+    }
 
-            msg.push_str(&msg_part);
+    fn edit_packages_page(bot_api: &BotApi,
+                          chat_id: Integer,
+                          message_id: Integer,
+                          session: &SearchSession,
+                          packages: &[Package],
+                          max_length: usize)
+                          -> Result<usize, Error> {
+        let pages = SearchHandler::render_pages(packages, max_length);
+        if pages.is_empty() {
+            return Ok(0);
         }
+        let page = if session.page >= pages.len() {
+            pages.len() - 1
+        } else {
+            session.page
+        };
 
-        try!(api.send_message(chat_id, msg, Some(ParseMode::Html), Some(true), None, None));
+        let markup = paging::keyboard(&session.ecosystem, page, pages.len());
+        try!(bot_api.edit_message_text(chat_id, message_id, &pages[page], markup.as_ref()));
 
-        Ok(())
+        Ok(page)
+    }
+
+    /// Splits the results into messages, packages not fitting into
+    /// `max_length` move to the next page and are cut when even a page of
+    /// their own is too short
+    fn render_pages(packages: &[Package], max_length: usize) -> Vec<String> {
+        let parts = packages.iter()
+            .map(|pkg| html::summary_within(pkg, max_length))
+            .collect::<Vec<String>>();
+        let lengths = parts.iter().map(|part| part.len()).collect::<Vec<usize>>();
+
+        paging::split_pages(&lengths, max_length)
+            .into_iter()
+            .map(|(start, end)| parts[start..end].concat())
+            .collect()
     }
 
     fn prepare_message_text(pkg: &Package) -> String {
-        let description: &str = match pkg.description {
-            Some(ref desc) => desc,
-            None => "",
+        HtmlMessageBuilder::summary(pkg).build()
+    }
+
+    fn search(&self, ctx: &RequestContext, cmd: &Command) {
+        if !cmd.query.is_empty() {
+            if let Err(err) = self.history.record(ctx.uid, ctx.chat_id, &self.name, cmd.query) {
+                error!("{:?}", err);
+            }
+        }
+
+        SearchHandler::run_search(ctx,
+                                  self.repo.clone(),
+                                  &self.name,
+                                  cmd.query,
+                                  &self.pool,
+                                  &self.sessions);
+    }
+
+    /// Searches in the background and sends the first page of results
+    fn run_search(ctx: &RequestContext,
+                  repo: Box<SearchRepo>,
+                  ecosystem: &str,
+                  query: &str,
+                  pool: &ThreadPool,
+                  sessions: &SessionStore) {
+        let query = String::from(query);
+        let api = ctx.bot_ctx.api.clone();
+        let bot_api = ctx.bot_ctx.bot_api.clone();
+        let chat_id = ctx.chat_id;
+        let uid = ctx.uid;
+        let analytics = ctx.bot_ctx.analytics.clone();
+        let settings = *ctx.bot_ctx.settings;
+        let sessions = sessions.clone();
+        let session = SearchSession {
+            ecosystem: ecosystem.to_owned(),
+            query: query.clone(),
+            page: 0,
         };
-        let repo_url: &str = match pkg.repository {
-            Some(ref u) => u,
-            None => "",
+
+        pool.execute(move || {
+            let started = Instant::now();
+            // Slow registries are cut off by the connect and read timeouts
+            // of the HTTP client
+            let search_result = repo.search(&query);
+
+            // Tracked once the user has the reply, sinks may be slow
+            let event = SearchEvent::new(uid,
+                                         &session.ecosystem,
+                                         &query,
+                                         &search_result,
+                                         started.elapsed());
+
+            let send_result = match search_result {
+                Ok(ref pkgs) if !pkgs.is_empty() => {
+                    SearchHandler::send_packages(&bot_api,
+                                                 chat_id,
+                                                 &sessions,
+                                                 session,
+                                                 pkgs,
+                                                 settings.max_message_length)
+                }
+                Ok(_) => SearchHandler::send_empty_result(&api, chat_id),
+                Err(ref err) => {
+                    error!("{} search for {:?} failed: {:?}", session.ecosystem, query, err);
+                    InfoHandler::send_text(&api, chat_id, failure_message(err))
+                }
+            };
+            if let Err(err) = send_result {
+                error!("{:?}", err);
+            }
+
+            if let Err(err) = analytics.track_search(&event) {
+                error!("{:?}", err);
+            }
+        });
+    }
+}
+
+impl CommandHandler for SearchHandler {
+    fn handle(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error> {
+        self.search(ctx, cmd);
+
+        Ok(())
+    }
+
+    fn allows(&self, chat_id: Integer) -> bool {
+        self.ecosystems.allows(&self.name, Some(chat_id))
+    }
+
+    fn handle_callback(&self,
+                       ctx: &RequestContext,
+                       callback: &Callback)
+                       -> Result<CallbackAnswer, Error> {
+        let page: usize = match callback.payload.parse() {
+            Ok(page) => page,
+            Err(_) => return Ok(CallbackAnswer::Done),
         };
-        let doc_url: &str = match pkg.documentation {
-            Some(ref u) => u,
-            None => "",
+
+        let mut session = match self.sessions.get(ctx.chat_id, callback.message_id) {
+            Some(session) => session,
+            None => {
+                let msg = "This search has expired, please repeat it";
+                return Ok(CallbackAnswer::Notify(msg.to_owned()));
+            }
         };
-        SearchHandler::render_html_message(&pkg.name, description, repo_url, doc_url)
+        session.page = page;
+
+        let repo = self.repo.clone();
+        let bot_api = ctx.bot_ctx.bot_api.clone();
+        let sessions = self.sessions.clone();
+        let chat_id = ctx.chat_id;
+        let message_id = callback.message_id;
+        let query_id = callback.query_id.to_owned();
+        let settings = *ctx.bot_ctx.settings;
+
+        self.pool.execute(move || {
+            let search_result = repo.search(&session.query);
+            let edit_result = search_result.and_then(|pkgs| {
+                SearchHandler::edit_packages_page(&bot_api,
+                                                  chat_id,
+                                                  message_id,
+                                                  &session,
+                                                  &pkgs,
+                                                  settings.max_message_length)
+            });
+
+            let notification = match edit_result {
+                Ok(page) => {
+                    sessions.set_page(chat_id, message_id, page);
+                    None
+                }
+                Err(err) => {
+                    error!("{:?}", err);
+                    Some(failure_message(&err))
+                }
+            };
+
+            if let Err(err) = bot_api.answer_callback_query(&query_id, notification) {
+                error!("{:?}", err);
+            }
+        });
+
+        Ok(CallbackAnswer::Deferred)
     }
+}
+
+/// Query prefixed with the ecosystem name, e.g. `rust serde`
+pub struct EcosystemQuery<'a> {
+    ecosystem: &'a str,
+    query: &'a str,
+}
+
+impl<'a> EcosystemQuery<'a> {
+    fn parse(t: &'a str) -> Option<EcosystemQuery<'a>> {
+        let t = t.trim();
+        let mut parts = t.splitn(2, char::is_whitespace);
+
+        match (parts.next(), parts.next()) {
+            (Some(ecosystem), Some(query)) if !query.trim().is_empty() => {
+                Some(EcosystemQuery {
+                    ecosystem: ecosystem,
+                    query: query.trim(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
 
-    fn render_html_message(name: &str, description: &str, url: &str, doc_url: &str) -> String {
-        let mut msg_builder = HtmlMessageBuilder::new();
-        {
-            msg_builder.name(name);
+/// Search repositories by ecosystem name.
+///
+/// Ecosystems with an allow-list are hidden from other chats and from inline
+/// queries, which don't carry a chat id.
+pub struct Ecosystems {
+    repos: BTreeMap<String, Box<SearchRepo>>,
+    canonical: BTreeMap<String, String>,
+    allowed_chats: BTreeMap<String, Vec<Integer>>,
+}
+
+impl Ecosystems {
+    fn new() -> Ecosystems {
+        Ecosystems {
+            repos: BTreeMap::new(),
+            canonical: BTreeMap::new(),
+            allowed_chats: BTreeMap::new(),
         }
+    }
+
+    fn insert(&mut self, ecosystem: &Ecosystem) {
+        self.repos.insert(ecosystem.command.clone(), ecosystem.repo.clone());
+        self.canonical.insert(ecosystem.command.clone(), ecosystem.canonical.clone());
 
-        if !description.is_empty() {
-            msg_builder.description(description);
+        if let Some(ref chats) = ecosystem.allowed_chats {
+            self.allowed_chats.insert(ecosystem.command.clone(), chats.clone());
         }
+    }
 
-        if !url.is_empty() {
-            msg_builder.repo_url(url);
+    fn allows(&self, name: &str, chat_id: Option<Integer>) -> bool {
+        match (self.allowed_chats.get(name), chat_id) {
+            (None, _) => true,
+            (Some(chats), Some(chat_id)) => chats.contains(&chat_id),
+            (Some(_), None) => false,
         }
+    }
+
+    /// The command an alias stands for, unknown names are kept as typed
+    fn canonical<'a>(&'a self, name: &'a str) -> &'a str {
+        self.canonical.get(name).map_or(name, |c| c.as_ref())
+    }
 
-        if !doc_url.is_empty() {
-            msg_builder.doc_url(doc_url);
+    /// The repo regardless of the allow-list, for background jobs
+    fn repo(&self, name: &str) -> Option<Box<SearchRepo>> {
+        self.repos.get(name).cloned()
+    }
+
+    fn get(&self, name: &str, chat_id: Option<Integer>) -> Option<Box<SearchRepo>> {
+        if self.allows(name, chat_id) {
+            self.repos.get(name).cloned()
+        } else {
+            None
         }
+    }
 
-        msg_builder.build()
+    /// Parses `<ecosystem> <query>` and finds the ecosystem's repo
+    fn find_query(&self,
+                  text: &str,
+                  chat_id: Option<Integer>)
+                  -> Option<(Box<SearchRepo>, String)> {
+        EcosystemQuery::parse(text)
+            .and_then(|q| self.get(q.ecosystem, chat_id).map(|r| (r, q.query.to_owned())))
     }
 
-    fn track(&self, ctx: &RequestContext, cmd: &Command) {
-        let query = String::from(cmd.query);
-        let uid = ctx.uid;
-        let tracker = ctx.bot_ctx.tracker.clone();
+    fn names(&self, chat_id: Option<Integer>) -> Vec<String> {
+        self.repos.keys().filter(|name| self.allows(name, chat_id)).cloned().collect()
+    }
+
+    fn cache_stats(&self, chat_id: Option<Integer>) -> Vec<(String, CacheStats)> {
+        self.repos
+            .iter()
+            .filter(|&(name, _)| self.allows(name, chat_id))
+            .filter_map(|(name, repo)| repo.cache_stats().map(|stats| (name.clone(), stats)))
+            .collect()
+    }
+}
+
+struct InfoHandler {
+    ecosystems: Arc<Ecosystems>,
+    pool: Arc<ThreadPool>,
+}
+
+impl InfoHandler {
+    fn new(ecosystems: Arc<Ecosystems>, pool: Arc<ThreadPool>) -> InfoHandler {
+        InfoHandler {
+            ecosystems: ecosystems,
+            pool: pool,
+        }
+    }
+
+    fn send_text(api: &Api, chat_id: Integer, msg: &str) -> Result<(), Error> {
+        try!(api.send_message(chat_id, msg.to_owned(), Some(ParseMode::Html), None, None, None));
+
+        Ok(())
+    }
+
+    fn send_usage(&self, ctx: &RequestContext) -> Result<(), Error> {
+        let ecosystems = self.ecosystems.names(Some(ctx.chat_id)).join(", ");
+        let msg = format!("Usage: /info &lt;ecosystem&gt; &lt;name&gt;\nEcosystems: {}",
+                          ecosystems);
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, &msg)
+    }
+
+    fn prepare_card(pkg: &Package) -> String {
+        HtmlMessageBuilder::package(pkg).build()
+    }
+}
+
+impl CommandHandler for InfoHandler {
+    fn handle(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error> {
+        let lookup = self.ecosystems.find_query(cmd.query, Some(ctx.chat_id));
+
+        let (repo, name) = match lookup {
+            Some(found) => found,
+            None => return self.send_usage(ctx),
+        };
+
+        let api = ctx.bot_ctx.api.clone();
+        let chat_id = ctx.chat_id;
 
         self.pool.execute(move || {
-            let msg = SearchStatsMessage { query: query.clone() };
-            if let Err(err) = tracker.track(uid, "search", &msg) {
+            let send_result = match repo.lookup(&name) {
+                Ok(Some(ref pkg)) => {
+                    api.send_message(chat_id,
+                                      InfoHandler::prepare_card(pkg),
+                                      Some(ParseMode::Html),
+                                      Some(true),
+                                      None,
+                                      None)
+                        .map(|_| ())
+                        .map_err(Error::from)
+                }
+                Ok(_) => InfoHandler::send_text(&api, chat_id, "Package not found"),
+                Err(ref err) => {
+                    error!("lookup of {:?} failed: {:?}", name, err);
+                    InfoHandler::send_text(&api, chat_id, failure_message(err))
+                }
+            };
+            if let Err(err) = send_result {
                 error!("{:?}", err);
             }
         });
+
+        Ok(())
     }
+}
+
+struct VersionsHandler {
+    ecosystems: Arc<Ecosystems>,
+    pool: Arc<ThreadPool>,
+}
+
+impl VersionsHandler {
+    fn new(ecosystems: Arc<Ecosystems>, pool: Arc<ThreadPool>) -> VersionsHandler {
+        VersionsHandler {
+            ecosystems: ecosystems,
+            pool: pool,
+        }
+    }
+
+    fn send_usage(&self, ctx: &RequestContext) -> Result<(), Error> {
+        let ecosystems = self.ecosystems.names(Some(ctx.chat_id)).join(", ");
+        let msg = format!("Usage: /versions &lt;ecosystem&gt; &lt;name&gt;\nEcosystems: {}",
+                          ecosystems);
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, &msg)
+    }
+}
+
+impl CommandHandler for VersionsHandler {
+    fn handle(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error> {
+        let lookup = self.ecosystems.find_query(cmd.query, Some(ctx.chat_id));
+
+        let (repo, name) = match lookup {
+            Some(found) => found,
+            None => return self.send_usage(ctx),
+        };
 
-    fn search(&self, ctx: &RequestContext, cmd: &Command) {
-        let repo = self.repo.clone();
-        let query = String::from(cmd.query);
         let api = ctx.bot_ctx.api.clone();
         let chat_id = ctx.chat_id;
+        let max_length = ctx.bot_ctx.settings.max_message_length;
 
         self.pool.execute(move || {
-            let search_result = repo.search(&query);
-            let send_result = match search_result {
-                Ok(ref pkgs) if !pkgs.is_empty() => {
-                    SearchHandler::send_packages(&api, chat_id, pkgs)
+            let send_result = match repo.releases(&name) {
+                Ok(Some(ref releases)) if !releases.is_empty() => {
+                    let msg = html::releases_message(&name, releases, RELEASES_LIMIT, max_length);
+                    InfoHandler::send_text(&api, chat_id, &msg)
+                }
+                Ok(_) => InfoHandler::send_text(&api, chat_id, "No version data found"),
+                Err(ref err) => {
+                    error!("releases of {:?} failed: {:?}", name, err);
+                    InfoHandler::send_text(&api, chat_id, failure_message(err))
                 }
-                _ => SearchHandler::send_empty_result(&api, chat_id),
             };
             if let Err(err) = send_result {
                 error!("{:?}", err);
             }
         });
+
+        Ok(())
     }
 }
 
-impl CommandHandler for SearchHandler {
+struct HistoryHandler {
+    ecosystems: Arc<Ecosystems>,
+    pool: Arc<ThreadPool>,
+    sessions: SessionStore,
+    history: History,
+}
+
+impl CommandHandler for HistoryHandler {
+    fn handle(&self, ctx: &RequestContext, _: &Command) -> Result<(), Error> {
+        let entries = try!(self.history.entries(ctx.uid, ctx.chat_id));
+        if entries.is_empty() {
+            return InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, "No recent searches");
+        }
+
+        let keyboard = history::keyboard(ctx.uid, &entries);
+        try!(ctx.bot_ctx.bot_api.send_message(ctx.chat_id, "Recent searches:", Some(&keyboard)));
+
+        Ok(())
+    }
+
+    fn handle_callback(&self,
+                       ctx: &RequestContext,
+                       callback: &Callback)
+                       -> Result<CallbackAnswer, Error> {
+        let (uid, id) = match history::parse_payload(callback.payload) {
+            Some(parsed) => parsed,
+            None => return Ok(CallbackAnswer::Done),
+        };
+
+        if uid != ctx.uid {
+            let msg = "Use /history to see your own searches";
+            return Ok(CallbackAnswer::Notify(msg.to_owned()));
+        }
+
+        let entries = try!(self.history.entries(ctx.uid, ctx.chat_id));
+        let entry = match entries.iter().find(|entry| entry.id() == id) {
+            Some(entry) => entry,
+            None => {
+                let msg = "This search is no longer in your history";
+                return Ok(CallbackAnswer::Notify(msg.to_owned()));
+            }
+        };
+
+        let repo = match self.ecosystems.get(&entry.ecosystem, Some(ctx.chat_id)) {
+            Some(repo) => repo,
+            None => {
+                let msg = "This search is not available anymore";
+                return Ok(CallbackAnswer::Notify(msg.to_owned()));
+            }
+        };
+
+        try!(self.history.record(ctx.uid, ctx.chat_id, &entry.ecosystem, &entry.query));
+        SearchHandler::run_search(ctx,
+                                  repo,
+                                  &entry.ecosystem,
+                                  &entry.query,
+                                  &self.pool,
+                                  &self.sessions);
+
+        Ok(CallbackAnswer::Done)
+    }
+}
+
+struct ForgetHandler {
+    history: History,
+}
+
+impl CommandHandler for ForgetHandler {
+    fn handle(&self, ctx: &RequestContext, _: &Command) -> Result<(), Error> {
+        try!(self.history.forget(ctx.uid));
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, "Your search history is deleted")
+    }
+}
+
+struct WatchHandler {
+    ecosystems: Arc<Ecosystems>,
+    pool: Arc<ThreadPool>,
+    watchlist: Watchlist,
+}
+
+impl CommandHandler for WatchHandler {
     fn handle(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error> {
-        self.track(ctx, cmd);
-        self.search(ctx, cmd);
+        let query = EcosystemQuery::parse(cmd.query)
+            .and_then(|q| self.ecosystems.get(q.ecosystem, Some(ctx.chat_id)).map(|r| (r, q)));
+
+        let (repo, ecosystem, name) = match query {
+            Some((repo, q)) => {
+                (repo, self.ecosystems.canonical(q.ecosystem).to_owned(), q.query.to_owned())
+            }
+            None => {
+                let ecosystems = self.ecosystems.names(Some(ctx.chat_id)).join(", ");
+                let msg = format!("Usage: /watch &lt;ecosystem&gt; &lt;name&gt;\nEcosystems: {}",
+                                  ecosystems);
+                return InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, &msg);
+            }
+        };
+
+        let api = ctx.bot_ctx.api.clone();
+        let chat_id = ctx.chat_id;
+        let scope = Scope::new(ctx.uid, ctx.chat_id);
+        let watchlist = self.watchlist.clone();
+
+        self.pool.execute(move || {
+            let watched = match repo.lookup(&name) {
+                Ok(Some(pkg)) => {
+                    watchlist.watch(scope, &ecosystem, &pkg.name, pkg.version.clone())
+                        .map(|added| Some((pkg, added)))
+                }
+                Ok(None) => Ok(None),
+                Err(err) => Err(err),
+            };
+
+            let send_result = match watched {
+                Ok(Some((pkg, true))) => {
+                    let msg = format!("Watching {} {}, new releases will be announced here",
+                                      pkg.name,
+                                      pkg.version.unwrap_or(String::new()));
+                    InfoHandler::send_text(&api, chat_id, &html::html_escape(&msg))
+                }
+                Ok(Some((pkg, false))) => {
+                    let msg = format!("Already watching {}", pkg.name);
+                    InfoHandler::send_text(&api, chat_id, &html::html_escape(&msg))
+                }
+                Ok(None) => InfoHandler::send_text(&api, chat_id, "Package not found"),
+                Err(ref err) => {
+                    error!("watching {:?} failed: {:?}", name, err);
+                    InfoHandler::send_text(&api, chat_id, failure_message(err))
+                }
+            };
+            if let Err(err) = send_result {
+                error!("{:?}", err);
+            }
+        });
 
         Ok(())
     }
 }
 
+struct UnwatchHandler {
+    ecosystems: Arc<Ecosystems>,
+    watchlist: Watchlist,
+}
+
+impl CommandHandler for UnwatchHandler {
+    fn handle(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error> {
+        let q = match EcosystemQuery::parse(cmd.query) {
+            Some(q) => q,
+            None => {
+                let msg = "Usage: /unwatch &lt;ecosystem&gt; &lt;name&gt;";
+                return InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, msg);
+            }
+        };
+
+        let scope = Scope::new(ctx.uid, ctx.chat_id);
+        let ecosystem = self.ecosystems.canonical(q.ecosystem);
+        let msg = if try!(self.watchlist.unwatch(scope, ecosystem, q.query)) {
+            format!("Stopped watching {}", q.query)
+        } else {
+            format!("{} is not watched", q.query)
+        };
+
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, &html::html_escape(&msg))
+    }
+}
+
+struct WatchingHandler {
+    watchlist: Watchlist,
+}
+
+impl CommandHandler for WatchingHandler {
+    fn handle(&self, ctx: &RequestContext, _: &Command) -> Result<(), Error> {
+        let packages = try!(self.watchlist.packages(Scope::new(ctx.uid, ctx.chat_id)));
+
+        let msg = if packages.is_empty() {
+            "No watched packages, use /watch &lt;ecosystem&gt; &lt;name&gt;".to_owned()
+        } else {
+            packages.iter()
+                .map(|p| {
+                    html::html_escape(&format!("{} {} {}",
+                                               p.ecosystem,
+                                               p.name,
+                                               p.version.as_ref().map_or("", |v| v.as_ref())))
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        };
+
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, &msg)
+    }
+}
+
+/// Polls the watched packages in the background and announces new releases
+/// in the chats watching them. Lookups go through the repository caches, so
+/// a release may be announced up to a cache lifetime late.
+fn watch_releases(api: Api,
+                  ecosystems: Arc<Ecosystems>,
+                  watchlist: Watchlist,
+                  interval: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+
+            let lookup = |ecosystem: &str, name: &str| {
+                match ecosystems.repo(ecosystem).map(|repo| repo.lookup(name)) {
+                    Some(Ok(pkg)) => pkg,
+                    Some(Err(err)) => {
+                        warn!("checking {} {} failed: {:?}", ecosystem, name, err);
+                        None
+                    }
+                    None => None,
+                }
+            };
+
+            let releases = match watchlist.poll(lookup) {
+                Ok(releases) => releases,
+                Err(err) => {
+                    error!("{:?}", err);
+                    continue;
+                }
+            };
+
+            for release in releases {
+                let chat_id = release.scope.chat_id;
+                if !ecosystems.allows(&release.ecosystem, Some(chat_id)) {
+                    continue;
+                }
+
+                let msg = html::new_release_message(&release.package, &release.previous);
+                let send_result = api.send_message(chat_id,
+                                                   msg,
+                                                   Some(ParseMode::Html),
+                                                   Some(true),
+                                                   None,
+                                                   None);
+                if let Err(err) = send_result {
+                    error!("{:?}", err);
+                }
+            }
+        }
+    });
+}
+
+struct CacheStatsHandler {
+    ecosystems: Arc<Ecosystems>,
+}
+
+impl CacheStatsHandler {
+    fn format_stats(name: &str, stats: &CacheStats) -> String {
+        let lookups = stats.hits + stats.stale_hits + stats.misses;
+        let hit_rate = if lookups > 0 {
+            100.0 * (stats.hits + stats.stale_hits) as f64 / lookups as f64
+        } else {
+            0.0
+        };
+
+        format!("<b>{}</b>: {} hits, {} stale, {} misses, {} evicted ({:.0}% hit rate)",
+                name,
+                stats.hits,
+                stats.stale_hits,
+                stats.misses,
+                stats.evictions,
+                hit_rate)
+    }
+}
+
+impl CommandHandler for CacheStatsHandler {
+    fn handle(&self, ctx: &RequestContext, _: &Command) -> Result<(), Error> {
+        let lines = self.ecosystems
+            .cache_stats(Some(ctx.chat_id))
+            .iter()
+            .map(|&(ref name, ref stats)| CacheStatsHandler::format_stats(name, stats))
+            .collect::<Vec<String>>();
+
+        let msg = if lines.is_empty() {
+            "Caching is disabled".to_owned()
+        } else {
+            lines.join("\n")
+        };
+
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, &msg)
+    }
+}
+
+/// Report on an uploaded dependency file, sent as a paginated message and
+/// as a text file
+trait FileReport: Send + Sync {
+    fn len(&self) -> usize;
+    fn render_page(&self, page: usize) -> String;
+    fn summary(&self) -> String;
+    fn to_text(&self) -> String;
+}
+
+impl FileReport for AuditReport {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn render_page(&self, page: usize) -> String {
+        let (start, end) = paging::page_bounds(self.entries.len(), page);
+        html::audit_page(self, &self.entries[start..end])
+    }
+
+    fn summary(&self) -> String {
+        AuditReport::summary(self)
+    }
+
+    fn to_text(&self) -> String {
+        AuditReport::to_text(self)
+    }
+}
+
+impl FileReport for GoModReport {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn render_page(&self, page: usize) -> String {
+        let (start, end) = paging::page_bounds(self.entries.len(), page);
+        html::go_mod_page(self, &self.entries[start..end])
+    }
+
+    fn summary(&self) -> String {
+        GoModReport::summary(self)
+    }
+
+    fn to_text(&self) -> String {
+        GoModReport::to_text(self)
+    }
+}
+
+/// Audits `Cargo.lock` and `go.mod` files sent to the chat
+pub struct AuditHandler {
+    auditor: Arc<Auditor>,
+    files: FileApi,
+    pool: Arc<ThreadPool>,
+    sessions: SessionStore<Arc<FileReport>>,
+}
+
+impl AuditHandler {
+    fn is_lockfile(name: &str) -> bool {
+        name == "Cargo.lock"
+    }
+
+    fn is_go_mod(name: &str) -> bool {
+        name == "go.mod"
+    }
+
+    fn run_audit(auditor: &Auditor,
+                 files: &FileApi,
+                 file_id: &str,
+                 file_name: &str)
+                 -> Result<Arc<FileReport>, Error> {
+        let data = try!(files.download(file_id, MAX_UPLOAD_SIZE));
+        let data = String::from_utf8_lossy(&data);
+
+        if AuditHandler::is_go_mod(file_name) {
+            let go_mod = try!(gomod::parse_go_mod(&data));
+            Ok(Arc::new(auditor.audit_go_mod(&go_mod)))
+        } else {
+            let packages = try!(audit::parse_lockfile(&data));
+            Ok(Arc::new(auditor.audit(&packages)))
+        }
+    }
+
+    fn send_report(bot_api: &BotApi,
+                   files: &FileApi,
+                   chat_id: Integer,
+                   sessions: &SessionStore<Arc<FileReport>>,
+                   report: Arc<FileReport>)
+                   -> Result<(), Error> {
+        let pages = paging::pages_count(report.len());
+        let markup = paging::keyboard("audit", 0, pages);
+        let sent = try!(bot_api.send_message(chat_id, &report.render_page(0), markup.as_ref()));
+
+        if pages > 1 {
+            sessions.insert(chat_id, sent.message_id, report.clone());
+        }
+
+        files.send_document(chat_id,
+                            AUDIT_REPORT_NAME,
+                            report.to_text().as_bytes(),
+                            &report.summary())
+    }
+
+    fn handle_document(&self, ctx: &RequestContext, document: Document) -> Result<(), Error> {
+        let file_name = match document.file_name {
+            Some(ref name) if AuditHandler::is_lockfile(name) || AuditHandler::is_go_mod(name) => {
+                name.clone()
+            }
+            _ => return Ok(()),
+        };
+
+        if document.file_size.map_or(false, |size| size as u64 > MAX_UPLOAD_SIZE) {
+            return InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, FILE_TOO_LARGE);
+        }
+
+        let api = ctx.bot_ctx.api.clone();
+        let bot_api = ctx.bot_ctx.bot_api.clone();
+        let chat_id = ctx.chat_id;
+        let auditor = self.auditor.clone();
+        let files = self.files.clone();
+        let sessions = self.sessions.clone();
+
+        try!(InfoHandler::send_text(ctx.bot_ctx.api,
+                                    chat_id,
+                                    &format!("Auditing {}…", html::html_escape(&file_name))));
+        self.pool.execute(move || {
+            let audit_result =
+                AuditHandler::run_audit(&auditor, &files, &document.file_id, &file_name);
+
+            let send_result = match audit_result {
+                Ok(report) => {
+                    AuditHandler::send_report(&bot_api, &files, chat_id, &sessions, report)
+                }
+                // Documents sent without a size
+                Err(Error::FileTooLarge(_)) => {
+                    InfoHandler::send_text(&api, chat_id, FILE_TOO_LARGE)
+                }
+                Err(ref err) if err.kind() == ErrorKind::Parse => {
+                    let msg = format!("Can't read {}: {}", file_name, err);
+                    InfoHandler::send_text(&api, chat_id, &html::html_escape(&msg))
+                }
+                Err(ref err) => {
+                    error!("audit of {} failed: {:?}", file_name, err);
+                    InfoHandler::send_text(&api, chat_id, failure_message(err))
+                }
+            };
+            if let Err(err) = send_result {
+                error!("{:?}", err);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl CommandHandler for AuditHandler {
+    fn handle(&self, ctx: &RequestContext, _: &Command) -> Result<(), Error> {
+        let msg = "Send a Cargo.lock file to check it for outdated, yanked and vulnerable crates \
+                   or a go.mod file to find upgrades and retracted versions";
+        InfoHandler::send_text(ctx.bot_ctx.api, ctx.chat_id, msg)
+    }
+
+    fn handle_callback(&self,
+                       ctx: &RequestContext,
+                       callback: &Callback)
+                       -> Result<CallbackAnswer, Error> {
+        let page: usize = match callback.payload.parse() {
+            Ok(page) => page,
+            Err(_) => return Ok(CallbackAnswer::Done),
+        };
+
+        let report = match self.sessions.get(ctx.chat_id, callback.message_id) {
+            Some(report) => report,
+            None => {
+                let msg = "This report has expired, please send the file again";
+                return Ok(CallbackAnswer::Notify(msg.to_owned()));
+            }
+        };
+
+        let pages = paging::pages_count(report.len());
+        if page >= pages {
+            return Ok(CallbackAnswer::Done);
+        }
+
+        let markup = paging::keyboard("audit", page, pages);
+        try!(ctx.bot_ctx.bot_api.edit_message_text(ctx.chat_id,
+                                                   callback.message_id,
+                                                   &report.render_page(page),
+                                                   markup.as_ref()));
+
+        Ok(CallbackAnswer::Done)
+    }
+}
+
+pub struct InlineQueryHandler {
+    ecosystems: Arc<Ecosystems>,
+    pool: Arc<ThreadPool>,
+}
+
+impl InlineQueryHandler {
+    fn new(ecosystems: Arc<Ecosystems>, pool: Arc<ThreadPool>) -> InlineQueryHandler {
+        InlineQueryHandler {
+            ecosystems: ecosystems,
+            pool: pool,
+        }
+    }
+
+    fn handle(&self, ctx: &BotContext, query: InlineQuery) -> Result<(), Error> {
+        let repo = self.ecosystems.find_query(&query.query, None);
+
+        let (repo, search_query) = match repo {
+            Some(found) => found,
+            None => {
+                return ctx.bot_api.answer_inline_query(&query.id, &[], INLINE_CACHE_TIME);
+            }
+        };
+
+        let bot_api = ctx.bot_api.clone();
+        let received_at = Instant::now();
+        let deadline = ctx.settings.inline_deadline;
+
+        self.pool.execute(move || {
+            // The client shows a spinner until the query is answered
+            let (results, cache_time) = match InlineQueryHandler::search_until(repo,
+                                                                               &search_query,
+                                                                               deadline,
+                                                                               received_at) {
+                Some(pkgs) => (InlineQueryHandler::articles(&pkgs), INLINE_CACHE_TIME),
+                None => (Vec::new(), INLINE_FAILURE_CACHE_TIME),
+            };
+
+            if let Err(err) = bot_api.answer_inline_query(&query.id, &results, cache_time) {
+                error!("{:?}", err);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Searches on the calling pool thread, `None` when the search failed,
+    /// the query waited in the queue past the deadline or the answer would
+    /// arrive too late
+    fn search_until(repo: Box<SearchRepo>,
+                    query: &str,
+                    deadline: Duration,
+                    received_at: Instant)
+                    -> Option<Vec<Package>> {
+        if received_at.elapsed() >= deadline {
+            warn!("inline query {:?} waited too long in queue", query);
+            return None;
+        }
+
+        match repo.search(query) {
+            Ok(_) if received_at.elapsed() >= deadline => {
+                warn!("inline search for {:?} did not finish in time", query);
+                None
+            }
+            Ok(pkgs) => Some(pkgs),
+            Err(err) => {
+                error!("{:?}", err);
+                None
+            }
+        }
+    }
+
+    fn articles(packages: &[Package]) -> Vec<InlineQueryResultArticle> {
+        packages.iter()
+            .take(INLINE_RESULTS_LIMIT)
+            .enumerate()
+            .map(|(i, pkg)| {
+                InlineQueryResultArticle {
+                    id: i.to_string(),
+                    title: pkg.name.clone(),
+                    message_text: SearchHandler::prepare_message_text(pkg),
+                    url: pkg.repository.clone(),
+                    description: pkg.description.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
 pub trait Bot {
     fn get_handler<'a>(&'a self, cmd_name: &str) -> Option<&'a CommandHandler>;
 
+    fn get_inline_handler<'a>(&'a self) -> Option<&'a InlineQueryHandler> {
+        None
+    }
+
+    fn get_audit_handler<'a>(&'a self) -> Option<&'a AuditHandler> {
+        None
+    }
+
     fn handle(&self, ctx: &RequestContext, text: &str) -> Result<(), Error> {
         if let Some(cmd) = Command::parse(text) {
             self.handle_cmd(ctx, &cmd)
         } else {
-            Err(Error::General("Not a command".to_owned()))
+            Err(Error::NotCommand(text.to_owned()))
         }
     }
 
     fn handle_cmd(&self, ctx: &RequestContext, cmd: &Command) -> Result<(), Error> {
-        if let Some(ref handler) = self.get_handler(cmd.name) {
-            handler.handle(ctx, cmd)
-        } else {
-            self.send_unrecognized_command(ctx)
+        match self.get_handler(cmd.name) {
+            Some(handler) if handler.allows(ctx.chat_id) => handler.handle(ctx, cmd),
+            _ => self.send_unrecognized_command(ctx),
         }
     }
 
@@ -233,66 +1267,251 @@ pub trait Bot {
         Ok(())
     }
 
-    fn run_api(&self, ctx: &BotContext) {
-        let mut listener = ctx.api.listener(ListeningMethod::LongPoll(None));
+    fn handle_inline(&self, ctx: &BotContext, query: InlineQuery) -> Result<(), Error> {
+        if let Some(handler) = self.get_inline_handler() {
+            handler.handle(ctx, query)
+        } else {
+            Ok(())
+        }
+    }
 
-        let res = listener.listen(|u| {
-            if let Some(m) = u.message {
-                let req_ctx = RequestContext::new(ctx.clone(), m.chat.id(), m.from.id);
+    fn handle_callback(&self, ctx: &BotContext, q: CallbackQuery) -> Result<(), Error> {
+        let answer = match (q.message.as_ref(), q.data.as_ref()) {
+            (Some(m), Some(data)) => {
+                let req_ctx = RequestContext::new(ctx.clone(), m.chat.id(), q.from.id);
 
-                if let MessageType::Text(text) = m.msg {
-                    if let Err(err) = self.handle(&req_ctx, &text) {
-                            error!("{:?}", err);
+                match Callback::parse(data, m.message_id, &q.id) {
+                    Some(ref callback) => {
+                        match self.get_handler(callback.command) {
+                            Some(handler) if handler.allows(req_ctx.chat_id) => {
+                                match handler.handle_callback(&req_ctx, callback) {
+                                    Ok(answer) => answer,
+                                    Err(err) => {
+                                        error!("{:?}", err);
+                                        CallbackAnswer::Notify(failure_message(&err).to_owned())
+                                    }
+                                }
+                            }
+                            _ => CallbackAnswer::Done,
                         }
+                    }
+                    None => CallbackAnswer::Done,
                 }
             }
+            _ => CallbackAnswer::Done,
+        };
 
-            Ok(ListeningAction::Continue)
-        });
+        match answer {
+            CallbackAnswer::Done => try!(ctx.bot_api.answer_callback_query(&q.id, None)),
+            CallbackAnswer::Notify(ref msg) => {
+                try!(ctx.bot_api.answer_callback_query(&q.id, Some(msg.as_str())))
+            }
+            CallbackAnswer::Deferred => {}
+        }
+
+        Ok(())
+    }
 
-        if let Err(e) = res {
-            error!("An error occured: {}", e);
+    fn handle_update(&self, ctx: &BotContext, u: Update) {
+        if let Some(m) = u.message {
+            let req_ctx = RequestContext::new(ctx.clone(), m.chat.id(), m.from.id);
+
+            let handle_result = match m.msg {
+                MessageType::Text(text) => self.handle(&req_ctx, &text),
+                MessageType::File(document) => {
+                    match self.get_audit_handler() {
+                        Some(handler) => handler.handle_document(&req_ctx, document),
+                        None => Ok(()),
+                    }
+                }
+                _ => Ok(()),
+            };
+            if let Err(err) = handle_result {
+                error!("{:?}", err);
+            }
+        } else if let Some(q) = u.inline_query {
+            if let Err(err) = self.handle_inline(ctx, q) {
+                error!("{:?}", err);
+            }
+        } else if let Some(q) = u.callback_query {
+            if let Err(err) = self.handle_callback(ctx, q) {
+                error!("{:?}", err);
+            }
         }
     }
+
+    fn run_api(&self, ctx: &BotContext, signals: &Receiver<Signal>) {
+        let mut offset = 0;
+
+        loop {
+            if let Ok(signal) = signals.try_recv() {
+                info!("received {:?}, shutting down", signal);
+                break;
+            }
+
+            let updates = match ctx.bot_api.get_updates(offset) {
+                Ok(updates) => updates,
+                // Polls without updates may run into a shorter read timeout
+                Err(ref err) if err.kind() == ErrorKind::Timeout => continue,
+                Err(err) => {
+                    error!("An error occured: {}", err);
+                    thread::sleep(Duration::from_secs(POLL_RETRY_DELAY));
+                    continue;
+                }
+            };
+
+            for u in updates {
+                offset = u.update_id + 1;
+                self.handle_update(ctx, u);
+            }
+        }
+    }
+
+    fn run_webhook(&self, ctx: &BotContext, events: Receiver<WebhookEvent>) {
+        for event in events.iter() {
+            match event {
+                WebhookEvent::Update(u) => self.handle_update(ctx, u),
+                WebhookEvent::Shutdown => break,
+            }
+        }
+    }
+}
+
+/// How the bot receives updates
+#[derive(Debug, Clone)]
+pub enum ListenMode {
+    LongPoll,
+    Webhook(WebhookConfig),
 }
 
 pub struct PkgsBot {
+    settings: BotConfig,
     api: Api,
-    botan: Botan,
+    bot_api: BotApi,
+    analytics: Arc<AnalyticsSink>,
     handlers: BTreeMap<String, Box<CommandHandler>>,
+    inline_handler: InlineQueryHandler,
+    audit_handler: AuditHandler,
 }
 
 impl PkgsBot {
     pub fn new(telegram_token: &str,
-               botanio_token: &str,
-               repos: Vec<(&str, Box<SearchRepo>)>)
+               settings: BotConfig,
+               analytics: Arc<AnalyticsSink>,
+               state: State,
+               ecosystems: Vec<Ecosystem>,
+               auditor: Auditor)
                -> Result<PkgsBot, Error> {
         let api = try!(Api::from_token(telegram_token));
-        let botan = Botan::new(botanio_token);
         let mut handlers: BTreeMap<String, Box<CommandHandler>> = BTreeMap::new();
-        let pool = Arc::new(ThreadPool::new(SEARCH_WORKERS_COUNT));
+        let pool = Arc::new(ThreadPool::new(settings.workers));
+        let sessions = SessionStore::new();
+        let history = History::new(state.clone());
 
-        for repo in repos.into_iter() {
-            let handler = SearchHandler::new(repo.1, pool.clone());
-            handlers.insert(repo.0.to_owned(), Box::new(handler));
+        let mut registered = Ecosystems::new();
+        for ecosystem in &ecosystems {
+            registered.insert(ecosystem);
         }
+        let registered = Arc::new(registered);
+
+        for ecosystem in ecosystems.into_iter() {
+            let handler = SearchHandler::new(&ecosystem.command,
+                                             ecosystem.repo,
+                                             registered.clone(),
+                                             pool.clone(),
+                                             sessions.clone(),
+                                             history.clone());
+            handlers.insert(ecosystem.command, Box::new(handler));
+        }
+
+        let info_handler = InfoHandler::new(registered.clone(), pool.clone());
+        handlers.insert("info".to_owned(), Box::new(info_handler));
+
+        let versions_handler = VersionsHandler::new(registered.clone(), pool.clone());
+        handlers.insert("versions".to_owned(), Box::new(versions_handler));
+
+        let history_handler = HistoryHandler {
+            ecosystems: registered.clone(),
+            pool: pool.clone(),
+            sessions: sessions.clone(),
+            history: history.clone(),
+        };
+        handlers.insert("history".to_owned(), Box::new(history_handler));
+        handlers.insert("forget".to_owned(), Box::new(ForgetHandler { history: history }));
+
+        let watchlist = Watchlist::new(state);
+        let watch_handler = WatchHandler {
+            ecosystems: registered.clone(),
+            pool: pool.clone(),
+            watchlist: watchlist.clone(),
+        };
+        handlers.insert("watch".to_owned(), Box::new(watch_handler));
+        let unwatch_handler = UnwatchHandler {
+            ecosystems: registered.clone(),
+            watchlist: watchlist.clone(),
+        };
+        handlers.insert("unwatch".to_owned(), Box::new(unwatch_handler));
+        handlers.insert("watching".to_owned(),
+                        Box::new(WatchingHandler { watchlist: watchlist.clone() }));
+        watch_releases(api.clone(),
+                       registered.clone(),
+                       watchlist,
+                       settings.watch_interval);
+
+        let cache_stats_handler = CacheStatsHandler { ecosystems: registered.clone() };
+        handlers.insert("cachestats".to_owned(), Box::new(cache_stats_handler));
+
+        let audit_handler = AuditHandler {
+            auditor: Arc::new(auditor),
+            files: FileApi::new(telegram_token),
+            pool: pool.clone(),
+            sessions: SessionStore::new(),
+        };
 
         Ok(PkgsBot {
+            settings: settings,
             api: api,
-            botan: botan,
+            bot_api: BotApi::new(telegram_token),
+            analytics: analytics,
             handlers: handlers,
+            inline_handler: InlineQueryHandler::new(registered, pool),
+            audit_handler: audit_handler,
         })
     }
 
-    pub fn run(&self) {
-        let ctx = BotContext::new(&self.api, &self.botan);
-        self.run_api(&ctx);
+    /// Handles updates until one of `signals` arrives, see `webhook::watch_signals`
+    pub fn run(&self, mode: &ListenMode, signals: Receiver<Signal>) -> Result<(), Error> {
+        let ctx = BotContext::new(&self.api, &self.bot_api, &self.analytics, &self.settings);
+
+        match *mode {
+            ListenMode::LongPoll => self.run_api(&ctx, &signals),
+            ListenMode::Webhook(ref config) => {
+                let (server, events) = try!(WebhookServer::start(self.bot_api.clone(),
+                                                                 config,
+                                                                 signals));
+                self.run_webhook(&ctx, events);
+                try!(server.stop());
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl Bot for PkgsBot {
     fn get_handler<'a>(&'a self, cmd_name: &str) -> Option<&'a CommandHandler> {
-        self.handlers.get(cmd_name).map(|b| b.deref())
+        match cmd_name {
+            "audit" => Some(&self.audit_handler),
+            _ => self.handlers.get(cmd_name).map(|b| b.deref()),
+        }
+    }
+
+    fn get_inline_handler<'a>(&'a self) -> Option<&'a InlineQueryHandler> {
+        Some(&self.inline_handler)
+    }
+
+    fn get_audit_handler<'a>(&'a self) -> Option<&'a AuditHandler> {
+        Some(&self.audit_handler)
     }
 }
 
@@ -322,4 +1541,88 @@ fn test_parse_invalid_command() {
     for sample in &data {
         assert!(Command::parse(sample).is_none());
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_parse_inline_query() {
+    let data = vec![
+        ("rust serde", "rust", "serde"),
+        ("  go   http router ", "go", "http router")];
+
+    for sample in &data {
+        let search = EcosystemQuery::parse(sample.0).unwrap();
+        assert_eq!(search.ecosystem, sample.1);
+        assert_eq!(search.query, sample.2);
+    }
+
+    assert!(EcosystemQuery::parse("rust").is_none());
+    assert!(EcosystemQuery::parse("rust   ").is_none());
+    assert!(EcosystemQuery::parse("").is_none());
+}
+
+#[test]
+fn test_parse_callback() {
+    let callback = Callback::parse("rust:2", 42, "q1").unwrap();
+    assert_eq!(callback.command, "rust");
+    assert_eq!(callback.payload, "2");
+    assert_eq!(callback.message_id, 42);
+    assert_eq!(callback.query_id, "q1");
+
+    assert!(Callback::parse("rust", 42, "q1").is_none());
+    assert!(Callback::parse(":2", 42, "q1").is_none());
+}
+
+#[test]
+fn test_ecosystems_allow_list() {
+    let mut ecosystems = Ecosystems::new();
+    ecosystems.insert(&Ecosystem::public("rust", Box::new(::npm::NpmRepo::npmjs())));
+    ecosystems.insert(&Ecosystem::private("ourcrates",
+                                          Box::new(::npm::NpmRepo::npmjs()),
+                                          vec![-100]));
+
+    assert!(ecosystems.allows("rust", None));
+    assert!(ecosystems.allows("ourcrates", Some(-100)));
+    assert!(!ecosystems.allows("ourcrates", Some(42)));
+    assert!(!ecosystems.allows("ourcrates", None));
+
+    assert!(ecosystems.find_query("ourcrates tokio", Some(-100)).is_some());
+    assert!(ecosystems.find_query("ourcrates tokio", Some(42)).is_none());
+    assert_eq!(ecosystems.names(Some(42)), vec!["rust"]);
+    assert_eq!(ecosystems.names(Some(-100)), vec!["ourcrates", "rust"]);
+}
+
+#[test]
+fn test_ecosystem_aliases_share_watches() {
+    let mut ecosystems = Ecosystems::new();
+    let npm = Ecosystem::public("npm", Box::new(::npm::NpmRepo::npmjs()));
+    let js = Ecosystem { command: "js".to_owned(), ..Ecosystem::public("npm", npm.repo.clone()) };
+    ecosystems.insert(&npm);
+    ecosystems.insert(&js);
+
+    assert_eq!(ecosystems.canonical("js"), "npm");
+    assert_eq!(ecosystems.canonical("npm"), "npm");
+    assert_eq!(ecosystems.canonical("pypi"), "pypi");
+}
+
+#[test]
+fn test_format_cache_stats() {
+    let stats = CacheStats {
+        hits: 6,
+        stale_hits: 2,
+        misses: 2,
+        evictions: 1,
+    };
+
+    assert_eq!(CacheStatsHandler::format_stats("rust", &stats),
+               "<b>rust</b>: 6 hits, 2 stale, 2 misses, 1 evicted (80% hit rate)");
+}
+
+#[test]
+fn test_audited_file_names() {
+    assert!(AuditHandler::is_lockfile("Cargo.lock"));
+    assert!(!AuditHandler::is_lockfile("yarn.lock"));
+    assert!(!AuditHandler::is_lockfile("Gemfile.lock"));
+    assert!(AuditHandler::is_go_mod("go.mod"));
+    assert!(!AuditHandler::is_go_mod("notgo.mod"));
+    assert!(!AuditHandler::is_go_mod("cargo.mod"));
+}
@@ -13,20 +13,22 @@
 use std::io::Read;
 
 use hyper::Url;
-use hyper::client::{Client, IntoUrl};
+use hyper::client::IntoUrl;
 use hyper::header::ContentType;
 use hyper::error::Error as HyperError;
 use url::ParseError as UrlError;
 use rustc_serialize::Encodable;
 use rustc_serialize::json::{encode, decode};
+use http::{HttpClient, WithHttp};
 
 const URL: &'static str = "https://api.botan.io/track";
 
 /// An entry point to the botan.io API
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone)]
 pub struct Botan {
     url: Url,
     token: String,
+    http: HttpClient,
 }
 
 quick_error! {
@@ -62,6 +64,7 @@ impl Botan {
         Botan {
             url: URL.into_url().unwrap(),
             token: token.to_string(),
+            http: HttpClient::shared(),
         }
     }
 
@@ -80,7 +83,7 @@ impl Botan {
     ///   * message: Any object that can be encoded to json
     pub fn track<M: Encodable>(&self, uid: i64, name: &str, message: &M) -> Result<(), BotanError> {
         let request = try!(self.prepare_request(uid, name, message));
-        let response = try!(Self::make_request(request));
+        let response = try!(self.make_request(request));
         Self::treat_response(response)
     }
 
@@ -104,11 +107,10 @@ impl Botan {
         })
     }
 
-    fn make_request(request: BotanRequest) -> Result<BotanResponse, BotanError> {
-        let client = Client::new();
-
+    fn make_request(&self, request: BotanRequest) -> Result<BotanResponse, BotanError> {
         let mut response_string = String::new();
-        try!(client.post(request.url)
+        try!(self.http
+            .post(request.url)
             .header(ContentType::json())
             .body(&request.body)
             .send()
@@ -140,7 +142,59 @@ impl Botan {
     }
 }
 
+impl WithHttp for Botan {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
 #[test]
 fn test_is_default_url_correct() {
     assert!(URL.into_url().is_ok())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_track() {
+    use std::io::ErrorKind;
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use replay::{fixture_client, ReplayTransport};
+
+    let botan = Botan::new("test-token").with_http(fixture_client("fixtures/botan"));
+    assert!(botan.track(42, "search", &vec!["express"]).is_ok());
+
+    let url = |uid: i64| {
+        format!("https://api.botan.io/track?token=test-token&uid={}&name=search", uid)
+    };
+    let (transport, http) = ReplayTransport::new()
+        .respond(Method::Post, &url(1), StatusCode::Ok, "{\"status\": \"failed\"}")
+        .respond(Method::Post,
+                 &url(2),
+                 StatusCode::Ok,
+                 "{\"status\": \"bad request\", \"info\": \"invalid uid\"}")
+        .respond(Method::Post, &url(3), StatusCode::BadGateway, "<html>Bad gateway</html>")
+        .fail(Method::Post, &url(4), ErrorKind::ConnectionReset)
+        .into_client();
+    let botan = Botan::new("test-token").with_http(http);
+
+    match botan.track(1, "search", &vec!["express"]) {
+        Err(BotanError::Failed) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    match botan.track(2, "search", &vec!["express"]) {
+        Err(BotanError::BadRequest(ref info)) => assert_eq!(info, "invalid uid"),
+        other => panic!("unexpected result {:?}", other),
+    }
+    match botan.track(3, "search", &vec!["express"]) {
+        Err(BotanError::JsonDecoding(ref body)) => assert_eq!(body, "<html>Bad gateway</html>"),
+        other => panic!("unexpected result {:?}", other),
+    }
+    match botan.track(4, "search", &vec!["express"]) {
+        Err(BotanError::Http(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let requests = transport.requests();
+    assert_eq!(requests[0].body, b"[\"express\"]");
+    assert_eq!(requests[0].headers.get::<ContentType>(), Some(&ContentType::json()));
+}
@@ -0,0 +1,353 @@
+//! Bot API methods and update types missing from the telegram_bot crate:
+//! inline queries, callback queries and inline keyboards. Messages are still
+//! decoded with the crate's types.
+
+use std::io::Read;
+use hyper::header::ContentType;
+use rustc_serialize::{Decodable, Encodable, Encoder};
+use rustc_serialize::json;
+use telegram_bot::types::{Integer, Message, User};
+use url::form_urlencoded;
+use error::Error;
+use http::{HttpClient, WithHttp};
+
+pub const TELEGRAM_API_ENDPOINT: &'static str = "https://api.telegram.org";
+/// Kept below the default HTTP read timeout, Telegram answers earlier as
+/// soon as there are updates
+pub const LONG_POLL_TIMEOUT: Integer = 10;
+const ALLOWED_UPDATES: &'static str = r#"["message","inline_query","callback_query"]"#;
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct Update {
+    pub update_id: Integer,
+    pub message: Option<Message>,
+    pub inline_query: Option<InlineQuery>,
+    pub callback_query: Option<CallbackQuery>,
+}
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct InlineQuery {
+    pub id: String,
+    pub from: User,
+    pub query: String,
+    pub offset: String,
+}
+
+#[derive(RustcDecodable, Debug, Clone)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    /// Missing when the message is too old
+    pub message: Option<Message>,
+    pub data: Option<String>,
+}
+
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    pub callback_data: String,
+}
+
+/// Buttons shown under a message, one inner vector per row
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+/// Inline result sending an HTML message when picked
+#[derive(Debug, Clone)]
+pub struct InlineQueryResultArticle {
+    pub id: String,
+    pub title: String,
+    pub message_text: String,
+    pub url: Option<String>,
+    pub description: Option<String>,
+}
+
+// Encoded by hand because of the `type` field and the nested message content
+impl Encodable for InlineQueryResultArticle {
+    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        e.emit_struct("InlineQueryResultArticle", 6, |e| {
+            try!(e.emit_struct_field("type", 0, |e| "article".encode(e)));
+            try!(e.emit_struct_field("id", 1, |e| self.id.encode(e)));
+            try!(e.emit_struct_field("title", 2, |e| self.title.encode(e)));
+            try!(e.emit_struct_field("input_message_content", 3, |e| {
+                e.emit_struct("InputTextMessageContent", 3, |e| {
+                    try!(e.emit_struct_field("message_text", 0, |e| self.message_text.encode(e)));
+                    try!(e.emit_struct_field("parse_mode", 1, |e| "HTML".encode(e)));
+                    e.emit_struct_field("disable_web_page_preview", 2, |e| true.encode(e))
+                })
+            }));
+            if let Some(ref url) = self.url {
+                try!(e.emit_struct_field("url", 4, |e| url.encode(e)));
+            }
+            if let Some(ref description) = self.description {
+                try!(e.emit_struct_field("description", 5, |e| description.encode(e)));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[derive(RustcDecodable)]
+struct ApiResponse<T> {
+    ok: bool,
+    error_code: Option<Integer>,
+    description: Option<String>,
+    result: Option<T>,
+}
+
+/// Decodes the answer to a Bot API call, failures keep Telegram's error code
+pub fn decode_response<T: Decodable>(method: &str, data: &str) -> Result<T, Error> {
+    let resp: ApiResponse<T> = try!(json::decode(data));
+    match resp.result {
+        Some(result) if resp.ok => Ok(result),
+        _ => {
+            Err(Error::TelegramApi(method.to_owned(),
+                                   resp.error_code,
+                                   resp.description.unwrap_or(String::new())))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BotApi {
+    token: String,
+    http: HttpClient,
+}
+
+impl BotApi {
+    pub fn new(token: &str) -> BotApi {
+        BotApi {
+            token: token.to_owned(),
+            http: HttpClient::shared(),
+        }
+    }
+
+    /// Waits up to `LONG_POLL_TIMEOUT` seconds for updates after `offset`
+    pub fn get_updates(&self, offset: Integer) -> Result<Vec<Update>, Error> {
+        self.call("getUpdates",
+                  &[("offset", &offset.to_string()),
+                    ("timeout", &LONG_POLL_TIMEOUT.to_string()),
+                    ("allowed_updates", ALLOWED_UPDATES)])
+    }
+
+    /// Sends an HTML message without link previews
+    pub fn send_message(&self,
+                        chat_id: Integer,
+                        text: &str,
+                        markup: Option<&InlineKeyboardMarkup>)
+                        -> Result<Message, Error> {
+        let chat_id = chat_id.to_string();
+        let markup = try!(BotApi::encode_markup(markup));
+        self.call("sendMessage", &BotApi::text_params(&chat_id, None, text, &markup))
+    }
+
+    /// Replaces the text and the buttons of a message sent by the bot
+    pub fn edit_message_text(&self,
+                             chat_id: Integer,
+                             message_id: Integer,
+                             text: &str,
+                             markup: Option<&InlineKeyboardMarkup>)
+                             -> Result<(), Error> {
+        let chat_id = chat_id.to_string();
+        let message_id = message_id.to_string();
+        let markup = try!(BotApi::encode_markup(markup));
+        let params = BotApi::text_params(&chat_id, Some(&message_id), text, &markup);
+        let _: Message = try!(self.call("editMessageText", &params));
+
+        Ok(())
+    }
+
+    /// Stops the loading animation of the pressed button, `text` is shown
+    /// to the user as a notification
+    pub fn answer_callback_query(&self,
+                                 callback_query_id: &str,
+                                 text: Option<&str>)
+                                 -> Result<(), Error> {
+        let mut params = vec![("callback_query_id", callback_query_id)];
+        if let Some(text) = text {
+            params.push(("text", text));
+        }
+        let _: bool = try!(self.call("answerCallbackQuery", &params));
+
+        Ok(())
+    }
+
+    pub fn answer_inline_query(&self,
+                               inline_query_id: &str,
+                               results: &[InlineQueryResultArticle],
+                               cache_time: Integer)
+                               -> Result<(), Error> {
+        let results = try!(json::encode(&results));
+        let _: bool = try!(self.call("answerInlineQuery",
+                                     &[("inline_query_id", inline_query_id),
+                                       ("results", &results),
+                                       ("cache_time", &cache_time.to_string())]));
+
+        Ok(())
+    }
+
+    fn encode_markup(markup: Option<&InlineKeyboardMarkup>) -> Result<Option<String>, Error> {
+        match markup {
+            Some(markup) => Ok(Some(try!(json::encode(markup)))),
+            None => Ok(None),
+        }
+    }
+
+    fn text_params<'a>(chat_id: &'a str,
+                       message_id: Option<&'a str>,
+                       text: &'a str,
+                       markup: &'a Option<String>)
+                       -> Vec<(&'a str, &'a str)> {
+        let mut params = vec![("chat_id", chat_id),
+                              ("text", text),
+                              ("parse_mode", "HTML"),
+                              ("disable_web_page_preview", "true")];
+        if let Some(message_id) = message_id {
+            params.push(("message_id", message_id));
+        }
+        if let Some(ref markup) = *markup {
+            params.push(("reply_markup", markup));
+        }
+
+        params
+    }
+
+    /// Calls the method with form encoded parameters
+    pub fn call<T: Decodable>(&self, method: &str, params: &[(&str, &str)]) -> Result<T, Error> {
+        let url = format!("{}/bot{}/{}", TELEGRAM_API_ENDPOINT, self.token, method);
+        let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+
+        let mut resp = try!(self.http
+            .post(&url)
+            .header(ContentType::form_url_encoded())
+            .body(&body)
+            .send());
+
+        let mut data = String::new();
+        try!(resp.read_to_string(&mut data));
+
+        decode_response(method, &data)
+    }
+}
+
+impl WithHttp for BotApi {
+    fn http_mut(&mut self) -> &mut HttpClient {
+        &mut self.http
+    }
+}
+
+#[cfg(test)]
+fn params(request: &::http::HttpRequest) -> Vec<(String, String)> {
+    form_urlencoded::parse(&request.body).into_owned().collect()
+}
+
+#[test]
+fn test_get_updates() {
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use replay::ReplayTransport;
+
+    let (transport, http) = ReplayTransport::new()
+        .respond(Method::Post,
+                 "https://api.telegram.org/botTOKEN/getUpdates",
+                 StatusCode::Ok,
+                 include_str!("../../fixtures/telegram/updates.json"))
+        .into_client();
+    let api = BotApi::new("TOKEN").with_http(http);
+
+    let updates = api.get_updates(7).unwrap();
+    assert_eq!(updates.len(), 3);
+    assert!(updates[0].message.is_some());
+
+    let query = updates[1].inline_query.as_ref().unwrap();
+    assert_eq!(query.query, "rust serde");
+    assert_eq!(query.from.id, 42);
+
+    let callback = updates[2].callback_query.as_ref().unwrap();
+    assert_eq!(callback.data.as_ref().unwrap(), "rust:1");
+    assert_eq!(callback.message.as_ref().unwrap().message_id, 100);
+
+    let sent = params(&transport.requests()[0]);
+    assert!(sent.contains(&("offset".to_owned(), "7".to_owned())));
+}
+
+#[test]
+fn test_answer_inline_query() {
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use rustc_serialize::json::Json;
+    use replay::ReplayTransport;
+
+    let url = "https://api.telegram.org/botTOKEN/answerInlineQuery";
+    let (transport, http) = ReplayTransport::new()
+        .respond(Method::Post, url, StatusCode::Ok, r#"{"ok":true,"result":true}"#)
+        .into_client();
+    let api = BotApi::new("TOKEN").with_http(http);
+
+    let article = InlineQueryResultArticle {
+        id: "0".to_owned(),
+        title: "serde".to_owned(),
+        message_text: "<b>serde</b>".to_owned(),
+        url: None,
+        description: Some("A serialization framework".to_owned()),
+    };
+    api.answer_inline_query("q1", &[article], 300).unwrap();
+
+    let sent = params(&transport.requests()[0]);
+    let results = &sent.iter().find(|&&(ref k, _)| k == "results").unwrap().1;
+    let results = Json::from_str(results).unwrap();
+    let article = &results.as_array().unwrap()[0];
+    assert_eq!(article.find("type").unwrap().as_string(), Some("article"));
+    assert_eq!(article.find_path(&["input_message_content", "parse_mode"])
+                   .unwrap()
+                   .as_string(),
+               Some("HTML"));
+    assert!(article.find("url").is_none());
+
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Post,
+                 url,
+                 StatusCode::BadRequest,
+                 r#"{"ok":false,"error_code":400,"description":"query is too old"}"#)
+        .into_client();
+    let api = BotApi::new("TOKEN").with_http(http);
+    let err = api.answer_inline_query("q1", &[], 300).unwrap_err();
+    assert_eq!(err.to_string(), "answerInlineQuery failed: query is too old");
+    match err {
+        Error::TelegramApi(_, code, _) => assert_eq!(code, Some(400)),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn test_send_message_with_keyboard() {
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use replay::ReplayTransport;
+
+    let (transport, http) = ReplayTransport::new()
+        .respond(Method::Post,
+                 "https://api.telegram.org/botTOKEN/sendMessage",
+                 StatusCode::Ok,
+                 include_str!("../../fixtures/telegram/sent_message.json"))
+        .into_client();
+    let api = BotApi::new("TOKEN").with_http(http);
+
+    let markup = InlineKeyboardMarkup {
+        inline_keyboard: vec![vec![InlineKeyboardButton {
+                                       text: "Next »".to_owned(),
+                                       callback_data: "rust:1".to_owned(),
+                                   }]],
+    };
+    let sent = api.send_message(42, "<b>serde</b>", Some(&markup)).unwrap();
+    assert_eq!(sent.message_id, 100);
+
+    let sent = params(&transport.requests()[0]);
+    assert!(sent.contains(&("parse_mode".to_owned(), "HTML".to_owned())));
+    assert!(sent.contains(&("reply_markup".to_owned(),
+                            r#"{"inline_keyboard":[[{"text":"Next »","callback_data":"rust:1"}]]}"#
+                                .to_owned())));
+}
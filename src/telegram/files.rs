@@ -0,0 +1,138 @@
+//! File transfers: downloading documents sent to the bot and uploading
+//! documents, neither of which the telegram_bot crate supports.
+
+use std::io::Read;
+use hyper::header::ContentType;
+use telegram_bot::types::Integer;
+use telegram::botapi::{self, TELEGRAM_API_ENDPOINT};
+use error::{self, Error};
+use http::HttpClient;
+
+const MULTIPART_BOUNDARY: &'static str = "packagesbot-document-boundary";
+
+#[derive(RustcDecodable)]
+struct SentMessage {
+    #[allow(dead_code)]
+    message_id: Integer,
+}
+
+#[derive(RustcDecodable)]
+struct FileInfo {
+    file_path: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct FileApi {
+    token: String,
+    http: HttpClient,
+}
+
+impl FileApi {
+    pub fn new(token: &str) -> FileApi {
+        FileApi {
+            token: token.to_owned(),
+            http: HttpClient::shared(),
+        }
+    }
+
+    /// Downloads a file sent to the bot, files larger than `max_size` bytes
+    /// fail with `Error::FileTooLarge`
+    pub fn download(&self, file_id: &str, max_size: u64) -> Result<Vec<u8>, Error> {
+        let url = format!("{}/bot{}/getFile?file_id={}",
+                          TELEGRAM_API_ENDPOINT,
+                          self.token,
+                          file_id);
+
+        let mut data = String::new();
+        try!(try!(self.http.get(&url).send()).read_to_string(&mut data));
+
+        let info: FileInfo = try!(botapi::decode_response("getFile", &data));
+        let path = match info.file_path {
+            Some(path) => path,
+            None => {
+                return Err(Error::TelegramApi("getFile".to_owned(),
+                                              None,
+                                              "no file path".to_owned()))
+            }
+        };
+
+        let url = format!("{}/file/bot{}/{}", TELEGRAM_API_ENDPOINT, self.token, path);
+        let resp = try!(self.http.get(&url).send());
+        try!(error::check_status(resp.status, "telegram files"));
+
+        let mut contents = Vec::new();
+        try!(resp.take(max_size + 1).read_to_end(&mut contents));
+        if contents.len() as u64 > max_size {
+            return Err(Error::FileTooLarge(max_size));
+        }
+
+        Ok(contents)
+    }
+
+    /// Sends `contents` to the chat as a document named `file_name`
+    pub fn send_document(&self,
+                         chat_id: Integer,
+                         file_name: &str,
+                         contents: &[u8],
+                         caption: &str)
+                         -> Result<(), Error> {
+        let url = format!("{}/bot{}/sendDocument", TELEGRAM_API_ENDPOINT, self.token);
+        let body = multipart_body(&[("chat_id", &chat_id.to_string()), ("caption", caption)],
+                                  "document",
+                                  file_name,
+                                  contents);
+        let content_type = format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY);
+
+        let mut resp = try!(self.http
+            .post(&url)
+            .header(ContentType(content_type.parse().unwrap()))
+            .body(&body[..])
+            .send());
+
+        let mut data = String::new();
+        try!(resp.read_to_string(&mut data));
+
+        try!(botapi::decode_response::<SentMessage>("sendDocument", &data));
+        Ok(())
+    }
+}
+
+fn multipart_body(fields: &[(&str, &str)],
+                  file_field: &str,
+                  file_name: &str,
+                  contents: &[u8])
+                  -> Vec<u8> {
+    let mut body = Vec::new();
+
+    for &(name, value) in fields {
+        body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                            MULTIPART_BOUNDARY,
+                            name,
+                            value)
+            .bytes());
+    }
+
+    body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"; \
+                         filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                        MULTIPART_BOUNDARY,
+                        file_field,
+                        file_name.replace('"', ""))
+        .bytes());
+    body.extend_from_slice(contents);
+    body.extend(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).bytes());
+
+    body
+}
+
+#[test]
+fn test_multipart_body() {
+    let body = multipart_body(&[("chat_id", "42")], "document", "audit.txt", b"ok");
+
+    assert_eq!(String::from_utf8(body).unwrap(),
+               "--packagesbot-document-boundary\r\n\
+                Content-Disposition: form-data; name=\"chat_id\"\r\n\r\n42\r\n\
+                --packagesbot-document-boundary\r\n\
+                Content-Disposition: form-data; name=\"document\"; filename=\"audit.txt\"\r\n\
+                Content-Type: application/octet-stream\r\n\r\n\
+                ok\r\n--packagesbot-document-boundary--\r\n");
+}
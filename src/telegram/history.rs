@@ -0,0 +1,148 @@
+use telegram::botapi::InlineKeyboardMarkup;
+use telegram::paging;
+use state::{Scope, State};
+use error::Error;
+
+/// Number of queries remembered per user and chat
+pub const HISTORY_LIMIT: usize = 10;
+const HISTORY_KEY: &'static str = "history";
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct HistoryEntry {
+    pub ecosystem: String,
+    pub query: String,
+}
+
+impl HistoryEntry {
+    /// FNV-1a hash of the ecosystem and the query, identifies the entry in
+    /// callback data while `record` reorders the list
+    pub fn id(&self) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for b in self.ecosystem.bytes().chain(Some(b' ')).chain(self.query.bytes()) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        format!("{:016x}", hash)
+    }
+}
+
+/// Recent searches of every user, the newest first
+#[derive(Clone)]
+pub struct History {
+    state: State,
+}
+
+impl History {
+    pub fn new(state: State) -> History {
+        History { state: state }
+    }
+
+    /// Puts the query on top, repeated queries are moved instead of duplicated
+    pub fn record(&self, uid: i64, cid: i64, ecosystem: &str, query: &str) -> Result<(), Error> {
+        let entry = HistoryEntry {
+            ecosystem: ecosystem.to_owned(),
+            query: query.to_owned(),
+        };
+
+        let mut entries = try!(self.entries(uid, cid));
+        entries.retain(|e| *e != entry);
+        entries.insert(0, entry);
+        entries.truncate(HISTORY_LIMIT);
+
+        self.state.save(Scope::new(uid, cid), HISTORY_KEY, &entries)
+    }
+
+    pub fn entries(&self, uid: i64, cid: i64) -> Result<Vec<HistoryEntry>, Error> {
+        let entries: Option<Vec<HistoryEntry>> =
+            try!(self.state.load(Scope::new(uid, cid), HISTORY_KEY));
+        Ok(entries.unwrap_or(Vec::new()))
+    }
+
+    /// Wipes the history of the user in all chats
+    pub fn forget(&self, uid: i64) -> Result<(), Error> {
+        let scopes = try!(self.state.scan::<Vec<HistoryEntry>>(HISTORY_KEY));
+
+        for (scope, _) in scopes.into_iter().filter(|&(scope, _)| scope.uid == uid) {
+            try!(self.state.remove(scope, HISTORY_KEY));
+        }
+
+        Ok(())
+    }
+}
+
+/// One button per entry re-running the search, callback data is
+/// `history:<uid>:<entry id>` so that other users in the chat can't use it
+pub fn keyboard(uid: i64, entries: &[HistoryEntry]) -> InlineKeyboardMarkup {
+    let rows = entries.iter()
+        .map(|e| {
+            let text = format!("/{} {}", e.ecosystem, e.query);
+            vec![paging::button(&text, &format!("history:{}:{}", uid, e.id()))]
+        })
+        .collect();
+
+    InlineKeyboardMarkup { inline_keyboard: rows }
+}
+
+/// Parses the `<uid>:<entry id>` callback payload
+pub fn parse_payload(payload: &str) -> Option<(i64, &str)> {
+    let mut parts = payload.splitn(2, ':');
+
+    match (parts.next().map(|p| p.parse()), parts.next()) {
+        (Some(Ok(uid)), Some(id)) if !id.is_empty() => Some((uid, id)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_history_record() {
+    let history = History::new(::state::StateConfig::Memory.open().unwrap());
+
+    for query in &["serde", "tokio", "serde"] {
+        history.record(1, 10, "rust", query).unwrap();
+    }
+    history.record(1, 10, "go", "mux").unwrap();
+
+    let entries = history.entries(1, 10).unwrap();
+    let queries = entries.iter().map(|e| e.query.as_ref()).collect::<Vec<&str>>();
+    assert_eq!(queries, vec!["mux", "serde", "tokio"]);
+    assert!(history.entries(1, 20).unwrap().is_empty());
+
+    for i in 0..(HISTORY_LIMIT + 5) {
+        history.record(1, 10, "npm", &format!("pkg{}", i)).unwrap();
+    }
+    assert_eq!(history.entries(1, 10).unwrap().len(), HISTORY_LIMIT);
+
+    history.forget(1).unwrap();
+    assert!(history.entries(1, 10).unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_payload() {
+    assert_eq!(parse_payload("42:5d6e1f0a2b3c4d5e"), Some((42, "5d6e1f0a2b3c4d5e")));
+    assert_eq!(parse_payload("42"), None);
+    assert_eq!(parse_payload("42:"), None);
+    assert_eq!(parse_payload("x:5d6e1f0a2b3c4d5e"), None);
+}
+
+#[test]
+fn test_entry_id_is_stable() {
+    let history = History::new(::state::StateConfig::Memory.open().unwrap());
+    history.record(1, 10, "rust", "serde").unwrap();
+    history.record(1, 10, "rust", "tokio").unwrap();
+
+    let serde = history.entries(1, 10).unwrap()[1].clone();
+    let id = serde.id();
+    assert_eq!(id.len(), 16);
+
+    history.record(1, 10, "rust", "serde").unwrap();
+    let entries = history.entries(1, 10).unwrap();
+    assert_eq!(entries[0].id(), id);
+    assert!(entries[1].id() != id);
+
+    let other = HistoryEntry {
+        ecosystem: "rus".to_owned(),
+        query: "t serde".to_owned(),
+    };
+    assert!(other.id() != id);
+}
@@ -1,4 +1,11 @@
+use {Package, Release};
+use audit::{AuditEntry, AuditReport, Finding};
+use gomod::{GoFinding, GoModEntry, GoModReport};
+
 const LONGEST_ESCAPE: usize = 6;
+const ELLIPSIS: &'static str = "…";
+/// Longest release attribute shown, feature lists of big crates run long
+const MAX_ATTRIBUTE_LENGTH: usize = 300;
 
 struct Escape<I: Iterator<Item = u8>> {
     inner: I,
@@ -49,10 +56,26 @@ impl<I: Iterator<Item = u8>> Iterator for Escape<I> {
 }
 
 
-fn html_escape(s: &str) -> String {
+pub fn html_escape(s: &str) -> String {
     String::from_utf8(Escape::new(s.bytes()).collect()).unwrap()
 }
 
+/// Cuts plain text to at most `max_length` bytes, a cut ends with an ellipsis
+pub fn truncate(text: &str, max_length: usize) -> String {
+    if text.len() <= max_length {
+        return text.to_owned();
+    }
+    if max_length < ELLIPSIS.len() {
+        return String::new();
+    }
+
+    let mut end = max_length - ELLIPSIS.len();
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &text[..end], ELLIPSIS)
+}
+
 struct Html {
     msg: String,
 }
@@ -98,19 +121,86 @@ impl Html {
 
 pub struct HtmlMessageBuilder<'a> {
     name: Option<&'a str>,
+    version: Option<&'a str>,
     repo_url: Option<&'a str>,
     doc_url: Option<&'a str>,
+    homepage: Option<&'a str>,
     description: Option<&'a str>,
+    license: Option<&'a str>,
+    downloads: Option<u64>,
+    updated_at: Option<&'a str>,
+    keywords: Option<&'a [String]>,
+    attributes: Vec<(&'a str, &'a str)>,
 }
 
 impl<'a> HtmlMessageBuilder<'a> {
     pub fn new() -> HtmlMessageBuilder<'a> {
         HtmlMessageBuilder {
             name: None,
+            version: None,
             repo_url: None,
             doc_url: None,
+            homepage: None,
             description: None,
+            license: None,
+            downloads: None,
+            updated_at: None,
+            keywords: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Builder for a short search result entry: name, version, links and description
+    pub fn summary(pkg: &'a Package) -> HtmlMessageBuilder<'a> {
+        let mut msg_builder = HtmlMessageBuilder::new();
+        msg_builder.name(&pkg.name);
+
+        if let Some(ref version) = pkg.version {
+            msg_builder.version(version);
+        }
+
+        if let Some(ref url) = pkg.repository {
+            msg_builder.repo_url(url);
+        }
+
+        if let Some(ref url) = pkg.documentation {
+            msg_builder.doc_url(url);
+        }
+
+        if let Some(ref url) = pkg.homepage {
+            msg_builder.homepage(url);
         }
+
+        if let Some(ref description) = pkg.description {
+            msg_builder.description(description);
+        }
+
+        msg_builder
+    }
+
+    /// Builder for a full package card with every known field
+    pub fn package(pkg: &'a Package) -> HtmlMessageBuilder<'a> {
+        let mut msg_builder = HtmlMessageBuilder::summary(pkg);
+
+        if let Some(ref license) = pkg.license {
+            msg_builder.license(license);
+        }
+
+        if let Some(downloads) = pkg.downloads {
+            msg_builder.downloads(downloads);
+        }
+
+        if let Some(ref updated_at) = pkg.updated_at {
+            msg_builder.updated_at(updated_at);
+        }
+
+        msg_builder.keywords(&pkg.keywords);
+
+        for (key, value) in &pkg.attributes {
+            msg_builder.attribute(key, value);
+        }
+
+        msg_builder
     }
 
     pub fn name(&mut self, n: &'a str) {
@@ -129,11 +219,47 @@ impl<'a> HtmlMessageBuilder<'a> {
         self.description = Some(d);
     }
 
+    pub fn version(&mut self, v: &'a str) {
+        self.version = Some(v);
+    }
+
+    pub fn homepage(&mut self, u: &'a str) {
+        self.homepage = Some(u);
+    }
+
+    pub fn license(&mut self, l: &'a str) {
+        self.license = Some(l);
+    }
+
+    pub fn downloads(&mut self, d: u64) {
+        self.downloads = Some(d);
+    }
+
+    pub fn updated_at(&mut self, t: &'a str) {
+        self.updated_at = Some(t);
+    }
+
+    pub fn keywords(&mut self, k: &'a [String]) {
+        if !k.is_empty() {
+            self.keywords = Some(k);
+        }
+    }
+
+    pub fn attribute(&mut self, key: &'a str, value: &'a str) {
+        self.attributes.push((key, value));
+    }
+
     pub fn build(&self) -> String {
         let mut html = Html::new();
 
         if let Some(name) = self.name {
             html.bold(name);
+
+            if let Some(version) = self.version {
+                html.text(" ");
+                html.italic(version);
+            }
+
             html.text("\n");
         }
 
@@ -145,6 +271,10 @@ impl<'a> HtmlMessageBuilder<'a> {
             html.url("[doc]", doc_url);
         }
 
+        if let Some(homepage) = self.homepage {
+            html.url("[home]", homepage);
+        }
+
         html.text("\n");
 
         if let Some(description) = self.description {
@@ -152,6 +282,361 @@ impl<'a> HtmlMessageBuilder<'a> {
             html.text("\n");
         }
 
+        if let Some(license) = self.license {
+            html.text("License: ");
+            html.text(license);
+            html.text("\n");
+        }
+
+        if let Some(downloads) = self.downloads {
+            html.text(&format!("Downloads: {}\n", downloads));
+        }
+
+        if let Some(updated_at) = self.updated_at {
+            html.text("Updated: ");
+            html.text(updated_at);
+            html.text("\n");
+        }
+
+        if let Some(keywords) = self.keywords {
+            html.text("Keywords: ");
+            html.text(&keywords.join(", "));
+            html.text("\n");
+        }
+
+        for &(key, value) in &self.attributes {
+            html.text(key);
+            html.text(": ");
+            html.text(value);
+            html.text("\n");
+        }
+
         html.message().to_owned()
     }
 }
+
+/// Search result entry of at most `max_length` bytes. A long description is
+/// cut, when even that doesn't help only the name is kept.
+pub fn summary_within(pkg: &Package, max_length: usize) -> String {
+    let text = HtmlMessageBuilder::summary(pkg).build();
+    if text.len() <= max_length {
+        return text;
+    }
+
+    // Escaping never shrinks text, so cutting `overflow` bytes of the
+    // description shortens the message by at least as much
+    let overflow = text.len() - max_length;
+    if let Some(ref description) = pkg.description {
+        let short = truncate(description, description.len().saturating_sub(overflow));
+        let mut builder = HtmlMessageBuilder::summary(pkg);
+        builder.description(&short);
+
+        let text = builder.build();
+        if text.len() <= max_length {
+            return text;
+        }
+    }
+
+    let markup = "<b></b>\n\n".len();
+    let name = truncate(&pkg.name, max_length.saturating_sub(markup) / LONGEST_ESCAPE);
+    let mut builder = HtmlMessageBuilder::new();
+    builder.name(&name);
+    builder.build()
+}
+
+/// Lists up to `limit` of the latest releases, newest first, leaving out the
+/// ones that don't fit into `max_length` bytes
+pub fn releases_message(name: &str,
+                        releases: &[Release],
+                        limit: usize,
+                        max_length: usize)
+                        -> String {
+    let mut html = Html::new();
+    html.bold(&truncate(name, max_length / 2 / LONGEST_ESCAPE));
+    html.text(&format!(" has {} versions\n", releases.len()));
+
+    for release in releases.iter().rev().take(limit) {
+        let mut entry = Html::new();
+        entry.text("\n");
+        entry.bold(&release.version);
+
+        if release.yanked {
+            entry.text(" ");
+            entry.italic("yanked");
+        }
+
+        for (key, value) in &release.attributes {
+            entry.text(&format!("\n  {}: {}", key, truncate(value, MAX_ATTRIBUTE_LENGTH)));
+        }
+
+        // Leaves room for the closing newline
+        if html.message().len() + entry.message().len() + 1 > max_length {
+            break;
+        }
+        html.msg.push_str(entry.message());
+    }
+
+    html.text("\n");
+    html.message().to_owned()
+}
+
+/// Notification about a new version of a watched package
+pub fn new_release_message(pkg: &Package, previous: &str) -> String {
+    let mut html = Html::new();
+    html.bold(&pkg.name);
+    html.text(" ");
+    html.italic(previous);
+    html.text(" → ");
+    html.italic(pkg.version.as_ref().map_or("", |v| v.as_ref()));
+    html.text("\n");
+
+    if let Some(ref repo_url) = pkg.repository {
+        html.url("[repo]", repo_url);
+    }
+
+    if let Some(ref doc_url) = pkg.documentation {
+        html.url("[doc]", doc_url);
+    }
+
+    if let Some(ref homepage) = pkg.homepage {
+        html.url("[home]", homepage);
+    }
+
+    html.text("\n");
+    html.message().to_owned()
+}
+
+/// One page of audit findings below the report summary
+pub fn audit_page(report: &AuditReport, entries: &[AuditEntry]) -> String {
+    let mut html = Html::new();
+    html.bold(&report.summary());
+    html.text("\n");
+
+    for entry in entries {
+        html.text("\n");
+        html.bold(&entry.name);
+        html.text(" ");
+        html.text(&entry.version);
+
+        for finding in &entry.findings {
+            html.text("\n  ");
+            match *finding {
+                Finding::Outdated(ref latest) => {
+                    html.text("latest is ");
+                    html.italic(latest);
+                }
+                Finding::Yanked => {
+                    html.italic("yanked");
+                }
+                Finding::Vulnerable { ref id, ref title, ref url } => {
+                    match *url {
+                        Some(ref url) => html.url(id, url),
+                        None => html.bold(id),
+                    };
+                    html.text(" ");
+                    html.text(title);
+                }
+                Finding::Unchecked(ref reason) => {
+                    html.text("not checked: ");
+                    html.text(reason);
+                }
+            }
+        }
+    }
+
+    html.text("\n");
+    html.message().to_owned()
+}
+
+/// One page of go.mod findings below the report summary
+pub fn go_mod_page(report: &GoModReport, entries: &[GoModEntry]) -> String {
+    let mut html = Html::new();
+    html.bold(&report.summary());
+    html.text("\n");
+
+    for entry in entries {
+        html.text("\n");
+        html.bold(&entry.path);
+        html.text(" ");
+        html.text(&entry.version);
+
+        for finding in &entry.findings {
+            html.text("\n  ");
+            match *finding {
+                GoFinding::Upgrade(ref latest) => {
+                    html.text("upgrade to ");
+                    html.italic(latest);
+                }
+                GoFinding::Retracted(ref rationale) => {
+                    html.italic("retracted");
+                    if let Some(ref rationale) = *rationale {
+                        html.text(": ");
+                        html.text(rationale);
+                    }
+                }
+                GoFinding::MajorVersion(ref path, ref version) => {
+                    html.text("new major version ");
+                    html.bold(path);
+                    html.text(" ");
+                    html.italic(version);
+                }
+                GoFinding::Replaced(ref dir) => {
+                    html.text("replaced by ");
+                    html.text(dir);
+                }
+                GoFinding::Unchecked(ref reason) => {
+                    html.text("not checked: ");
+                    html.text(reason);
+                }
+            }
+        }
+    }
+
+    html.text("\n");
+    html.message().to_owned()
+}
+
+#[test]
+fn test_build_package_card() {
+    let keywords = vec!["serde".to_owned(), "json".to_owned()];
+    let mut builder = HtmlMessageBuilder::new();
+    builder.name("serde_json");
+    builder.version("0.8.0");
+    builder.repo_url("https://github.com/serde-rs/json");
+    builder.license("MIT/Apache-2.0");
+    builder.downloads(1000);
+    builder.keywords(&keywords);
+
+    assert_eq!(builder.build(),
+               "<b>serde_json</b> <i>0.8.0</i>\n\
+                <a href=\"https://github.com/serde-rs/json\">[repo]</a>\n\
+                License: MIT/Apache-2.0\n\
+                Downloads: 1000\n\
+                Keywords: serde, json\n");
+}
+
+#[test]
+fn test_build_package_attributes() {
+    let mut pkg = Package::empty();
+    pkg.name = "github.com/gorilla/mux".to_owned();
+    pkg.attributes.insert("import path".to_owned(), "github.com/gorilla/mux".to_owned());
+
+    assert_eq!(HtmlMessageBuilder::package(&pkg).build(),
+               "<b>github.com/gorilla/mux</b>\n\n\
+                import path: github.com/gorilla/mux\n");
+    assert_eq!(HtmlMessageBuilder::summary(&pkg).build(),
+               "<b>github.com/gorilla/mux</b>\n\n");
+}
+
+#[test]
+fn test_summary_within() {
+    let mut pkg = Package::empty();
+    pkg.name = "serde".to_owned();
+    pkg.description = Some("Serialization & deserialization ".repeat(10));
+
+    let text = summary_within(&pkg, 100);
+    assert!(text.len() <= 100);
+    assert!(text.starts_with("<b>serde</b>\n\nSerialization &amp; deserialization"));
+    assert!(text.ends_with("…\n"));
+    assert_eq!(summary_within(&pkg, 1000), HtmlMessageBuilder::summary(&pkg).build());
+
+    pkg.name = "<&>".repeat(100);
+    let text = summary_within(&pkg, 100);
+    assert!(text.len() <= 100);
+    assert!(text.starts_with("<b>&lt;&amp;&gt;"));
+
+    assert_eq!(truncate("naïve", 6), "naïve");
+    assert_eq!(truncate("naïve", 5), "na…");
+    assert_eq!(truncate("naïve", 4), "n…");
+    assert_eq!(truncate("naïve", 2), "");
+}
+
+#[test]
+fn test_releases_message() {
+    let mut yanked = Release::new("0.2.0");
+    yanked.yanked = true;
+    let mut latest = Release::new("0.3.0");
+    latest.attributes.insert("rust version".to_owned(), "1.13".to_owned());
+    let releases = vec![Release::new("0.1.0"), yanked, latest];
+
+    assert_eq!(releases_message("serde", &releases, 2, 4096),
+               "<b>serde</b> has 3 versions\n\
+                \n<b>0.3.0</b>\n  rust version: 1.13\
+                \n<b>0.2.0</b> <i>yanked</i>\n");
+
+    let features = (0..500).map(|i| format!("feature{}", i)).collect::<Vec<String>>();
+    let releases = (0..10)
+        .map(|i| {
+            let mut release = Release::new(&format!("1.{}.0", i));
+            release.attributes.insert("features".to_owned(), features.join(", "));
+            release
+        })
+        .collect::<Vec<Release>>();
+
+    let msg = releases_message("windows", &releases, 10, 1000);
+    assert!(msg.len() <= 1000);
+    assert!(msg.contains("<b>1.9.0</b>\n  features: feature0, feature1"));
+    assert!(msg.contains("…"));
+    assert!(!msg.contains("<b>1.0.0</b>"));
+}
+
+#[test]
+fn test_new_release_message() {
+    let mut pkg = Package::empty();
+    pkg.name = "tokio".to_owned();
+    pkg.version = Some("0.2.0".to_owned());
+    pkg.documentation = Some("https://docs.rs/tokio".to_owned());
+
+    assert_eq!(new_release_message(&pkg, "0.1.0"),
+               "<b>tokio</b> <i>0.1.0</i> → <i>0.2.0</i>\n\
+                <a href=\"https://docs.rs/tokio\">[doc]</a>\n");
+}
+
+#[test]
+fn test_audit_page() {
+    let entry = AuditEntry {
+        name: "smallvec".to_owned(),
+        version: "0.6.9".to_owned(),
+        findings: vec![Finding::Outdated("0.6.10".to_owned()),
+                       Finding::Vulnerable {
+                           id: "RUSTSEC-2019-0009".to_owned(),
+                           title: "Double-free in SmallVec::grow()".to_owned(),
+                           url: Some("https://rustsec.org/a/0009".to_owned()),
+                       }],
+    };
+    let report = AuditReport {
+        packages: 3,
+        skipped: 0,
+        entries: vec![entry],
+        advisories_checked: true,
+    };
+
+    assert_eq!(audit_page(&report, &report.entries),
+               "<b>3 packages: 1 outdated, 0 yanked, 1 vulnerable</b>\n\
+                \n<b>smallvec</b> 0.6.9\
+                \n  latest is <i>0.6.10</i>\
+                \n  <a href=\"https://rustsec.org/a/0009\">RUSTSEC-2019-0009</a> \
+                Double-free in SmallVec::grow()\n");
+}
+
+#[test]
+fn test_go_mod_page() {
+    let entry = GoModEntry {
+        path: "github.com/go-redis/redis".to_owned(),
+        version: "v6.15.9+incompatible".to_owned(),
+        findings: vec![GoFinding::Retracted(Some("Broken".to_owned())),
+                       GoFinding::MajorVersion("github.com/go-redis/redis/v8".to_owned(),
+                                               "v8.11.5".to_owned())],
+    };
+    let report = GoModReport {
+        module: None,
+        requirements: 4,
+        entries: vec![entry],
+    };
+
+    assert_eq!(go_mod_page(&report, &report.entries),
+               "<b>4 modules: 0 upgradable, 1 retracted, 1 with a new major version</b>\n\
+                \n<b>github.com/go-redis/redis</b> v6.15.9+incompatible\
+                \n  <i>retracted</i>: Broken\
+                \n  new major version <b>github.com/go-redis/redis/v8</b> <i>v8.11.5</i>\n");
+}
@@ -1,3 +1,10 @@
+pub mod analytics;
 pub mod bot;
+pub mod botapi;
 pub mod botanio;
+pub mod files;
+pub mod history;
 pub mod html;
+pub mod paging;
+pub mod watch;
+pub mod webhook;
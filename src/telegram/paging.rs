@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use telegram_bot::types::Integer;
+use telegram::botapi::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+pub const PAGE_SIZE: usize = 10;
+const MAX_SESSIONS: usize = 1000;
+
+/// Everything needed to re-render a paginated search result message
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchSession {
+    pub ecosystem: String,
+    pub query: String,
+    pub page: usize,
+}
+
+struct Sessions<T> {
+    map: HashMap<(Integer, Integer), T>,
+    order: VecDeque<(Integer, Integer)>,
+}
+
+/// Sessions of the sent paginated messages keyed by chat and message id.
+///
+/// Only the latest `MAX_SESSIONS` messages are kept, buttons of older
+/// messages report that the search has expired.
+pub struct SessionStore<T = SearchSession> {
+    inner: Arc<Mutex<Sessions<T>>>,
+}
+
+impl<T> Clone for SessionStore<T> {
+    fn clone(&self) -> SessionStore<T> {
+        SessionStore { inner: self.inner.clone() }
+    }
+}
+
+impl<T: Clone> SessionStore<T> {
+    pub fn new() -> SessionStore<T> {
+        SessionStore {
+            inner: Arc::new(Mutex::new(Sessions {
+                map: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn insert(&self, chat_id: Integer, message_id: Integer, session: T) {
+        let mut sessions = self.inner.lock().unwrap();
+        let key = (chat_id, message_id);
+
+        if sessions.map.insert(key, session).is_none() {
+            sessions.order.push_back(key);
+        }
+
+        while sessions.order.len() > MAX_SESSIONS {
+            if let Some(old) = sessions.order.pop_front() {
+                sessions.map.remove(&old);
+            }
+        }
+    }
+
+    pub fn get(&self, chat_id: Integer, message_id: Integer) -> Option<T> {
+        let sessions = self.inner.lock().unwrap();
+        sessions.map.get(&(chat_id, message_id)).cloned()
+    }
+}
+
+impl SessionStore<SearchSession> {
+    pub fn set_page(&self, chat_id: Integer, message_id: Integer, page: usize) {
+        let mut sessions = self.inner.lock().unwrap();
+        if let Some(session) = sessions.map.get_mut(&(chat_id, message_id)) {
+            session.page = page;
+        }
+    }
+}
+
+pub fn pages_count(items: usize) -> usize {
+    (items + PAGE_SIZE - 1) / PAGE_SIZE
+}
+
+pub fn page_bounds(items: usize, page: usize) -> (usize, usize) {
+    let start = page * PAGE_SIZE;
+    if start >= items {
+        return (items, items);
+    }
+
+    let end = start + PAGE_SIZE;
+    (start, if end > items { items } else { end })
+}
+
+/// Groups rendered items into pages of at most `PAGE_SIZE` items and
+/// `max_length` bytes, returns the item range of every page. Items that
+/// don't fit move to the next page, a single longer item gets its own.
+pub fn split_pages(lengths: &[usize], max_length: usize) -> Vec<(usize, usize)> {
+    let mut pages = Vec::new();
+    let mut start = 0;
+    let mut length = 0;
+
+    for (i, &len) in lengths.iter().enumerate() {
+        if i > start && (i - start == PAGE_SIZE || length + len > max_length) {
+            pages.push((start, i));
+            start = i;
+            length = 0;
+        }
+        length += len;
+    }
+
+    if start < lengths.len() {
+        pages.push((start, lengths.len()));
+    }
+
+    pages
+}
+
+/// Builds "Prev / Next" buttons for the page, callback data is
+/// `<prefix>:<target page>`
+pub fn keyboard(prefix: &str, page: usize, pages: usize) -> Option<InlineKeyboardMarkup> {
+    let mut row = Vec::new();
+
+    if page > 0 {
+        row.push(button("« Prev", &format!("{}:{}", prefix, page - 1)));
+    }
+
+    if page + 1 < pages {
+        row.push(button("Next »", &format!("{}:{}", prefix, page + 1)));
+    }
+
+    if row.is_empty() {
+        None
+    } else {
+        Some(InlineKeyboardMarkup { inline_keyboard: vec![row] })
+    }
+}
+
+pub fn button(text: &str, callback_data: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton {
+        text: text.to_owned(),
+        callback_data: callback_data.to_owned(),
+    }
+}
+
+#[test]
+fn test_page_bounds() {
+    assert_eq!(pages_count(0), 0);
+    assert_eq!(pages_count(10), 1);
+    assert_eq!(pages_count(11), 2);
+
+    assert_eq!(page_bounds(25, 0), (0, 10));
+    assert_eq!(page_bounds(25, 2), (20, 25));
+    assert_eq!(page_bounds(25, 3), (25, 25));
+}
+
+#[test]
+fn test_split_pages() {
+    assert!(split_pages(&[], 100).is_empty());
+    assert_eq!(split_pages(&[10; 25], 1000), vec![(0, 10), (10, 20), (20, 25)]);
+    assert_eq!(split_pages(&[40, 40, 40, 10], 100), vec![(0, 2), (2, 4)]);
+    assert_eq!(split_pages(&[150, 10], 100), vec![(0, 1), (1, 2)]);
+}
+
+#[test]
+fn test_session_store_evicts_oldest() {
+    let store = SessionStore::new();
+    for i in 0..(MAX_SESSIONS as Integer + 1) {
+        store.insert(1,
+                     i,
+                     SearchSession {
+                         ecosystem: "rust".to_owned(),
+                         query: "serde".to_owned(),
+                         page: 0,
+                     });
+    }
+
+    assert!(store.get(1, 0).is_none());
+    assert!(store.get(1, MAX_SESSIONS as Integer).is_some());
+
+    store.set_page(1, 1, 3);
+    assert_eq!(store.get(1, 1).unwrap().page, 3);
+}
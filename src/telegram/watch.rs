@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use Package;
+use state::{Scope, State};
+use error::Error;
+
+const WATCHLIST_KEY: &'static str = "watchlist";
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct WatchedPackage {
+    pub ecosystem: String,
+    pub name: String,
+    /// The latest version seen, `None` until the first successful lookup
+    pub version: Option<String>,
+}
+
+/// A watched package got a new version
+pub struct NewRelease {
+    pub scope: Scope,
+    pub ecosystem: String,
+    pub package: Package,
+    pub previous: String,
+}
+
+/// Packages watched by every user, notifications go to the chat the
+/// package was watched in
+#[derive(Clone)]
+pub struct Watchlist {
+    state: State,
+    // Serializes the read-modify-write cycles of the stored lists
+    lock: Arc<Mutex<()>>,
+}
+
+impl Watchlist {
+    pub fn new(state: State) -> Watchlist {
+        Watchlist {
+            state: state,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn packages(&self, scope: Scope) -> Result<Vec<WatchedPackage>, Error> {
+        let packages: Option<Vec<WatchedPackage>> = try!(self.state.load(scope, WATCHLIST_KEY));
+        Ok(packages.unwrap_or(Vec::new()))
+    }
+
+    /// Returns `false` if the package is watched already
+    pub fn watch(&self,
+                 scope: Scope,
+                 ecosystem: &str,
+                 name: &str,
+                 version: Option<String>)
+                 -> Result<bool, Error> {
+        let _guard = self.lock.lock().unwrap();
+        let mut packages = try!(self.packages(scope));
+        if packages.iter().any(|p| p.ecosystem == ecosystem && p.name == name) {
+            return Ok(false);
+        }
+
+        packages.push(WatchedPackage {
+            ecosystem: ecosystem.to_owned(),
+            name: name.to_owned(),
+            version: version,
+        });
+        try!(self.state.save(scope, WATCHLIST_KEY, &packages));
+
+        Ok(true)
+    }
+
+    /// Returns `false` if the package wasn't watched
+    pub fn unwatch(&self, scope: Scope, ecosystem: &str, name: &str) -> Result<bool, Error> {
+        let _guard = self.lock.lock().unwrap();
+        let mut packages = try!(self.packages(scope));
+        let count = packages.len();
+        packages.retain(|p| p.ecosystem != ecosystem || p.name != name);
+
+        if packages.len() == count {
+            return Ok(false);
+        }
+
+        try!(self.state.save(scope, WATCHLIST_KEY, &packages));
+        Ok(true)
+    }
+
+    /// Looks up every watched package once and stores the versions that
+    /// changed. `lookup` gets the ecosystem and the package name and returns
+    /// `None` if the package can't be checked at the moment.
+    ///
+    /// The lookups run unlocked, the versions are then merged into the lists
+    /// as they are stored by then, so that concurrent `watch` and `unwatch`
+    /// calls aren't undone.
+    pub fn poll<F>(&self, lookup: F) -> Result<Vec<NewRelease>, Error>
+        where F: Fn(&str, &str) -> Option<Package>
+    {
+        let watchlists = try!(self.state.scan::<Vec<WatchedPackage>>(WATCHLIST_KEY));
+
+        let mut latest = BTreeMap::new();
+        for &(_, ref packages) in &watchlists {
+            for p in packages {
+                let key = (p.ecosystem.clone(), p.name.clone());
+                if !latest.contains_key(&key) {
+                    let pkg = lookup(&p.ecosystem, &p.name);
+                    latest.insert(key, pkg);
+                }
+            }
+        }
+
+        let _guard = self.lock.lock().unwrap();
+        let mut releases = Vec::new();
+
+        for (scope, _) in watchlists {
+            let mut packages = try!(self.packages(scope));
+            let mut changed = false;
+
+            for p in &mut packages {
+                let pkg = match latest.get(&(p.ecosystem.clone(), p.name.clone())) {
+                    Some(&Some(ref pkg)) if pkg.version.is_some() && pkg.version != p.version => {
+                        pkg
+                    }
+                    _ => continue,
+                };
+
+                if let Some(ref previous) = p.version {
+                    releases.push(NewRelease {
+                        scope: scope,
+                        ecosystem: p.ecosystem.clone(),
+                        package: pkg.clone(),
+                        previous: previous.clone(),
+                    });
+                }
+
+                p.version = pkg.version.clone();
+                changed = true;
+            }
+
+            if changed {
+                try!(self.state.save(scope, WATCHLIST_KEY, &packages));
+            }
+        }
+
+        Ok(releases)
+    }
+}
+
+#[test]
+fn test_watchlist() {
+    let watchlist = Watchlist::new(::state::StateConfig::Memory.open().unwrap());
+    let alice = Scope::new(1, 10);
+    let bob = Scope::new(2, 20);
+
+    assert!(watchlist.watch(alice, "rust", "tokio", Some("0.1.0".to_owned())).unwrap());
+    assert!(!watchlist.watch(alice, "rust", "tokio", None).unwrap());
+    assert!(watchlist.watch(alice, "go", "github.com/gorilla/mux", None).unwrap());
+    assert!(watchlist.watch(bob, "rust", "tokio", Some("0.1.0".to_owned())).unwrap());
+
+    let lookup = |ecosystem: &str, name: &str| {
+        let mut pkg = Package::empty();
+        pkg.name = name.to_owned();
+        pkg.version = Some(if ecosystem == "go" { "v1.8.0" } else { "0.2.0" }.to_owned());
+        Some(pkg)
+    };
+
+    let releases = watchlist.poll(&lookup).unwrap();
+    let notified = releases.iter()
+        .map(|r| (r.scope, r.package.name.as_ref(), r.previous.as_ref()))
+        .collect::<Vec<(Scope, &str, &str)>>();
+    // The first version seen for mux isn't a release
+    assert_eq!(notified, vec![(alice, "tokio", "0.1.0"), (bob, "tokio", "0.1.0")]);
+
+    assert!(watchlist.poll(&lookup).unwrap().is_empty());
+    assert_eq!(watchlist.packages(alice).unwrap()[1].version, Some("v1.8.0".to_owned()));
+
+    assert!(watchlist.unwatch(alice, "rust", "tokio").unwrap());
+    assert!(!watchlist.unwatch(alice, "rust", "tokio").unwrap());
+    assert_eq!(watchlist.packages(alice).unwrap().len(), 1);
+}
+
+#[test]
+fn test_watchlist_poll_keeps_concurrent_changes() {
+    let watchlist = Watchlist::new(::state::StateConfig::Memory.open().unwrap());
+    let alice = Scope::new(1, 10);
+
+    watchlist.watch(alice, "rust", "tokio", Some("0.1.0".to_owned())).unwrap();
+    watchlist.watch(alice, "rust", "serde", Some("0.8.0".to_owned())).unwrap();
+
+    // The user changes the list while the lookups are running
+    let lookup = |_: &str, name: &str| {
+        if name == "tokio" {
+            watchlist.unwatch(alice, "rust", "serde").unwrap();
+            watchlist.watch(alice, "npm", "express", None).unwrap();
+        }
+
+        let mut pkg = Package::empty();
+        pkg.name = name.to_owned();
+        pkg.version = Some("1.0.0".to_owned());
+        Some(pkg)
+    };
+
+    let releases = watchlist.poll(&lookup).unwrap();
+    let names = releases.iter().map(|r| r.package.name.as_ref()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["tokio"]);
+
+    let packages = watchlist.packages(alice).unwrap();
+    let names = packages.iter().map(|p| p.name.as_ref()).collect::<Vec<&str>>();
+    assert_eq!(names, vec!["tokio", "express"]);
+    assert_eq!(packages[0].version, Some("1.0.0".to_owned()));
+}
@@ -0,0 +1,226 @@
+//! Webhook mode: Telegram pushes updates to an embedded HTTP(S) server
+//! instead of the bot polling for them.
+
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use chan_signal::{self, Signal};
+use hyper::method::Method;
+use hyper::net::Openssl;
+use hyper::server::{Handler, Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use rustc_serialize::json;
+use telegram::botapi::{BotApi, Update};
+use error::Error;
+
+const SECRET_TOKEN_HEADER: &'static str = "X-Telegram-Bot-Api-Secret-Token";
+const MAX_UPDATE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Address the embedded server listens on, e.g. `0.0.0.0:8443`
+    pub listen: String,
+    /// Public url registered with `setWebhook`
+    pub url: String,
+    /// Path of the requests carrying updates
+    pub path: String,
+    /// Telegram sends it back in the `X-Telegram-Bot-Api-Secret-Token` header
+    pub secret_token: String,
+    /// Certificate and private key files, plain HTTP is used without them
+    pub tls: Option<(PathBuf, PathBuf)>,
+}
+
+pub enum WebhookEvent {
+    Update(Update),
+    Shutdown,
+}
+
+/// Blocks SIGINT and SIGTERM and delivers them through the returned
+/// receiver. Has to be called before any other thread is spawned, threads
+/// started earlier keep the default handlers and would kill the process.
+pub fn watch_signals() -> Receiver<Signal> {
+    let signals = chan_signal::notify(&[Signal::INT, Signal::TERM]);
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        while let Some(signal) = signals.recv() {
+            if tx.send(signal).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+// Compares every byte so that the time taken doesn't leak the secret
+fn secret_matches(expected: &str, actual: &[u8]) -> bool {
+    if expected.len() != actual.len() {
+        return false;
+    }
+
+    expected.bytes().zip(actual.iter()).fold(0, |acc, (a, &b)| acc | (a ^ b)) == 0
+}
+
+struct UpdatesHandler {
+    path: String,
+    secret_token: String,
+    updates: Mutex<Sender<WebhookEvent>>,
+}
+
+impl UpdatesHandler {
+    fn accept(&self, mut req: Request) -> StatusCode {
+        match req.uri {
+            RequestUri::AbsolutePath(ref path) if *path == self.path => {}
+            _ => return StatusCode::NotFound,
+        }
+
+        if req.method != Method::Post {
+            return StatusCode::MethodNotAllowed;
+        }
+
+        let authorized = req.headers
+            .get_raw(SECRET_TOKEN_HEADER)
+            .and_then(|values| values.first())
+            .map_or(false, |value| secret_matches(&self.secret_token, value));
+        if !authorized {
+            return StatusCode::Unauthorized;
+        }
+
+        let mut body = String::new();
+        if let Err(err) = req.by_ref().take(MAX_UPDATE_SIZE).read_to_string(&mut body) {
+            error!("failed to read webhook request: {}", err);
+            return StatusCode::BadRequest;
+        }
+
+        match json::decode::<Update>(&body) {
+            Ok(update) => {
+                let _ = self.updates.lock().unwrap().send(WebhookEvent::Update(update));
+                StatusCode::Ok
+            }
+            Err(err) => {
+                error!("invalid update: {}", err);
+                StatusCode::BadRequest
+            }
+        }
+    }
+}
+
+impl Handler for UpdatesHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        *res.status_mut() = self.accept(req);
+    }
+}
+
+/// Embedded server receiving updates, the webhook stays registered until `stop`
+pub struct WebhookServer {
+    api: BotApi,
+    listening: Listening,
+}
+
+impl WebhookServer {
+    /// Starts the server and registers the webhook with Telegram. Updates
+    /// and the first of `signals` are delivered through the returned receiver.
+    pub fn start(api: BotApi,
+                 config: &WebhookConfig,
+                 signals: Receiver<Signal>)
+                 -> Result<(WebhookServer, Receiver<WebhookEvent>), Error> {
+        let (tx, rx) = mpsc::channel();
+
+        let shutdown = tx.clone();
+        thread::spawn(move || {
+            if let Ok(signal) = signals.recv() {
+                info!("received {:?}, shutting down", signal);
+                let _ = shutdown.send(WebhookEvent::Shutdown);
+            }
+        });
+
+        let handler = UpdatesHandler {
+            path: config.path.clone(),
+            secret_token: config.secret_token.clone(),
+            updates: Mutex::new(tx),
+        };
+
+        let listening = match config.tls {
+            Some((ref cert, ref key)) => {
+                let ssl = match Openssl::with_cert_and_key(cert, key) {
+                    Ok(ssl) => ssl,
+                    Err(e) => return Err(Error::Config(format!("invalid certificate: {}", e))),
+                };
+                try!(Server::https(config.listen.as_str(), ssl).and_then(|s| s.handle(handler)))
+            }
+            None => try!(Server::http(config.listen.as_str()).and_then(|s| s.handle(handler))),
+        };
+        info!("webhook server is listening on {}", config.listen);
+
+        let mut server = WebhookServer {
+            api: api,
+            listening: listening,
+        };
+
+        // Dropping `Listening` waits for the server thread, which never ends
+        if let Err(err) = server.call_api("setWebhook",
+                                          &[("url", &config.url),
+                                            ("secret_token", &config.secret_token)]) {
+            let _ = server.listening.close();
+            return Err(err);
+        }
+
+        Ok((server, rx))
+    }
+
+    /// Removes the webhook and stops accepting requests
+    pub fn stop(mut self) -> Result<(), Error> {
+        let removed = self.call_api("deleteWebhook", &[]);
+        try!(self.listening.close());
+
+        removed
+    }
+
+    // The webhook methods are called directly because the secret token
+    // isn't supported by the telegram_bot crate
+    fn call_api(&self, method: &str, params: &[(&str, &str)]) -> Result<(), Error> {
+        let _: bool = try!(self.api.call(method, params));
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_failed_registration_stops_server() {
+    use hyper::status::StatusCode;
+    use http::WithHttp;
+    use replay::ReplayTransport;
+
+    let (_, http) = ReplayTransport::new()
+        .respond(Method::Post,
+                 "https://api.telegram.org/botTOKEN/setWebhook",
+                 StatusCode::Unauthorized,
+                 r#"{"ok":false,"error_code":401,"description":"Unauthorized"}"#)
+        .into_client();
+    let config = WebhookConfig {
+        listen: "127.0.0.1:0".to_owned(),
+        url: "https://bot.example.com/updates".to_owned(),
+        path: "/updates".to_owned(),
+        secret_token: "s3cr3t".to_owned(),
+        tls: None,
+    };
+    let (_, signals) = mpsc::channel();
+
+    match WebhookServer::start(BotApi::new("TOKEN").with_http(http), &config, signals) {
+        Err(Error::TelegramApi(_, code, _)) => assert_eq!(code, Some(401)),
+        Err(other) => panic!("unexpected error {:?}", other),
+        Ok(_) => panic!("the webhook isn't registered"),
+    }
+}
+
+#[test]
+fn test_secret_matches() {
+    assert!(secret_matches("s3cr3t", b"s3cr3t"));
+    assert!(!secret_matches("s3cr3t", b"s3cr3T"));
+    assert!(!secret_matches("s3cr3t", b"s3cr3t0"));
+    assert!(!secret_matches("s3cr3t", b""));
+}